@@ -0,0 +1,205 @@
+use std::{fs::File, io::{BufWriter, Write, Seek, SeekFrom}, path::Path};
+
+use crate::error::RenderError;
+use crate::output::to_signed_int;
+use crate::riff::write_chunk;
+
+/// Step sizes of IMA-ADPCM for each of the 89 step indices, the same table the NDS decodes with
+pub const ADPCM_STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73, 80, 88, 97, 107, 118, 130, 143,
+    157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552,
+    1707, 1878, 2066, 2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487,
+    12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// Change of the step index after each nibble, by the magnitude bits of the nibble
+pub const ADPCM_INDEX_TABLE: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+/// Samples per channel in a block of an IMA-ADPCM wave-file, including the one stored in the block header
+const SAMPLES_PER_BLOCK: usize = 1017;
+
+/// Encodes 16-bit samples into 4-bit IMA-ADPCM nibbles, one channel at a time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AdpcmEncoder {
+    predictor: i32,
+    index: i32,
+}
+
+impl AdpcmEncoder {
+    /// Starts predicting from `predictor` with the step size at `index`
+    pub fn new(predictor: i16, index: u8) -> Self {
+        AdpcmEncoder { predictor: predictor as i32, index: (index as i32).min(88) }
+    }
+
+    /// The predicted sample and step index, as stored in a block header
+    pub fn state(&self) -> (i16, u8) {
+        (self.predictor as i16, self.index as u8)
+    }
+
+    /// Encodes `sample` into a nibble, updating the prediction exactly like a decoder does
+    pub fn encode(&mut self, sample: i16) -> u8 {
+        let step = ADPCM_STEP_TABLE[self.index as usize];
+        let mut difference = sample as i32 - self.predictor;
+        let mut nibble = 0;
+        if difference < 0 {
+            nibble = 8;
+            difference = -difference;
+        }
+        let mut delta = step >> 3;
+        for (bit, step) in [(4, step), (2, step >> 1), (1, step >> 2)] {
+            if difference >= step {
+                nibble |= bit;
+                difference -= step;
+                delta += step;
+            }
+        }
+        // The NDS clamps to a symmetric range, which is a subset of what other decoders allow
+        self.predictor = (self.predictor + if nibble & 8 != 0 { -delta } else { delta }).clamp(-0x7FFF, 0x7FFF);
+        self.index = (self.index + ADPCM_INDEX_TABLE[(nibble & 7) as usize]).clamp(0, 88);
+        nibble
+    }
+}
+
+/// Encodes `samples` as the ADPCM stream of an NDS sound channel: a 4-byte header with the initial sample and step index,
+/// then a nibble per sample, low nibble first
+pub fn encode_nds_adpcm(samples: &[i16]) -> Vec<u8> {
+    let first = samples.first().copied().unwrap_or(0);
+    let mut encoder = AdpcmEncoder::new(first, 0);
+    let mut bytes = Vec::with_capacity(4 + samples.len().div_ceil(2));
+    bytes.extend_from_slice(&first.to_le_bytes());
+    bytes.extend_from_slice(&[0, 0]);
+    for pair in samples.chunks(2) {
+        let low = encoder.encode(pair[0]);
+        let high = pair.get(1).map_or(0, |&sample| encoder.encode(sample));
+        bytes.push(low | high << 4);
+    }
+    bytes
+}
+
+/// A mono or stereo file of 4-bit IMA-ADPCM, either a wave-file with format tag `0x0011` or headerless NDS streams
+/// 
+/// A wave-file is encoded block by block as the samples arrive, and its header is completed by [`AdpcmFileWriter::finalize`].
+/// The headerless layout is one stream per channel as [`encode_nds_adpcm`] writes it, the right channel following the left,
+/// so its samples are collected until then.
+pub struct AdpcmFileWriter {
+    file: BufWriter<File>,
+    sample_rate: u32,
+    raw: bool,
+    /// Samples of each channel that are not encoded yet, which for a wave-file is less than a block
+    channels: Vec<Vec<i16>>,
+    encoders: Vec<AdpcmEncoder>,
+    frames: u64,
+    blocks: u64,
+}
+
+impl AdpcmFileWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16, raw: bool) -> Result<Self, RenderError> {
+        let channels = channels.clamp(1, 2) as usize;
+        let mut writer = AdpcmFileWriter {
+            file: BufWriter::new(File::create(path)?),
+            sample_rate,
+            raw,
+            channels: vec![Vec::new(); channels],
+            encoders: vec![AdpcmEncoder::default(); channels],
+            frames: 0,
+            blocks: 0,
+        };
+        if !raw {
+            // Filled in once the length is known
+            let header = writer.wave_header();
+            writer.file.write_all(&header)?;
+        }
+        Ok(writer)
+    }
+
+    /// Appends a block of the `left` and `right` channels; a mono file only takes `left`
+    pub fn write(&mut self, left: &[f32], right: &[f32]) -> Result<(), RenderError> {
+        for (samples, block) in self.channels.iter_mut().zip([left, right]) {
+            samples.extend(block.iter().map(|&x| to_signed_int(x, 16) as i16));
+        }
+        self.frames += left.len() as u64;
+        while !self.raw && self.channels[0].len() >= SAMPLES_PER_BLOCK {
+            self.encode_block()?;
+        }
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> Result<(), RenderError> {
+        if self.raw {
+            for samples in &self.channels {
+                self.file.write_all(&encode_nds_adpcm(samples))?;
+            }
+        } else {
+            if !self.channels[0].is_empty() {
+                self.encode_block()?;
+            }
+            let header = self.wave_header();
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(&header)?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Bytes of a block of every channel
+    fn block_align(&self) -> u32 {
+        let channels = self.channels.len() as u32;
+        4 * channels + (SAMPLES_PER_BLOCK as u32 - 1) / 2 * channels
+    }
+
+    /// Everything of the wave-file up to its samples, with the sizes of the blocks encoded so far
+    fn wave_header(&self) -> Vec<u8> {
+        let channels = self.channels.len() as u16;
+        let block_align = self.block_align();
+        let mut fmt = Vec::with_capacity(20);
+        fmt.extend_from_slice(&0x0011_u16.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&self.sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&((self.sample_rate as u64 * block_align as u64 / SAMPLES_PER_BLOCK as u64) as u32).to_le_bytes());
+        fmt.extend_from_slice(&(block_align as u16).to_le_bytes());
+        fmt.extend_from_slice(&4_u16.to_le_bytes());
+        fmt.extend_from_slice(&2_u16.to_le_bytes());
+        fmt.extend_from_slice(&(SAMPLES_PER_BLOCK as u16).to_le_bytes());
+
+        // Blocks have an even length, so the data chunk needs no padding
+        let data_length = (self.blocks * block_align as u64) as u32;
+        let mut body = b"WAVE".to_vec();
+        write_chunk(&mut body, b"fmt ", &fmt);
+        write_chunk(&mut body, b"fact", &(self.frames as u32).to_le_bytes());
+        body.extend_from_slice(b"data");
+        body.extend_from_slice(&data_length.to_le_bytes());
+        let mut header = b"RIFF".to_vec();
+        header.extend_from_slice(&(body.len() as u32 + data_length).to_le_bytes());
+        header.extend_from_slice(&body);
+        header
+    }
+
+    /// Encodes the next block of the data chunk, starting with the header of every channel, followed by groups of 8 samples per channel in turn
+    fn encode_block(&mut self) -> Result<(), RenderError> {
+        let mut data = Vec::with_capacity(self.block_align() as usize);
+        let (channels, encoders) = (&self.channels, &mut self.encoders);
+        // The last block is padded with silence, which the fact chunk leaves out
+        let sample = |channel: usize, i: usize| channels[channel].get(i).copied().unwrap_or(0);
+        for (channel, encoder) in encoders.iter_mut().enumerate() {
+            // Every block restarts the prediction at its first sample, keeping the step index of the block before
+            *encoder = AdpcmEncoder::new(sample(channel, 0), encoder.state().1);
+            data.extend_from_slice(&sample(channel, 0).to_le_bytes());
+            data.extend_from_slice(&[encoder.state().1, 0]);
+        }
+        for group in (1..SAMPLES_PER_BLOCK).step_by(8) {
+            for (channel, encoder) in encoders.iter_mut().enumerate() {
+                for pair in (group..group + 8).step_by(2) {
+                    let low = encoder.encode(sample(channel, pair));
+                    let high = encoder.encode(sample(channel, pair + 1));
+                    data.push(low | high << 4);
+                }
+            }
+        }
+        for samples in &mut self.channels {
+            samples.drain(..samples.len().min(SAMPLES_PER_BLOCK));
+        }
+        self.file.write_all(&data)?;
+        self.blocks += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,636 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use crate::dither::DEFAULT_SEED;
+use crate::error::RenderError;
+use crate::output::{SampleFormat, Codec, Endianness};
+use crate::fade::FadeCurve;
+use crate::image::FftWindow;
+use crate::normalize::NormalizeMode;
+use crate::resample::ResampleMode;
+use crate::dsp::{EqBand, MAX_STEREO_WIDTH};
+use crate::midi::DRUM_CHANNEL;
+use crate::silence::SilenceStop;
+use crate::transform::{BankMode, VelocityCurve, VoiceStealing};
+
+/// Default length of [`RenderConfig::tail`] in seconds, enough for typical release envelopes
+pub const DEFAULT_TAIL: f64 = 0.5;
+/// Length of [`RenderConfig::tail`] in seconds that lets the reverb of [`RenderConfig::reverb_and_chorus`] ring out
+pub const DEFAULT_REVERB_TAIL: f64 = 3.0;
+
+/// Options controlling how a MIDI file is rendered
+/// 
+/// The defaults match the command-line tool: 10-bit reduction at 32729 Hz with no repeats.
+#[derive(Clone, Debug)]
+pub struct RenderConfig {
+    /// Target bit-depth for bit reduction from 1 to 32 (0 disables it, 1 only keeps the sign), the internal resolution of the NDS
+    pub bitdepth: u8,
+    /// Bit depth to quantize the final output to once more, after resampling, or `None` to leave that to the sample format
+    /// 
+    /// Bit reduction to `bitdepth` happens at the NDS rate; this second stage models storing the result at the wider depth of the output,
+    /// e.g. 16 bits, so that even a resampled or floating-point file only carries levels of that depth.
+    pub output_bitdepth: Option<u8>,
+    /// Hold every sample of the output for this many frames, or `None` to leave it be
+    /// 
+    /// This sample-and-hold comes after resampling, so it steps the audio at the output rate without changing the rate of the file.
+    pub crush_rate: Option<u32>,
+    /// Internal sample rate the synthesizer runs at, which is where the zero-interpolation aliasing of the NDS happens
+    pub nds_rate: u32,
+    /// Run the synthesizer at this multiple of `nds_rate` and decimate back to it with a low-pass, 1 for no oversampling
+    /// 
+    /// This keeps the aliasing of the synthesizer's own envelopes and oscillators out of the render, leaving just the artifacts of the stages after it.
+    /// The synthesizer does `oversample` times the work, so rendering takes about that much longer.
+    pub oversample: u32,
+    /// Sample rate of the written output, or `None` to write it out at `nds_rate`
+    /// 
+    /// When it differs from `nds_rate` the rendered audio is resampled to it after bit reduction.
+    pub output_rate: Option<u32>,
+    /// Interpolation used when resampling to `output_rate`
+    pub resample: ResampleMode,
+    /// Low-pass cutoff, as a fraction of the output Nyquist frequency, applied before downsampling to `output_rate`
+    /// 
+    /// `None` leaves the folded-back aliasing in, which is part of the NDS character; it has no effect when not downsampling.
+    pub antialias: Option<f32>,
+    /// How many times to play the MIDI file in a row, where a fractional count plays the last repetition partly
+    /// 
+    /// A file with a loop, see `loop_region`, plays what comes before the loop once and repeats only the loop.
+    pub repeat: f64,
+    /// Seconds to crossfade over between repetitions, or 0.0 to play them in a row as a single file
+    /// 
+    /// Every repetition is then rendered on its own, and the release tail of one fades out while the start of the next fades in.
+    pub loop_crossfade: f64,
+    /// Start and end in seconds of the loop of the MIDI file, replacing the loop points it marks itself, see [`crate::Smf::loop_points`]
+    /// 
+    /// The loop, wherever it comes from, is what `repeat` and `loop_crossfade` repeat and what the `smpl` chunk of a wave-file loops over.
+    /// The times are those of the file as it is, before changes such as `tempo_scale` or `start`.
+    pub loop_region: Option<(f64, f64)>,
+    /// Seconds rendered past the end of the MIDI file so that releasing notes can decay instead of being cut off
+    /// 
+    /// When repeating, the tail follows the last repetition.
+    pub tail: f64,
+    /// Render until the output has gone silent instead of for the length of the file plus `tail`
+    /// 
+    /// Avoids both cut-off release tails and long trailing silence; `repeat` and `tail` are ignored in favour of the maximum duration.
+    pub stop_on_silence: Option<SilenceStop>,
+    /// Threshold in dBFS below which leading and trailing frames are trimmed off the bit-reduced render, or `None` to keep them
+    pub trim: Option<f32>,
+    /// Level normalization applied to the render before bit reduction
+    pub normalize: NormalizeMode,
+    /// Level that [`NormalizeMode::Peak`] scales the largest sample to, in dBFS
+    pub target_peak_dbfs: f32,
+    /// Integrated loudness that [`NormalizeMode::Lufs`] brings the render to, in LUFS
+    pub target_lufs: f32,
+    /// Master gain in dB applied before bit reduction, on top of any normalization
+    /// 
+    /// Samples boosted past full scale are handled by `overflow` like any other.
+    pub gain_db: f32,
+    /// Length of the fade-in at the start of the render in seconds
+    pub fade_in: f64,
+    /// Length of the fade-out at the end of the render in seconds, measured back from its very last frame
+    pub fade_out: f64,
+    /// Shape of both fades
+    pub fade_curve: FadeCurve,
+    /// Run the render through the frequency response of the NDS output path before bit reduction, see [`crate::nds_dac_filter`]
+    pub dac_filter: bool,
+    /// Also roll off the bass like the output path does when `dac_filter` is set
+    pub dac_highpass: bool,
+    /// Cutoff in Hz of a Butterworth low-pass run on the render before bit reduction, or `None` for no low-pass
+    pub lowpass: Option<f64>,
+    /// Cutoff in Hz of a Butterworth high-pass run on the render before bit reduction, or `None` for no high-pass
+    pub highpass: Option<f64>,
+    /// Bands of a parametric EQ run on the render after the filters above, as a [`crate::BiquadChain`]
+    /// 
+    /// Like the filters it runs at the NDS rate before bit reduction, so whatever it boosts gets quantized along with the rest.
+    pub eq: Vec<EqBand>,
+    /// Subtract the mean of each channel over the whole render before bit reduction, which takes an extra pass to measure
+    pub remove_dc: bool,
+    /// Stereo width applied by mid/side processing before bit reduction, see [`crate::stereo_width`]: 0.0 is mono, 1.0 unchanged and above it wider,
+    /// up to [`MAX_STEREO_WIDTH`]
+    pub width: f32,
+    /// Swap the left and right channel of the output
+    pub swap_channels: bool,
+    /// Dither applied before bit reduction
+    pub dither: DitherMode,
+    /// Seed of the dither noise; the same seed and inputs always give byte-identical output
+    pub seed: u64,
+    /// Placement of the quantization levels used for bit reduction
+    pub quantization: QuantizationMode,
+    /// Rounding onto the quantization levels of bit reduction; mid-rise levels always round to the nearest one
+    pub rounding: RoundingMode,
+    /// Quantize onto the asymmetric two's complement range of the NDS, e.g. [-512, 511] at 10 bits, instead of a symmetric one
+    /// 
+    /// The levels are always mid-tread in this case, so `quantization` is ignored.
+    pub nds_clip: bool,
+    /// What bit reduction does with samples beyond full scale
+    pub overflow: OverflowMode,
+    /// Soft-clip the render above this level in dBFS before bit reduction, see [`crate::soft_clip`], or `None` to leave peaks to `overflow`
+    pub limit: Option<f32>,
+    /// Decibels to attenuate the render by right before bit reduction, after everything else including normalization and `limit`
+    /// 
+    /// Unlike `gain_db` this is meant to keep peaks off the ends of the quantizer, so a normalized render ends up `headroom_db` below its target.
+    pub headroom_db: f32,
+    /// Downmix to a single channel before writing, or `None` to keep stereo
+    pub mono: Option<MonoMode>,
+    /// Downmix before bit reduction instead of after it
+    /// 
+    /// Downmixing afterwards averages two independently quantized channels, so the mono output lands between the quantization levels.
+    pub mono_before_quantization: bool,
+    /// Sample encoding of the written files
+    pub format: SampleFormat,
+    /// Byte order of [`SampleFormat::Raw`] output
+    pub endian: Endianness,
+    /// Container and codec of the written files
+    pub codec: Codec,
+    /// Quality of [`Codec::Ogg`] output, from -2 (smallest) to 10 (best)
+    pub vorbis_quality: f32,
+    /// Whether the output goes into a stream that cannot seek, such as stdout, see [`crate::render_to_stream`]
+    /// 
+    /// Only [`Codec::Wav`] can be written that way, since the other codecs need the whole render or a seekable file.
+    pub stream: bool,
+    /// Folder to draw the waveform of every render into as a PNG named after its output file, see [`crate::write_waveform_png`]
+    pub waveform: Option<PathBuf>,
+    /// Folder to draw the spectrogram of every render into as a PNG named after its output file, see [`crate::write_spectrogram_png`]
+    pub spectrogram: Option<PathBuf>,
+    /// Samples per FFT of `spectrogram`, a power of two; larger sizes resolve frequencies more finely and time more coarsely
+    pub spectrogram_fft_size: usize,
+    /// Taper of each FFT of `spectrogram`
+    pub spectrogram_window: FftWindow,
+    /// MIDI channels (1 to 16) left out of the render
+    pub mute: Vec<u8>,
+    /// MIDI channels (1 to 16) to render on their own, or empty to render all of them
+    /// 
+    /// Channels that are both soloed and muted stay muted.
+    pub solo: Vec<u8>,
+    /// Programs (0 to 127) to hold MIDI channels (1 to 16) at as `(channel, program)`, whatever program changes the file makes on them
+    /// 
+    /// Bank selects still apply, so a channel keeps the bank the file chooses for it.
+    pub programs: Vec<(u8, u8)>,
+    /// MIDI channel (1 to 16) that the file puts its percussion on, [`crate::DRUM_CHANNEL`] by General MIDI
+    /// 
+    /// The synthesizer only plays drums on channel 10, so any other channel trades places with it.
+    /// Channel numbers of the other options, such as `mute` and `solo`, are those of the file.
+    pub drum_channel: u8,
+    /// Notes (0 to 127) of the drum channel to play as other notes, as `(from, to)`, for files written for a different drum map than the soundfont
+    /// 
+    /// By default the notes are left as they are, which selects the drum sounds by their General MIDI key numbers.
+    pub drum_map: Vec<(u8, u8)>,
+    /// Semitones to shift every note by, except on the drum channel
+    /// 
+    /// Notes shifted out of the MIDI range of 0 to 127 are dropped.
+    pub transpose: i32,
+    /// Factor to speed up every tempo of the MIDI file by, e.g. 0.5 for half speed
+    pub tempo_scale: f64,
+    /// Tempo in BPM to play the whole MIDI file at, replacing all of its tempo changes, or `None` to keep them
+    /// 
+    /// `tempo_scale` applies on top of it. Neither has an effect on files with SMPTE timing, which do not depend on the tempo.
+    pub bpm: Option<f64>,
+    /// Seconds into the MIDI file (after any tempo changes) to start rendering at
+    /// 
+    /// The file is skipped ahead to it: controllers and program changes before it still take effect, but notes that start earlier are not heard.
+    pub start: f64,
+    /// Seconds into the MIDI file to stop rendering at, or `None` to render to the end
+    /// 
+    /// `tail` is not added after it, so the render stops right there.
+    pub end: Option<f64>,
+    /// Factor to scale note velocities by, after remapping them along `velocity_curve`; results are clamped to 1 to 127
+    pub velocity_scale: f64,
+    /// Curve to remap note velocities along
+    pub velocity_curve: VelocityCurve,
+    /// How bank select messages pick the soundfont bank of a program change
+    pub bank_mode: BankMode,
+    /// Pitch bend range in semitones to give every channel, or `None` for the default of 2 and whatever the file sets with RPN 0
+    pub bend_range: Option<f64>,
+    /// Scale the render by the GM or GS master volume SysEx message the file starts with, unless normalizing
+    pub master_volume: bool,
+    /// Move pan controller positions to where the linear panning of the NDS puts them, instead of the equal-power panning of `rustysynth`
+    /// 
+    /// Only the balance between the channels changes; centred parts keep the level equal-power panning gives them.
+    pub nds_pan: bool,
+    /// Most voices that sound at once, 16 like the hardware channels of the NDS; `rustysynth` supports 8 to 256
+    /// 
+    /// Every soundfont layer has a synthesizer, and so a limit, of its own.
+    pub max_voices: usize,
+    /// Which voice makes room for a new one once `max_voices` are sounding
+    pub voice_stealing: VoiceStealing,
+    /// Run the reverb and chorus effects of the synthesizer, which `rustysynth` only has a single switch for
+    /// 
+    /// The reverb keeps sounding after the last note, so `tail` should be long enough for it, see [`DEFAULT_REVERB_TAIL`].
+    pub reverb_and_chorus: bool,
+}
+
+impl RenderConfig {
+    /// Sample rate of the rendered output
+    pub fn output_sample_rate(&self) -> u32 {
+        self.output_rate.unwrap_or(self.nds_rate)
+    }
+
+    /// Number of channels in the written files
+    pub fn channels(&self) -> u16 {
+        if self.mono.is_some() { 1 } else { 2 }
+    }
+
+    /// File extension of the written files
+    pub fn output_extension(&self) -> &'static str {
+        match self.format {
+            SampleFormat::Raw => "raw",
+            _ => self.codec.extension(),
+        }
+    }
+
+    /// Whether the MIDI channel `channel` (1 to 16) is heard in the render, following `mute` and `solo`
+    pub fn is_channel_audible(&self, channel: u8) -> bool {
+        (self.solo.is_empty() || self.solo.contains(&channel)) && !self.mute.contains(&channel)
+    }
+
+    /// Checks that the options describe a render that can actually be carried out
+    pub fn validate(&self) -> Result<(), RenderError> {
+        if self.bitdepth > 32 {
+            return Err(RenderError::InvalidConfig(format!("bit depth must be 0 (disabled) or between 1 and 32, got {}", self.bitdepth)));
+        }
+        if self.nds_rate == 0 {
+            return Err(RenderError::InvalidConfig("NDS sample rate must be positive, got 0".to_string()));
+        }
+        if self.output_rate == Some(0) {
+            return Err(RenderError::InvalidConfig("output sample rate must be positive, got 0".to_string()));
+        }
+        if let Some(bits) = self.output_bitdepth {
+            if !(2..=32).contains(&bits) {
+                return Err(RenderError::InvalidConfig(format!("output bit depth must be between 2 and 32, got {}", bits)));
+            }
+        }
+        if let Some(threshold) = self.limit {
+            if threshold.is_nan() || threshold > 0.0 {
+                return Err(RenderError::InvalidConfig(format!("limiter threshold must be at most 0 dBFS, got {}", threshold)));
+            }
+        }
+        if !(0.0..=MAX_STEREO_WIDTH).contains(&self.width) {
+            return Err(RenderError::InvalidConfig(format!("stereo width must be between 0 and {}, got {}", MAX_STEREO_WIDTH, self.width)));
+        }
+        if !(self.headroom_db >= 0.0 && self.headroom_db.is_finite()) {
+            return Err(RenderError::InvalidConfig(format!("headroom must be a non-negative number of decibels, got {}", self.headroom_db)));
+        }
+        if self.crush_rate == Some(0) {
+            return Err(RenderError::InvalidConfig("crush rate must hold every sample for at least 1 frame".to_string()));
+        }
+        if let Some(cutoff) = self.antialias {
+            if !(cutoff > 0.0 && cutoff <= 1.0) {
+                return Err(RenderError::InvalidConfig(format!("anti-aliasing cutoff must be a fraction of Nyquist in (0, 1], got {}", cutoff)));
+            }
+        }
+        if !(self.repeat > 0.0 && self.repeat.is_finite()) {
+            return Err(RenderError::InvalidConfig(format!("repeat count must be positive, got {}", self.repeat)));
+        }
+        if let Some((start, end)) = self.loop_region {
+            if !(start >= 0.0 && start < end && end.is_finite()) {
+                return Err(RenderError::InvalidConfig(format!("loop region must start at or after 0 seconds and end after it starts, got {} to {}", start, end)));
+            }
+        }
+        if !(self.loop_crossfade >= 0.0 && self.loop_crossfade.is_finite()) {
+            return Err(RenderError::InvalidConfig(format!("loop crossfade must be a non-negative number of seconds, got {}", self.loop_crossfade)));
+        }
+        if let Some(semitones) = self.bend_range {
+            if !(0.0..=127.0).contains(&semitones) {
+                return Err(RenderError::InvalidConfig(format!("pitch bend range must be between 0 and 127 semitones, got {}", semitones)));
+            }
+        }
+        for (name, cutoff) in [("low-pass", self.lowpass), ("high-pass", self.highpass)] {
+            if let Some(cutoff) = cutoff {
+                if !(cutoff > 0.0 && cutoff < self.nds_rate as f64 / 2.0) {
+                    return Err(RenderError::InvalidConfig(format!("{} cutoff must be between 0 Hz and the Nyquist frequency of the NDS rate, got {}", name, cutoff)));
+                }
+            }
+        }
+        for band in &self.eq {
+            if !(band.frequency > 0.0 && band.frequency < self.nds_rate as f64 / 2.0) {
+                return Err(RenderError::InvalidConfig(format!("EQ band frequency must be between 0 Hz and the Nyquist frequency of the NDS rate, got {}", band.frequency)));
+            }
+            if !(band.q > 0.0 && band.q.is_finite() && band.gain_db.is_finite()) {
+                return Err(RenderError::InvalidConfig(format!("EQ band `{}` needs a finite gain and a positive quality factor", band)));
+            }
+        }
+        if self.oversample == 0 {
+            return Err(RenderError::InvalidConfig("oversampling factor must be at least 1".to_string()));
+        }
+        // The limit of `rustysynth`
+        if self.nds_rate as u64 * self.oversample as u64 > 192_000 {
+            return Err(RenderError::InvalidConfig(format!("the synthesizer runs at most at 192000 Hz, but {} times {} Hz is more", self.oversample, self.nds_rate)));
+        }
+        if !(8..=256).contains(&self.max_voices) {
+            return Err(RenderError::InvalidConfig(format!("maximum number of voices must be between 8 and 256, got {}", self.max_voices)));
+        }
+        if !self.spectrogram_fft_size.is_power_of_two() || !(16..=65536).contains(&self.spectrogram_fft_size) {
+            return Err(RenderError::InvalidConfig(format!("spectrogram FFT size must be a power of two from 16 to 65536, got {}", self.spectrogram_fft_size)));
+        }
+        if !(-2.0..=10.0).contains(&self.vorbis_quality) {
+            return Err(RenderError::InvalidConfig(format!("Vorbis quality must be between -2 and 10, got {}", self.vorbis_quality)));
+        }
+        if self.format == SampleFormat::Raw && !matches!(self.codec, Codec::Wav | Codec::Adpcm) {
+            return Err(RenderError::InvalidConfig(format!("raw output is headerless and cannot be encoded as {}", self.codec)));
+        }
+        if self.stream && self.codec != Codec::Wav {
+            return Err(RenderError::InvalidConfig(format!("{} output cannot be streamed, only wav and raw output can", self.codec)));
+        }
+        if self.tail.is_nan() || self.tail < 0.0 {
+            return Err(RenderError::InvalidConfig(format!("tail length must not be negative, got {}", self.tail)));
+        }
+        if let Some(stop) = self.stop_on_silence {
+            if !(stop.hold > 0.0 && stop.max_duration > 0.0) {
+                return Err(RenderError::InvalidConfig(format!("silence hold and maximum duration must be positive, got {} and {}", stop.hold, stop.max_duration)));
+            }
+        }
+        if !(self.fade_in >= 0.0 && self.fade_out >= 0.0) {
+            return Err(RenderError::InvalidConfig(format!("fade lengths must not be negative, got {} and {}", self.fade_in, self.fade_out)));
+        }
+        if !(self.tempo_scale > 0.0 && self.tempo_scale.is_finite()) {
+            return Err(RenderError::InvalidConfig(format!("tempo scale must be positive, got {}", self.tempo_scale)));
+        }
+        if let Some(bpm) = self.bpm {
+            if !(bpm > 0.0 && bpm.is_finite()) {
+                return Err(RenderError::InvalidConfig(format!("tempo must be a positive number of BPM, got {}", bpm)));
+            }
+        }
+        if !(self.start >= 0.0 && self.start.is_finite()) {
+            return Err(RenderError::InvalidConfig(format!("start time must not be negative, got {}", self.start)));
+        }
+        if let Some(end) = self.end {
+            if end.is_nan() || end <= self.start {
+                return Err(RenderError::InvalidConfig(format!("end time must be after the start time of {}, got {}", self.start, end)));
+            }
+        }
+        if !(self.velocity_scale > 0.0 && self.velocity_scale.is_finite()) {
+            return Err(RenderError::InvalidConfig(format!("velocity scale must be positive, got {}", self.velocity_scale)));
+        }
+        let program_channels = self.programs.iter().map(|(channel, _)| channel);
+        if let Some(channel) = self.mute.iter().chain(self.solo.iter()).chain(program_channels).find(|channel| !(1..=16).contains(*channel)) {
+            return Err(RenderError::InvalidConfig(format!("MIDI channels are numbered 1 to 16, got {}", channel)));
+        }
+        if !(1..=16).contains(&self.drum_channel) {
+            return Err(RenderError::InvalidConfig(format!("MIDI channels are numbered 1 to 16, got {}", self.drum_channel)));
+        }
+        if let Some(note) = self.drum_map.iter().flat_map(|&(from, to)| [from, to]).find(|&note| note > 127) {
+            return Err(RenderError::InvalidConfig(format!("MIDI notes are numbered 0 to 127, got {}", note)));
+        }
+        if let Some((_, program)) = self.programs.iter().find(|(_, program)| *program > 127) {
+            return Err(RenderError::InvalidConfig(format!("MIDI programs are numbered 0 to 127, got {}", program)));
+        }
+        Ok(())
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            bitdepth: 10,
+            output_bitdepth: None,
+            crush_rate: None,
+            nds_rate: 32729,
+            oversample: 1,
+            output_rate: None,
+            resample: ResampleMode::Zoh,
+            antialias: None,
+            repeat: 1.0,
+            loop_crossfade: 0.0,
+            loop_region: None,
+            tail: DEFAULT_TAIL,
+            stop_on_silence: None,
+            trim: None,
+            normalize: NormalizeMode::None,
+            target_peak_dbfs: -1.0,
+            target_lufs: -14.0,
+            gain_db: 0.0,
+            fade_in: 0.0,
+            fade_out: 0.0,
+            fade_curve: FadeCurve::Linear,
+            dac_filter: false,
+            dac_highpass: false,
+            lowpass: None,
+            highpass: None,
+            eq: Vec::new(),
+            remove_dc: false,
+            width: 1.0,
+            swap_channels: false,
+            dither: DitherMode::None,
+            seed: DEFAULT_SEED,
+            quantization: QuantizationMode::MidTread,
+            rounding: RoundingMode::Nearest,
+            nds_clip: false,
+            overflow: OverflowMode::Clamp,
+            limit: None,
+            headroom_db: 0.0,
+            mono: None,
+            mono_before_quantization: false,
+            format: SampleFormat::Float32,
+            endian: Endianness::Little,
+            codec: Codec::Wav,
+            vorbis_quality: 6.0,
+            stream: false,
+            waveform: None,
+            spectrogram: None,
+            spectrogram_fft_size: 2048,
+            spectrogram_window: FftWindow::Hann,
+            mute: Vec::new(),
+            solo: Vec::new(),
+            programs: Vec::new(),
+            drum_channel: DRUM_CHANNEL,
+            drum_map: Vec::new(),
+            transpose: 0,
+            tempo_scale: 1.0,
+            bpm: None,
+            start: 0.0,
+            end: None,
+            velocity_scale: 1.0,
+            velocity_curve: VelocityCurve::Linear,
+            bank_mode: BankMode::Gs,
+            bend_range: None,
+            master_volume: true,
+            nds_pan: false,
+            max_voices: 16,
+            voice_stealing: VoiceStealing::Quietest,
+            reverb_and_chorus: false,
+        }
+    }
+}
+
+/// How the signal is dithered before bit reduction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DitherMode {
+    /// Plain rounding
+    #[default]
+    None,
+    /// ±1 LSB of triangular (TPDF) noise, which decorrelates the quantization error from the signal
+    Tpdf,
+    /// TPDF dither plus error-feedback noise shaping that pushes the noise towards high frequencies, see [`crate::NOISE_SHAPING_COEFFICIENTS`]
+    Shaped,
+}
+
+impl FromStr for DitherMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(DitherMode::None),
+            "tpdf" => Ok(DitherMode::Tpdf),
+            "shaped" => Ok(DitherMode::Shaped),
+            _ => Err(format!("unknown dither mode `{}` (expected none, tpdf or shaped)", s)),
+        }
+    }
+}
+
+impl fmt::Display for DitherMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DitherMode::None => "none",
+            DitherMode::Tpdf => "tpdf",
+            DitherMode::Shaped => "shaped",
+        })
+    }
+}
+
+/// Placement of the quantization levels relative to zero
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuantizationMode {
+    /// Levels at whole multiples of the step size, so silence stays exactly zero
+    /// 
+    /// This is how the integer samples of the NDS mixer behave, and is what this tool has always done.
+    #[default]
+    MidTread,
+    /// Levels halfway between multiples of the step size, so there is no zero level and silence toggles between ±½ LSB
+    MidRise,
+}
+
+impl FromStr for QuantizationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mid-tread" | "midtread" => Ok(QuantizationMode::MidTread),
+            "mid-rise" | "midrise" => Ok(QuantizationMode::MidRise),
+            _ => Err(format!("unknown quantization mode `{}` (expected mid-tread or mid-rise)", s)),
+        }
+    }
+}
+
+impl fmt::Display for QuantizationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QuantizationMode::MidTread => "mid-tread",
+            QuantizationMode::MidRise => "mid-rise",
+        })
+    }
+}
+
+/// How a sample between two quantization levels is rounded onto one of them
+/// 
+/// References for the NDS disagree on this, so it can be matched to whichever one a render is compared against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// To the nearest level, halfway cases away from zero
+    #[default]
+    Nearest,
+    /// To the nearest level, halfway cases towards positive infinity
+    HalfUp,
+    /// To the level closer to zero, like a cast to an integer
+    TowardZero,
+    /// To the level below, like an arithmetic shift right
+    Floor,
+}
+
+impl RoundingMode {
+    /// Rounds `x` to an integer
+    pub fn round(self, x: f32) -> f32 {
+        match self {
+            RoundingMode::Nearest => x.round(),
+            RoundingMode::HalfUp => (x + 0.5).floor(),
+            RoundingMode::TowardZero => x.trunc(),
+            RoundingMode::Floor => x.floor(),
+        }
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" | "round" => Ok(RoundingMode::Nearest),
+            "half-up" | "halfup" => Ok(RoundingMode::HalfUp),
+            "toward-zero" | "truncate" => Ok(RoundingMode::TowardZero),
+            "floor" => Ok(RoundingMode::Floor),
+            _ => Err(format!("unknown rounding mode `{}` (expected nearest, half-up, toward-zero or floor)", s)),
+        }
+    }
+}
+
+impl fmt::Display for RoundingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RoundingMode::Nearest => "nearest",
+            RoundingMode::HalfUp => "half-up",
+            RoundingMode::TowardZero => "toward-zero",
+            RoundingMode::Floor => "floor",
+        })
+    }
+}
+
+/// How the two channels are combined by a mono downmix
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MonoMode {
+    /// The mean of both channels, which can never clip but is 6 dB quieter for sounds panned to one side
+    #[default]
+    Average,
+    /// The sum of both channels attenuated by 3 dB, which keeps the loudness of uncorrelated material but can clip on loud centred sounds
+    Sum3dB,
+}
+
+impl FromStr for MonoMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "average" | "avg" => Ok(MonoMode::Average),
+            "sum" | "sum-3db" => Ok(MonoMode::Sum3dB),
+            _ => Err(format!("unknown mono downmix `{}` (expected average or sum-3db)", s)),
+        }
+    }
+}
+
+impl fmt::Display for MonoMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MonoMode::Average => "average",
+            MonoMode::Sum3dB => "sum-3db",
+        })
+    }
+}
+
+/// What bit reduction does with samples that land beyond the highest or lowest quantization level
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowMode {
+    /// Hard-limit to full scale
+    #[default]
+    Clamp,
+    /// Wrap around to the opposite end of the range like an overflowing integer
+    /// 
+    /// This intentionally produces the harsh, aliasing-style distortion of overflowing NDS mixing rather than a clean clip.
+    Wrap,
+}
+
+impl FromStr for OverflowMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "clamp" => Ok(OverflowMode::Clamp),
+            "wrap" => Ok(OverflowMode::Wrap),
+            _ => Err(format!("unknown overflow mode `{}` (expected clamp or wrap)", s)),
+        }
+    }
+}
+
+impl fmt::Display for OverflowMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OverflowMode::Clamp => "clamp",
+            OverflowMode::Wrap => "wrap",
+        })
+    }
+}
@@ -0,0 +1,34 @@
+/// Seed used for the dither noise unless another one is given, so that renders are reproducible by default
+pub const DEFAULT_SEED: u64 = 0x4E44_535F_5345_4544;
+
+/// A small SplitMix64 generator, plenty for dither noise and fully reproducible from its seed
+#[derive(Clone, Debug)]
+pub struct DitherRng {
+    state: u64,
+}
+
+impl DitherRng {
+    pub fn new(seed: u64) -> Self {
+        DitherRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed number in [0.0, 1.0)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1_u64 << 24) as f32
+    }
+
+    /// Triangular noise in (-1.0, 1.0), the sum of two uniform numbers
+    /// 
+    /// Scaled by the size of one quantization step this is TPDF dither of ±1 LSB.
+    pub fn tpdf(&mut self) -> f32 {
+        self.next_f32() - self.next_f32()
+    }
+}
@@ -0,0 +1,434 @@
+use crate::error::RenderError;
+use crate::riff::{chunks, find_chunk, write_chunk, write_list, Chunk};
+
+/// Whether `bytes` is a DLS (Downloadable Sounds) instrument bank
+pub fn is_dls(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"DLS "
+}
+
+/// Converts the DLS instrument bank in `bytes` into an equivalent in-memory SF2 soundfont
+/// 
+/// Every DLS instrument becomes a preset at the same bank and program (drum instruments go to bank 128, where General MIDI drums are
+/// looked up) holding an instrument with a zone per region. Key and velocity ranges, root keys, tuning, gain, loops, exclusive groups,
+/// pan and the volume envelope carry over; other articulations such as LFOs and the filter are left out.
+/// Only 8- and 16-bit PCM waves are supported, and of multi-channel waves only the first channel is kept.
+pub fn convert_dls(bytes: &[u8]) -> Result<Vec<u8>, RenderError> {
+    if !is_dls(bytes) {
+        return Err(invalid("not a DLS file"));
+    }
+    let (_, body) = *chunks(bytes)?.first().ok_or_else(|| invalid("empty file"))?;
+    let top = chunks(&body[4..])?;
+
+    // Waves are referred to by their offset in the wave pool, through the pool table
+    let wvpl = lists(&top, b"wvpl").next().ok_or_else(|| invalid("missing wvpl list"))?;
+    let mut waves = Vec::new();
+    for (id, wave) in chunks(wvpl)? {
+        if id == b"LIST" && wave.starts_with(b"wave") {
+            let offset = wave.as_ptr() as usize - wvpl.as_ptr() as usize - 8;
+            waves.push((offset, parse_wave(&wave[4..])?));
+        }
+    }
+    let ptbl = top.iter().find(|(id, _)| *id == b"ptbl").map(|(_, body)| *body).ok_or_else(|| invalid("missing ptbl chunk"))?;
+    let table_start = u32_at(ptbl, 0)? as usize;
+    let cue_count = u32_at(ptbl, 4)? as usize;
+    let mut pool = Vec::with_capacity(cue_count);
+    for cue in 0..cue_count {
+        let offset = u32_at(ptbl, table_start + cue * 4)? as usize;
+        pool.push(waves.iter().position(|(wave_offset, _)| *wave_offset == offset));
+    }
+
+    let mut sf2 = Sf2Builder::default();
+    let mut sample_ids = Vec::with_capacity(waves.len());
+    for (index, (_, wave)) in waves.iter().enumerate() {
+        sample_ids.push(sf2.add_sample(&format!("Wave {}", index), wave));
+    }
+
+    let lins = lists(&top, b"lins").next().ok_or_else(|| invalid("missing lins list"))?;
+    for (id, instrument) in chunks(lins)? {
+        if id != b"LIST" || !instrument.starts_with(b"ins ") {
+            continue;
+        }
+        let instrument = chunks(&instrument[4..])?;
+        let insh = instrument.iter().find(|(id, _)| *id == b"insh").map(|(_, body)| *body).ok_or_else(|| invalid("instrument without insh chunk"))?;
+        let bank = u32_at(insh, 4)?;
+        let program = u32_at(insh, 8)? & 0x7F;
+        // Drum kits are flagged by the top bit, and otherwise the bank select MSB is in bits 8 to 14
+        let bank = if bank & 0x8000_0000 != 0 { 128 } else { (bank >> 8) & 0x7F };
+        let name = info_name(&instrument).unwrap_or_else(|| format!("Bank {} Program {}", bank, program));
+        let instrument_articulation = articulation(&instrument)?;
+
+        let mut zones = Vec::new();
+        for region_list in lists(&instrument, b"lrgn") {
+            for (id, region) in chunks(region_list)? {
+                if id != b"LIST" || !(region.starts_with(b"rgn ") || region.starts_with(b"rgn2")) {
+                    continue;
+                }
+                let region = chunks(&region[4..])?;
+                let Some(zone) = parse_region(&region, &pool, &waves, &sample_ids, &instrument_articulation)? else { continue };
+                zones.push(zone);
+            }
+        }
+        sf2.add_preset(&name, bank as u16, program as u16, zones);
+    }
+
+    Ok(sf2.finish())
+}
+
+/// A DLS wave: mono 16-bit samples with the default sample info of the wave
+struct Wave {
+    sample_rate: u32,
+    samples: Vec<i16>,
+    sample_info: Option<SampleInfo>,
+}
+
+/// The contents of a `wsmp` chunk
+#[derive(Clone, Copy)]
+struct SampleInfo {
+    unity_note: u16,
+    fine_tune: i16,
+    gain: i32,
+    /// Start and length of the loop in samples
+    sample_loop: Option<(u32, u32)>,
+}
+
+fn parse_wave(wave: &[u8]) -> Result<Wave, RenderError> {
+    let wave = chunks(wave)?;
+    let format = wave.iter().find(|(id, _)| *id == b"fmt ").map(|(_, body)| *body).ok_or_else(|| invalid("wave without fmt chunk"))?;
+    let data = wave.iter().find(|(id, _)| *id == b"data").map(|(_, body)| *body).ok_or_else(|| invalid("wave without data chunk"))?;
+    let format_tag = u16_at(format, 0)?;
+    let channels = u16_at(format, 2)?.max(1) as usize;
+    let sample_rate = u32_at(format, 4)?;
+    let bits_per_sample = u16_at(format, 14)?;
+    if format_tag != 1 {
+        return Err(invalid(&format!("unsupported wave format {}, only PCM is supported", format_tag)));
+    }
+    let samples = match bits_per_sample {
+        // 8-bit PCM is unsigned
+        8 => data.iter().step_by(channels).map(|&x| ((x as i16) - 128) << 8).collect(),
+        16 => data.chunks_exact(2).step_by(channels).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect(),
+        _ => return Err(invalid(&format!("unsupported wave bit depth {}", bits_per_sample))),
+    };
+    let sample_info = wave.iter().find(|(id, _)| *id == b"wsmp").map(|(_, body)| parse_sample_info(body)).transpose()?;
+    Ok(Wave { sample_rate, samples, sample_info })
+}
+
+fn parse_sample_info(wsmp: &[u8]) -> Result<SampleInfo, RenderError> {
+    let size = u32_at(wsmp, 0)? as usize;
+    let loop_count = u32_at(wsmp, 16)?;
+    let sample_loop = if loop_count > 0 {
+        Some((u32_at(wsmp, size + 8)?, u32_at(wsmp, size + 12)?))
+    } else {
+        None
+    };
+    Ok(SampleInfo {
+        unity_note: u16_at(wsmp, 4)?,
+        fine_tune: u16_at(wsmp, 6)? as i16,
+        gain: u32_at(wsmp, 8)? as i32,
+        sample_loop,
+    })
+}
+
+/// An instrument zone, whose generators end with the sample it plays
+struct Zone {
+    generators: Vec<(u16, u16)>,
+}
+
+fn parse_region(region: &[Chunk<'_>], pool: &[Option<usize>], waves: &[(usize, Wave)], sample_ids: &[u16], instrument_articulation: &[(u16, u16)]) -> Result<Option<Zone>, RenderError> {
+    let Some(rgnh) = region.iter().find(|(id, _)| *id == b"rgnh").map(|(_, body)| *body) else { return Ok(None) };
+    let Some(wlnk) = region.iter().find(|(id, _)| *id == b"wlnk").map(|(_, body)| *body) else { return Ok(None) };
+    let Some(Some(wave_index)) = pool.get(u32_at(wlnk, 8)? as usize).copied() else { return Ok(None) };
+    let wave = &waves[wave_index].1;
+
+    let key_range = (u16_at(rgnh, 0)?.min(127), u16_at(rgnh, 2)?.min(127));
+    let mut velocity_range = (u16_at(rgnh, 4)?.min(127), u16_at(rgnh, 6)?.min(127));
+    // Some banks leave the velocity range empty to mean all velocities
+    if velocity_range == (0, 0) {
+        velocity_range = (0, 127);
+    }
+    let key_group = u16_at(rgnh, 10)?;
+
+    // SF2 requires the key and velocity ranges to come first and the sample last
+    let mut generators = vec![
+        (GEN_KEY_RANGE, key_range.0 | key_range.1 << 8),
+        (GEN_VELOCITY_RANGE, velocity_range.0 | velocity_range.1 << 8),
+    ];
+    let region_articulation = articulation(region)?;
+    for &(generator, amount) in instrument_articulation {
+        if !region_articulation.iter().any(|&(region_generator, _)| region_generator == generator) {
+            generators.push((generator, amount));
+        }
+    }
+    generators.extend_from_slice(&region_articulation);
+    if key_group != 0 {
+        generators.push((GEN_EXCLUSIVE_CLASS, key_group));
+    }
+
+    let region_info = region.iter().find(|(id, _)| *id == b"wsmp").map(|(_, body)| parse_sample_info(body)).transpose()?;
+    if let Some(info) = region_info.or(wave.sample_info) {
+        generators.push((GEN_OVERRIDING_ROOT_KEY, info.unity_note.min(127)));
+        if info.fine_tune != 0 {
+            generators.push((GEN_FINE_TUNE, info.fine_tune as u16));
+        }
+        // The gain is in 1/655360 dB, the attenuation in centibels
+        let attenuation = (-(info.gain as f64) / 65536.0).round().clamp(0.0, 1440.0) as u16;
+        if attenuation != 0 {
+            generators.push((GEN_INITIAL_ATTENUATION, attenuation));
+        }
+        if let Some((start, length)) = info.sample_loop {
+            // The sample header holds the loop of the wave itself, so a region with a loop of its own moves it
+            let (wave_start, wave_end) = wave.sample_info.and_then(|info| info.sample_loop).map_or((0, 0), |(start, length)| (start, start + length));
+            let start_offset = start as i64 - wave_start as i64;
+            let end_offset = (start + length) as i64 - wave_end as i64;
+            push_offset(&mut generators, GEN_START_LOOP_OFFSET, GEN_START_LOOP_COARSE_OFFSET, start_offset);
+            push_offset(&mut generators, GEN_END_LOOP_OFFSET, GEN_END_LOOP_COARSE_OFFSET, end_offset);
+            generators.push((GEN_SAMPLE_MODES, 1));
+        }
+    }
+    generators.push((GEN_SAMPLE_ID, sample_ids[wave_index]));
+    Ok(Some(Zone { generators }))
+}
+
+/// Splits a sample offset into the fine and coarse (32768 samples) generators that hold it
+fn push_offset(generators: &mut Vec<(u16, u16)>, fine: u16, coarse: u16, offset: i64) {
+    if offset == 0 {
+        return;
+    }
+    let coarse_amount = offset.div_euclid(32768);
+    let fine_amount = offset.rem_euclid(32768);
+    if coarse_amount != 0 {
+        generators.push((coarse, coarse_amount as i16 as u16));
+    }
+    generators.push((fine, fine_amount as i16 as u16));
+}
+
+/// The volume envelope and pan of the level 1 or 2 articulation among `chunks`, as SF2 generators
+fn articulation(children: &[Chunk<'_>]) -> Result<Vec<(u16, u16)>, RenderError> {
+    let mut generators = Vec::new();
+    for list in children.iter().filter(|(id, body)| *id == b"LIST" && (body.starts_with(b"lart") || body.starts_with(b"lar2"))) {
+        for (id, art) in chunks(&list.1[4..])? {
+            if id != b"art1" && id != b"art2" {
+                continue;
+            }
+            let size = u32_at(art, 0)? as usize;
+            let count = u32_at(art, 4)? as usize;
+            for block in 0..count {
+                let offset = size + block * 12;
+                let (source, control, destination) = (u16_at(art, offset)?, u16_at(art, offset + 2)?, u16_at(art, offset + 4)?);
+                let scale = u32_at(art, offset + 8)? as i32;
+                // Only constant connections map onto plain generators
+                if source != 0 || control != 0 {
+                    continue;
+                }
+                let timecents = || if scale == i32::MIN { -12000 } else { (scale as f64 / 65536.0).round().clamp(-12000.0, 8000.0) as i16 };
+                let generator = match destination {
+                    DLS_PAN => Some((GEN_PAN, (scale as f64 / 65536.0).round().clamp(-500.0, 500.0) as i16)),
+                    DLS_EG1_DELAY => Some((GEN_DELAY_VOL_ENV, timecents())),
+                    DLS_EG1_ATTACK => Some((GEN_ATTACK_VOL_ENV, timecents())),
+                    DLS_EG1_HOLD => Some((GEN_HOLD_VOL_ENV, timecents())),
+                    DLS_EG1_DECAY => Some((GEN_DECAY_VOL_ENV, timecents())),
+                    DLS_EG1_RELEASE => Some((GEN_RELEASE_VOL_ENV, timecents())),
+                    DLS_EG1_SUSTAIN => {
+                        // A level in tenths of a percent, while SF2 wants the attenuation below full scale in centibels
+                        let level = (scale as f64 / 65536.0 / 1000.0).clamp(0.0, 1.0);
+                        let attenuation = if level > 0.0 { (-200.0 * level.log10()).min(1440.0) } else { 1440.0 };
+                        Some((GEN_SUSTAIN_VOL_ENV, attenuation.round() as i16))
+                    }
+                    _ => None,
+                };
+                if let Some((generator, amount)) = generator {
+                    generators.retain(|&(existing, _)| existing != generator);
+                    generators.push((generator, amount as u16));
+                }
+            }
+        }
+    }
+    Ok(generators)
+}
+
+/// The `INAM` of the `INFO` list among `chunks`
+fn info_name(children: &[Chunk<'_>]) -> Option<String> {
+    let info = lists(children, b"INFO").next()?;
+    let name = find_chunk(info, b"INAM").ok()?;
+    let name = String::from_utf8_lossy(name).trim_end_matches('\0').trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// The bodies of the `LIST` chunks of type `kind` among `chunks`, without the type
+fn lists<'a>(children: &'a [Chunk<'a>], kind: &'a [u8; 4]) -> impl Iterator<Item = &'a [u8]> + 'a {
+    children.iter().filter(move |(id, body)| *id == b"LIST" && body.starts_with(kind)).map(|(_, body)| &body[4..])
+}
+
+// DLS articulation destinations
+const DLS_PAN: u16 = 0x0004;
+const DLS_EG1_ATTACK: u16 = 0x0206;
+const DLS_EG1_DECAY: u16 = 0x0207;
+const DLS_EG1_RELEASE: u16 = 0x0209;
+const DLS_EG1_SUSTAIN: u16 = 0x020A;
+const DLS_EG1_DELAY: u16 = 0x020B;
+const DLS_EG1_HOLD: u16 = 0x020C;
+
+// SF2 generators
+const GEN_START_LOOP_OFFSET: u16 = 2;
+const GEN_END_LOOP_OFFSET: u16 = 3;
+const GEN_PAN: u16 = 17;
+const GEN_DELAY_VOL_ENV: u16 = 33;
+const GEN_ATTACK_VOL_ENV: u16 = 34;
+const GEN_HOLD_VOL_ENV: u16 = 35;
+const GEN_DECAY_VOL_ENV: u16 = 36;
+const GEN_SUSTAIN_VOL_ENV: u16 = 37;
+const GEN_RELEASE_VOL_ENV: u16 = 38;
+const GEN_INSTRUMENT: u16 = 41;
+const GEN_KEY_RANGE: u16 = 43;
+const GEN_VELOCITY_RANGE: u16 = 44;
+const GEN_START_LOOP_COARSE_OFFSET: u16 = 45;
+const GEN_INITIAL_ATTENUATION: u16 = 48;
+const GEN_END_LOOP_COARSE_OFFSET: u16 = 50;
+const GEN_FINE_TUNE: u16 = 52;
+const GEN_SAMPLE_ID: u16 = 53;
+const GEN_SAMPLE_MODES: u16 = 54;
+const GEN_EXCLUSIVE_CLASS: u16 = 57;
+const GEN_OVERRIDING_ROOT_KEY: u16 = 58;
+
+/// Zero samples that the SF2 specification requires after every sample
+const SAMPLE_PADDING: usize = 46;
+
+/// Assembles the chunks of an SF2 file with one instrument per preset
+#[derive(Default)]
+struct Sf2Builder {
+    samples: Vec<i16>,
+    shdr: Vec<u8>,
+    phdr: Vec<u8>,
+    pbag: Vec<u8>,
+    pgen: Vec<u8>,
+    inst: Vec<u8>,
+    ibag: Vec<u8>,
+    igen: Vec<u8>,
+    sample_count: u16,
+    preset_count: u16,
+    preset_bag_count: u16,
+    preset_generator_count: u16,
+    instrument_bag_count: u16,
+    instrument_generator_count: u16,
+}
+
+impl Sf2Builder {
+    fn add_sample(&mut self, name: &str, wave: &Wave) -> u16 {
+        let start = self.samples.len() as u32;
+        self.samples.extend_from_slice(&wave.samples);
+        let end = self.samples.len() as u32;
+        self.samples.resize(self.samples.len() + SAMPLE_PADDING, 0);
+        let info = wave.sample_info;
+        let (loop_start, loop_end) = info.and_then(|info| info.sample_loop).map_or((start, end), |(loop_start, length)| (start + loop_start, start + loop_start + length));
+        self.shdr.extend_from_slice(&name_bytes(name));
+        for field in [start, end, loop_start.min(end), loop_end.min(end), wave.sample_rate] {
+            self.shdr.extend_from_slice(&field.to_le_bytes());
+        }
+        self.shdr.push(info.map_or(60, |info| info.unity_note.min(127) as u8));
+        self.shdr.push(0); // pitch correction, applied through generators instead
+        self.shdr.extend_from_slice(&0_u16.to_le_bytes()); // sample link
+        self.shdr.extend_from_slice(&1_u16.to_le_bytes()); // mono sample
+        self.sample_count += 1;
+        self.sample_count - 1
+    }
+
+    fn add_preset(&mut self, name: &str, bank: u16, program: u16, zones: Vec<Zone>) {
+        let instrument = (self.inst.len() / 22) as u16;
+        self.inst.extend_from_slice(&name_bytes(name));
+        self.inst.extend_from_slice(&self.instrument_bag_count.to_le_bytes());
+        for zone in zones {
+            push_bag(&mut self.ibag, self.instrument_generator_count);
+            self.instrument_bag_count += 1;
+            for (generator, amount) in zone.generators {
+                push_generator(&mut self.igen, generator, amount);
+                self.instrument_generator_count += 1;
+            }
+        }
+
+        self.phdr.extend_from_slice(&name_bytes(name));
+        self.phdr.extend_from_slice(&program.to_le_bytes());
+        self.phdr.extend_from_slice(&bank.to_le_bytes());
+        self.phdr.extend_from_slice(&self.preset_bag_count.to_le_bytes());
+        self.phdr.extend_from_slice(&[0; 12]); // library, genre and morphology
+        push_bag(&mut self.pbag, self.preset_generator_count);
+        self.preset_bag_count += 1;
+        push_generator(&mut self.pgen, GEN_INSTRUMENT, instrument);
+        self.preset_generator_count += 1;
+        self.preset_count += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        // Every list ends with a terminal record
+        self.phdr.extend_from_slice(&name_bytes("EOP"));
+        self.phdr.extend_from_slice(&[0; 4]);
+        self.phdr.extend_from_slice(&self.preset_bag_count.to_le_bytes());
+        self.phdr.extend_from_slice(&[0; 12]);
+        push_bag(&mut self.pbag, self.preset_generator_count);
+        push_generator(&mut self.pgen, 0, 0);
+        self.inst.extend_from_slice(&name_bytes("EOI"));
+        self.inst.extend_from_slice(&self.instrument_bag_count.to_le_bytes());
+        push_bag(&mut self.ibag, self.instrument_generator_count);
+        push_generator(&mut self.igen, 0, 0);
+        self.shdr.extend_from_slice(&name_bytes("EOS"));
+        self.shdr.extend_from_slice(&[0; 26]);
+
+        let mut info = Vec::new();
+        write_chunk(&mut info, b"ifil", &[2, 0, 1, 0]);
+        write_chunk(&mut info, b"isng", b"EMU8000\0");
+        write_chunk(&mut info, b"INAM", b"Converted DLS\0");
+        let sample_bytes: Vec<u8> = self.samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        let mut sdta = Vec::new();
+        write_chunk(&mut sdta, b"smpl", &sample_bytes);
+        let mut pdta = Vec::new();
+        write_chunk(&mut pdta, b"phdr", &self.phdr);
+        write_chunk(&mut pdta, b"pbag", &self.pbag);
+        write_chunk(&mut pdta, b"pmod", &[0; 10]);
+        write_chunk(&mut pdta, b"pgen", &self.pgen);
+        write_chunk(&mut pdta, b"inst", &self.inst);
+        write_chunk(&mut pdta, b"ibag", &self.ibag);
+        write_chunk(&mut pdta, b"imod", &[0; 10]);
+        write_chunk(&mut pdta, b"igen", &self.igen);
+        write_chunk(&mut pdta, b"shdr", &self.shdr);
+
+        let mut body = b"sfbk".to_vec();
+        write_list(&mut body, b"INFO", &info);
+        write_list(&mut body, b"sdta", &sdta);
+        write_list(&mut body, b"pdta", &pdta);
+        let mut sf2 = Vec::with_capacity(body.len() + 8);
+        write_chunk(&mut sf2, b"RIFF", &body);
+        sf2
+    }
+}
+
+fn push_bag(bags: &mut Vec<u8>, generator_index: u16) {
+    bags.extend_from_slice(&generator_index.to_le_bytes());
+    // No modulators
+    bags.extend_from_slice(&0_u16.to_le_bytes());
+}
+
+fn push_generator(generators: &mut Vec<u8>, generator: u16, amount: u16) {
+    generators.extend_from_slice(&generator.to_le_bytes());
+    generators.extend_from_slice(&amount.to_le_bytes());
+}
+
+/// A name padded or cut to the 20 bytes of SF2 records, always terminated
+fn name_bytes(name: &str) -> [u8; 20] {
+    let mut bytes = [0; 20];
+    for (byte, c) in bytes.iter_mut().take(19).zip(name.bytes()) {
+        *byte = c;
+    }
+    bytes
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, RenderError> {
+    let bytes = data.get(offset..offset + 2).ok_or_else(|| invalid("chunk is too short"))?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, RenderError> {
+    let bytes = data.get(offset..offset + 4).ok_or_else(|| invalid("chunk is too short"))?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn invalid(message: &str) -> RenderError {
+    RenderError::InvalidSoundFont(message.to_string())
+}
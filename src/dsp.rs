@@ -0,0 +1,425 @@
+use std::{fmt, str::FromStr};
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+/// A second-order IIR filter section, run in transposed direct form II
+/// 
+/// Coefficients and state are kept in double precision so that low cutoffs stay stable. Samples go in and out as `f32`,
+/// or as `f64` through [`Biquad::process_sample_f64`] to chain sections without rounding in between.
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    /// Builds a section from the coefficients of `H(z) = (b0 + b1·z^-1 + b2·z^-2) / (a0 + a1·z^-1 + a2·z^-2)`
+    pub fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// Second-order low-pass at `cutoff` Hz with quality factor `q`, from the RBJ audio EQ cookbook
+    pub fn lowpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let (cos, alpha) = cookbook_terms(sample_rate, cutoff, q);
+        Biquad::new((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// Second-order high-pass at `cutoff` Hz with quality factor `q`, from the RBJ audio EQ cookbook
+    pub fn highpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let (cos, alpha) = cookbook_terms(sample_rate, cutoff, q);
+        Biquad::new((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// Peaking bell at `frequency` Hz that boosts or cuts by `gain_db`, as wide as quality factor `q` makes it, from the RBJ audio EQ cookbook
+    pub fn peaking(sample_rate: f64, frequency: f64, gain_db: f64, q: f64) -> Self {
+        let a = 10_f64.powf(gain_db / 40.0);
+        let (cos, alpha) = cookbook_terms(sample_rate, frequency, q);
+        Biquad::new(1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a)
+    }
+
+    /// Shelf that boosts or cuts everything below `frequency` Hz by `gain_db`, with quality factor `q`, from the RBJ audio EQ cookbook
+    pub fn low_shelf(sample_rate: f64, frequency: f64, gain_db: f64, q: f64) -> Self {
+        let a = 10_f64.powf(gain_db / 40.0);
+        let (cos, alpha) = cookbook_terms(sample_rate, frequency, q);
+        let shelf = 2.0 * a.sqrt() * alpha;
+        Biquad::new(
+            a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+            (a + 1.0) + (a - 1.0) * cos + shelf,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - shelf,
+        )
+    }
+
+    /// Shelf that boosts or cuts everything above `frequency` Hz by `gain_db`, with quality factor `q`, from the RBJ audio EQ cookbook
+    pub fn high_shelf(sample_rate: f64, frequency: f64, gain_db: f64, q: f64) -> Self {
+        let a = 10_f64.powf(gain_db / 40.0);
+        let (cos, alpha) = cookbook_terms(sample_rate, frequency, q);
+        let shelf = 2.0 * a.sqrt() * alpha;
+        Biquad::new(
+            a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+            (a + 1.0) - (a - 1.0) * cos + shelf,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - shelf,
+        )
+    }
+
+    pub fn process_sample(&mut self, x: f32) -> f32 {
+        self.process_sample_f64(x as f64) as f32
+    }
+
+    pub fn process_sample_f64(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    /// Filters `samples` in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for x in samples.iter_mut() {
+            *x = self.process_sample(*x);
+        }
+    }
+
+    /// Clears the filter state, as if it had only ever seen silence
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+}
+
+/// `cos(w0)` and `alpha` as used by the RBJ cookbook formulas, with the cutoff kept just under Nyquist
+fn cookbook_terms(sample_rate: f64, cutoff: f64, q: f64) -> (f64, f64) {
+    let cutoff = cutoff.clamp(1.0, sample_rate * 0.4999);
+    let w0 = 2.0 * PI * cutoff / sample_rate;
+    (w0.cos(), w0.sin() / (2.0 * q))
+}
+
+/// A 4th-order Butterworth low-pass at `cutoff` Hz, as a cascade of two [`Biquad`]s
+pub fn butterworth_lowpass(sample_rate: f64, cutoff: f64) -> [Biquad; 2] {
+    // Quality factors of the two sections, 1 / (2·cos(π/8)) and 1 / (2·cos(3π/8))
+    [
+        Biquad::lowpass(sample_rate, cutoff, 0.541_196_1),
+        Biquad::lowpass(sample_rate, cutoff, 1.306_563),
+    ]
+}
+
+/// Corner frequency in Hz of the treble roll-off of the NDS output path
+/// 
+/// Like [`NDS_DAC_HIGHPASS_HZ`] it approximates the response of the hardware rather than being a measurement of it.
+pub const NDS_DAC_LOWPASS_HZ: f64 = 11_000.0;
+/// Corner frequency in Hz of the bass roll-off from the coupling capacitors of the NDS output path
+pub const NDS_DAC_HIGHPASS_HZ: f64 = 30.0;
+
+/// The frequency response of the NDS output path: a Butterworth low-pass at [`NDS_DAC_LOWPASS_HZ`],
+/// and optionally a Butterworth high-pass at [`NDS_DAC_HIGHPASS_HZ`], each a single [`Biquad`] rolling off at 12 dB per octave
+pub fn nds_dac_filter(sample_rate: f64, highpass: bool) -> Vec<Biquad> {
+    let mut sections = vec![Biquad::lowpass(sample_rate, NDS_DAC_LOWPASS_HZ, FRAC_1_SQRT_2)];
+    if highpass {
+        sections.push(Biquad::highpass(sample_rate, NDS_DAC_HIGHPASS_HZ, FRAC_1_SQRT_2));
+    }
+    sections
+}
+
+/// Shape of an [`EqBand`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EqShape {
+    /// A bell around the frequency of the band
+    #[default]
+    Peak,
+    /// A shelf below the frequency of the band
+    LowShelf,
+    /// A shelf above the frequency of the band
+    HighShelf,
+}
+
+impl FromStr for EqShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "peak" | "bell" => Ok(EqShape::Peak),
+            "lowshelf" | "low-shelf" | "ls" => Ok(EqShape::LowShelf),
+            "highshelf" | "high-shelf" | "hs" => Ok(EqShape::HighShelf),
+            _ => Err(format!("unknown EQ band shape `{}` (expected peak, lowshelf or highshelf)", s)),
+        }
+    }
+}
+
+impl fmt::Display for EqShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EqShape::Peak => "peak",
+            EqShape::LowShelf => "lowshelf",
+            EqShape::HighShelf => "highshelf",
+        })
+    }
+}
+
+/// A band of the parametric EQ of [`crate::RenderConfig::eq`]
+/// 
+/// It parses from `FREQ:GAIN:Q` for a peak, or `SHAPE:FREQ:GAIN:Q` for any [`EqShape`], e.g. `3000:-4:2` or `lowshelf:120:3:0.7`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqBand {
+    pub shape: EqShape,
+    /// Centre frequency of a peak, or corner frequency of a shelf, in Hz
+    pub frequency: f64,
+    /// Boost (positive) or cut (negative) in dB
+    pub gain_db: f64,
+    /// Quality factor, where higher values make a peak narrower and a shelf steeper
+    pub q: f64,
+}
+
+impl EqBand {
+    /// The section that plays this band at `sample_rate`
+    pub fn biquad(&self, sample_rate: f64) -> Biquad {
+        match self.shape {
+            EqShape::Peak => Biquad::peaking(sample_rate, self.frequency, self.gain_db, self.q),
+            EqShape::LowShelf => Biquad::low_shelf(sample_rate, self.frequency, self.gain_db, self.q),
+            EqShape::HighShelf => Biquad::high_shelf(sample_rate, self.frequency, self.gain_db, self.q),
+        }
+    }
+}
+
+impl FromStr for EqBand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').map(str::trim).collect();
+        let (shape, frequency, gain_db, q) = match parts[..] {
+            [frequency, gain_db, q] => (EqShape::Peak, frequency, gain_db, q),
+            [shape, frequency, gain_db, q] => (shape.parse()?, frequency, gain_db, q),
+            _ => return Err(format!("expected FREQ:GAIN:Q or SHAPE:FREQ:GAIN:Q, got `{}`", s)),
+        };
+        let number = |text: &str, what: &str| text.parse::<f64>().map_err(|_| format!("`{}` is not a {}", text, what));
+        Ok(EqBand {
+            shape,
+            frequency: number(frequency, "frequency in Hz")?,
+            gain_db: number(gain_db, "gain in dB")?,
+            q: number(q, "quality factor")?,
+        })
+    }
+}
+
+impl fmt::Display for EqBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}:{}", self.shape, self.frequency, self.gain_db, self.q)
+    }
+}
+
+/// A cascade of [`Biquad`]s run one after the other on a single channel
+/// 
+/// Samples pass through the whole cascade in double precision and are only rounded back to `f32` at the end.
+#[derive(Clone, Debug, Default)]
+pub struct BiquadChain {
+    sections: Vec<Biquad>,
+}
+
+impl BiquadChain {
+    pub fn new(sections: Vec<Biquad>) -> Self {
+        BiquadChain { sections }
+    }
+
+    /// The chain that plays every band of `bands` at `sample_rate`, a parametric EQ
+    pub fn eq(sample_rate: f64, bands: &[EqBand]) -> Self {
+        BiquadChain::new(bands.iter().map(|band| band.biquad(sample_rate)).collect())
+    }
+
+    /// This chain with the sections of `next` run after its own
+    pub fn then(mut self, next: BiquadChain) -> Self {
+        self.sections.extend(next.sections);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    pub fn process_sample_f64(&mut self, x: f64) -> f64 {
+        self.sections.iter_mut().fold(x, |x, section| section.process_sample_f64(x))
+    }
+
+    /// Filters `samples` in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for x in samples.iter_mut() {
+            *x = self.process_sample_f64(*x as f64) as f32;
+        }
+    }
+
+    /// Clears the state of every section, as if the chain had only ever seen silence
+    pub fn reset(&mut self) {
+        self.sections.iter_mut().for_each(Biquad::reset);
+    }
+}
+
+/// A [`BiquadChain`] run on both channels, each with a state of its own
+#[derive(Clone, Debug, Default)]
+pub struct StereoFilter {
+    left: BiquadChain,
+    right: BiquadChain,
+}
+
+impl StereoFilter {
+    pub fn new(sections: Vec<Biquad>) -> Self {
+        StereoFilter::from_chain(BiquadChain::new(sections))
+    }
+
+    /// Runs `chain` on both channels
+    pub fn from_chain(chain: BiquadChain) -> Self {
+        StereoFilter { left: chain.clone(), right: chain }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty()
+    }
+
+    /// Filters `left` and `right` in place
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.left.process(left);
+        self.right.process(right);
+    }
+
+    /// Filters one sample of `channel`, 0 for left and 1 for right, leaving it in double precision for further processing
+    pub fn process_sample(&mut self, channel: usize, x: f64) -> f64 {
+        let chain = if channel == 0 { &mut self.left } else { &mut self.right };
+        chain.process_sample_f64(x)
+    }
+}
+
+/// Holds every `divisor`-th frame for the `divisor` frames that follow it, the stepping of a bitcrusher without changing the sample rate
+/// 
+/// The hold carries on across blocks, so a stream can be processed in chunks of any size.
+#[derive(Clone, Copy, Debug)]
+pub struct SampleHold {
+    divisor: usize,
+    position: usize,
+    held: (f32, f32),
+}
+
+impl SampleHold {
+    pub fn new(divisor: usize) -> Self {
+        SampleHold { divisor: divisor.max(1), position: 0, held: (0.0, 0.0) }
+    }
+
+    /// Replaces `left` and `right` in place with the frames being held
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            if self.position == 0 {
+                self.held = (*l, *r);
+            }
+            (*l, *r) = self.held;
+            self.position = (self.position + 1) % self.divisor;
+        }
+    }
+}
+
+/// Widest stereo image [`stereo_width`] is allowed to make, where the side signal is four times as loud as in the original
+/// 
+/// Wider than that and a hard-panned voice comes out at over twice its level on one side, in antiphase on the other.
+pub const MAX_STEREO_WIDTH: f32 = 4.0;
+
+/// Scales the side (difference) signal of `left` and `right` in place by `width`, keeping the mid (sum) signal as it is
+/// 
+/// A `width` of 0.0 folds the render to mono, 1.0 leaves it unchanged and values above 1.0 spread it wider; it is clamped to [0, [`MAX_STEREO_WIDTH`]].
+pub fn stereo_width(left: &mut [f32], right: &mut [f32], width: f32) {
+    let width = width.clamp(0.0, MAX_STEREO_WIDTH);
+    for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+        let mid = (*l + *r) * 0.5;
+        let side = (*l - *r) * 0.5 * width;
+        (*l, *r) = (mid + side, mid - side);
+    }
+}
+
+/// Soft-clips `samples` in place: anything within `threshold` of zero passes untouched,
+/// and the excess above it is bent by a tanh curve so that no sample ever reaches full scale
+/// 
+/// The curve joins the straight part with a matching slope, so signals just over the threshold only pick up a little distortion.
+pub fn soft_clip(samples: &mut [f32], threshold: f32) {
+    let threshold = threshold.clamp(0.0, 1.0);
+    let headroom = 1.0 - threshold;
+    for x in samples.iter_mut() {
+        let magnitude = x.abs();
+        if magnitude > threshold {
+            let bent = if headroom > 0.0 { threshold + headroom * ((magnitude - threshold) / headroom).tanh() } else { threshold };
+            *x = bent.copysign(*x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{PI, FRAC_1_SQRT_2};
+    use super::*;
+
+    const RATE: f64 = 32000.0;
+
+    /// Gain of `filter` on a sine at `frequency` Hz, measured once it has settled
+    fn gain(mut filter: Biquad, frequency: f64) -> f64 {
+        let output: Vec<f64> = (0..RATE as usize).map(|n| filter.process_sample_f64((2.0 * PI * frequency * n as f64 / RATE).sin())).collect();
+        // The second half, a whole number of periods for any even frequency
+        let settled = &output[output.len() / 2..];
+        let (re, im) = settled.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, &y)| {
+            let phase = 2.0 * PI * frequency * n as f64 / RATE;
+            (re + y * phase.cos(), im - y * phase.sin())
+        });
+        2.0 * (re * re + im * im).sqrt() / settled.len() as f64
+    }
+
+    fn assert_gain(filter: Biquad, frequency: f64, expected: f64) {
+        let gain = gain(filter, frequency);
+        assert!((gain - expected).abs() < 0.01, "gain of {} at {} Hz, expected {}", gain, frequency, expected);
+    }
+
+    #[test]
+    fn lowpass_and_highpass_cross_at_the_cutoff() {
+        let lowpass = Biquad::lowpass(RATE, 1000.0, FRAC_1_SQRT_2);
+        assert_gain(lowpass, 100.0, 1.0);
+        assert_gain(lowpass, 1000.0, FRAC_1_SQRT_2);
+        assert!(gain(lowpass, 10000.0) < 0.01);
+        let highpass = Biquad::highpass(RATE, 1000.0, FRAC_1_SQRT_2);
+        assert_gain(highpass, 10000.0, 1.0);
+        assert_gain(highpass, 1000.0, FRAC_1_SQRT_2);
+        assert!(gain(highpass, 100.0) < 0.02);
+    }
+
+    #[test]
+    fn peaking_and_shelves_reach_their_gain() {
+        let boost = 10_f64.powf(6.0 / 20.0);
+        assert_gain(Biquad::peaking(RATE, 1000.0, 6.0, 1.0), 1000.0, boost);
+        assert_gain(Biquad::peaking(RATE, 1000.0, 6.0, 1.0), 12000.0, 1.0);
+        assert_gain(Biquad::low_shelf(RATE, 1000.0, -6.0, FRAC_1_SQRT_2), 50.0, 1.0 / boost);
+        assert_gain(Biquad::low_shelf(RATE, 1000.0, -6.0, FRAC_1_SQRT_2), 12000.0, 1.0);
+        assert_gain(Biquad::high_shelf(RATE, 1000.0, 6.0, FRAC_1_SQRT_2), 12000.0, boost);
+        assert_gain(Biquad::high_shelf(RATE, 1000.0, 6.0, FRAC_1_SQRT_2), 50.0, 1.0);
+    }
+
+    #[test]
+    fn biquad_carries_its_state_across_blocks() {
+        let samples: Vec<f32> = (0..1000).map(|n| ((n * 7919) % 201) as f32 / 100.0 - 1.0).collect();
+        let mut filter = Biquad::lowpass(RATE, 3000.0, 0.9);
+        let mut whole = samples.clone();
+        filter.process(&mut whole);
+        filter.reset();
+        let mut blocks = samples.clone();
+        for block in blocks.chunks_mut(33) {
+            filter.process(block);
+        }
+        assert_eq!(whole, blocks);
+    }
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+use rustysynth::{SoundFontError, MidiFileError, SynthesizerError};
+use thiserror::Error;
+
+/// Everything that can go wrong while loading inputs or rendering them
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("failed to load soundfont: {0}")]
+    SoundFontLoad(#[from] SoundFontError),
+    #[error("failed to decode soundfont: {0}")]
+    InvalidSoundFont(String),
+    #[error("failed to parse MIDI file: {0}")]
+    MidiParse(#[from] MidiFileError),
+    #[error("failed to read MIDI events: {0}")]
+    InvalidMidi(String),
+    #[error("failed to set up the synthesizer: {0}")]
+    Synthesizer(#[from] SynthesizerError),
+    #[error("failed to write wave-file: {0}")]
+    Wav(#[from] hound::Error),
+    #[error("failed to measure loudness: {0}")]
+    Loudness(#[from] ebur128::Error),
+    #[error("failed to encode output: {0}")]
+    Encode(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("output path {} must be a folder", .0.display())]
+    OutputNotADirectory(PathBuf),
+    #[error("writing to stdout needs exactly one input file, but {0} were given")]
+    StdoutNeedsSingleInput(usize),
+    #[error("failed to play audio: {0}")]
+    Playback(String),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+}
@@ -0,0 +1,79 @@
+use std::{fmt, str::FromStr, f32::consts::FRAC_PI_2};
+
+/// Shape of the fade-in and fade-out envelopes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FadeCurve {
+    /// Gain rises or falls in a straight line
+    #[default]
+    Linear,
+    /// Gain follows a quarter sine, which keeps the perceived loudness steadier through the fade
+    EqualPower,
+}
+
+impl FadeCurve {
+    /// Gain at position `t` from 0.0 (silent) to 1.0 (full level)
+    pub fn gain(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => t,
+            FadeCurve::EqualPower => (t * FRAC_PI_2).sin(),
+        }
+    }
+}
+
+impl FromStr for FadeCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(FadeCurve::Linear),
+            "equal-power" | "equalpower" => Ok(FadeCurve::EqualPower),
+            _ => Err(format!("unknown fade curve `{}` (expected linear or equal-power)", s)),
+        }
+    }
+}
+
+impl fmt::Display for FadeCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FadeCurve::Linear => "linear",
+            FadeCurve::EqualPower => "equal-power",
+        })
+    }
+}
+
+/// Fade-in and fade-out envelopes over a render of a known number of frames
+pub(crate) struct Fades {
+    fade_in: usize,
+    fade_out: usize,
+    frames: usize,
+    curve: FadeCurve,
+}
+
+impl Fades {
+    pub fn new(fade_in: usize, fade_out: usize, frames: usize, curve: FadeCurve) -> Self {
+        Fades { fade_in, fade_out, frames, curve }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fade_in == 0 && self.fade_out == 0
+    }
+
+    /// Applies the envelopes to a block of both channels that starts `offset` frames into the render
+    pub fn apply(&self, left: &mut [f32], right: &mut [f32], offset: usize) {
+        for (i, (l, r)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+            let frame = offset + i;
+            let mut gain = 1.0;
+            if frame < self.fade_in {
+                gain *= self.curve.gain(frame as f32 / self.fade_in as f32);
+            }
+            // Measured from the very last frame, which ends up silent
+            let remaining = self.frames.saturating_sub(frame + 1);
+            if remaining < self.fade_out {
+                gain *= self.curve.gain(remaining as f32 / self.fade_out as f32);
+            }
+            *l *= gain;
+            *r *= gain;
+        }
+    }
+}
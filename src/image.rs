@@ -0,0 +1,165 @@
+use std::{f32::consts::PI, fmt, fs::File, io::BufWriter, path::Path, str::FromStr};
+use rustfft::{FftPlanner, num_complex::Complex};
+
+use crate::error::RenderError;
+use crate::output::write_atomically;
+
+/// Size in pixels of the images of [`write_waveform_png`]
+pub const WAVEFORM_WIDTH: u32 = 1024;
+pub const WAVEFORM_HEIGHT: u32 = 256;
+
+/// Size in pixels of the images of [`write_spectrogram_png`]
+pub const SPECTROGRAM_WIDTH: u32 = 1024;
+pub const SPECTROGRAM_HEIGHT: u32 = 512;
+/// Level in dB relative to a full-scale sine that is drawn black, everything quieter included
+const SPECTROGRAM_FLOOR_DB: f32 = -120.0;
+
+const BACKGROUND: [u8; 3] = [24, 24, 28];
+const CENTRE_LINE: [u8; 3] = [64, 64, 72];
+const WAVE: [u8; 3] = [110, 190, 255];
+const CLIPPED: [u8; 3] = [255, 80, 64];
+
+/// Draws the waveform of `left` and, for stereo, `right` into a PNG at `path`, one lane per channel
+/// 
+/// Every column of pixels spans the smallest to the largest sample of its stretch of the audio, so nothing is lost however long the render is.
+/// Columns with a sample at or beyond full scale are drawn in red, which makes clipping stand out.
+pub fn write_waveform_png<P: AsRef<Path>>(path: P, left: &[f32], right: Option<&[f32]>, width: u32, height: u32) -> Result<(), RenderError> {
+    let (width, height) = (width.max(1), height.max(2));
+    let mut image = vec![BACKGROUND; (width * height) as usize];
+    let lanes: Vec<&[f32]> = [Some(left), right].into_iter().flatten().collect();
+    let lane_height = height / lanes.len() as u32;
+    for (lane, samples) in lanes.iter().enumerate() {
+        let top = lane as u32 * lane_height;
+        // Full scale reaches the edges of the lane
+        let to_row = |x: f32| top + ((1.0 - x.clamp(-1.0, 1.0)) * 0.5 * (lane_height - 1) as f32).round() as u32;
+        for column in 0..width {
+            image[(to_row(0.0) * width + column) as usize] = CENTRE_LINE;
+        }
+        if samples.is_empty() {
+            continue;
+        }
+        for column in 0..width {
+            let start = column as usize * samples.len() / width as usize;
+            let end = ((column as usize + 1) * samples.len() / width as usize).max(start + 1).min(samples.len());
+            let Some(stretch) = samples.get(start..end) else { continue };
+            let (low, high) = stretch.iter().fold((f32::MAX, f32::MIN), |(low, high), &x| (low.min(x), high.max(x)));
+            let colour = if low <= -1.0 || high >= 1.0 { CLIPPED } else { WAVE };
+            for row in to_row(high)..=to_row(low) {
+                image[(row * width + column) as usize] = colour;
+            }
+        }
+    }
+    write_png(path, width, height, &image.concat())
+}
+
+/// Draws the spectrogram of `left` and `right` mixed to mono into a PNG at `path`, time from left to right and frequency from 0 Hz at the bottom
+/// up to Nyquist at the top, on a linear scale so that the mirror images of zero-order-hold resampling show up as evenly spaced copies
+/// 
+/// Each column is the spectrum of `fft_size` samples around its point in time, tapered by `window`; the rows of a column show the
+/// loudest bin they cover, from [`SPECTROGRAM_FLOOR_DB`] in black to full scale in white.
+pub fn write_spectrogram_png<P: AsRef<Path>>(path: P, left: &[f32], right: &[f32], fft_size: usize, window: FftWindow, width: u32, height: u32) -> Result<(), RenderError> {
+    let (width, height) = (width.max(1), height.max(1));
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(fft_size);
+    let coefficients: Vec<f32> = (0..fft_size).map(|i| window.coefficient(i, fft_size)).collect();
+    // A full-scale sine peaks at half the sum of the window in its bin
+    let reference = coefficients.iter().sum::<f32>() / 2.0;
+    let bins = fft_size / 2;
+    let frames = left.len();
+
+    let mut image = vec![[0_u8; 3]; (width * height) as usize];
+    let mut buffer = vec![Complex::new(0.0, 0.0); fft_size];
+    for column in 0..width {
+        let centre = (column as usize * frames + frames / 2) / width as usize;
+        for (i, value) in buffer.iter_mut().enumerate() {
+            let x = (centre + i).checked_sub(fft_size / 2).filter(|&index| index < frames).map_or(0.0, |index| (left[index] + right[index]) * 0.5);
+            *value = Complex::new(x * coefficients[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        for row in 0..height {
+            // Rows from the top, so the highest frequencies come first
+            let band = height - 1 - row;
+            let first = band as usize * bins / height as usize;
+            let last = ((band as usize + 1) * bins / height as usize).max(first + 1).min(bins);
+            let magnitude = buffer[first..last].iter().map(|bin| bin.norm()).fold(0_f32, f32::max);
+            let db = 20.0 * (magnitude / reference).max(1e-12).log10();
+            image[(row * width + column) as usize] = heat((db - SPECTROGRAM_FLOOR_DB) / -SPECTROGRAM_FLOOR_DB);
+        }
+    }
+    write_png(path, width, height, &image.concat())
+}
+
+/// A colour from black through blue, red and yellow to white for `level` from 0.0 to 1.0
+fn heat(level: f32) -> [u8; 3] {
+    const STOPS: [[f32; 3]; 5] = [[0.0, 0.0, 0.0], [40.0, 20.0, 140.0], [200.0, 30.0, 60.0], [255.0, 200.0, 0.0], [255.0, 255.0, 255.0]];
+    let position = level.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let t = position - index as f32;
+    let (from, to) = (STOPS[index], STOPS[index + 1]);
+    [0, 1, 2].map(|channel| (from[channel] + (to[channel] - from[channel]) * t).round() as u8)
+}
+
+/// Taper applied to each block of a spectrogram before its FFT, trading frequency resolution against leakage between bins
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FftWindow {
+    /// No taper, the sharpest peaks but the most leakage
+    Rectangular,
+    #[default]
+    Hann,
+    Hamming,
+    /// The least leakage, for seeing quiet aliases next to loud partials
+    Blackman,
+}
+
+impl FftWindow {
+    /// The weight of sample `i` of a block of `size`
+    pub fn coefficient(self, i: usize, size: usize) -> f32 {
+        let phase = 2.0 * PI * i as f32 / size.max(2) as f32;
+        match self {
+            FftWindow::Rectangular => 1.0,
+            FftWindow::Hann => 0.5 - 0.5 * phase.cos(),
+            FftWindow::Hamming => 0.54 - 0.46 * phase.cos(),
+            FftWindow::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+        }
+    }
+}
+
+impl FromStr for FftWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rectangular" | "none" => Ok(FftWindow::Rectangular),
+            "hann" | "hanning" => Ok(FftWindow::Hann),
+            "hamming" => Ok(FftWindow::Hamming),
+            "blackman" => Ok(FftWindow::Blackman),
+            _ => Err(format!("unknown window `{}` (expected rectangular, hann, hamming or blackman)", s)),
+        }
+    }
+}
+
+impl fmt::Display for FftWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FftWindow::Rectangular => "rectangular",
+            FftWindow::Hann => "hann",
+            FftWindow::Hamming => "hamming",
+            FftWindow::Blackman => "blackman",
+        })
+    }
+}
+
+/// Writes `rgb`, rows of 8-bit RGB pixels from the top, as a PNG at `path`
+pub(crate) fn write_png<P: AsRef<Path>>(path: P, width: u32, height: u32, rgb: &[u8]) -> Result<(), RenderError> {
+    write_atomically(path, |path| {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(encode_error)?;
+        writer.write_image_data(rgb).map_err(encode_error)?;
+        writer.finish().map_err(encode_error)
+    })
+}
+
+fn encode_error(error: png::EncodingError) -> RenderError {
+    RenderError::Encode(error.to_string())
+}
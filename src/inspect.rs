@@ -0,0 +1,125 @@
+use std::collections::BTreeSet;
+use rustysynth::{SoundFont, MidiFile};
+use serde::Serialize;
+use crate::midi::{Smf, EventKind};
+
+/// What a soundfont contains, for deciding which banks and programs to use
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SoundFontSummary {
+    /// Name of the soundfont from its `INAM` chunk
+    pub name: String,
+    /// Every preset, ordered by bank and then by program
+    pub presets: Vec<PresetSummary>,
+    pub instrument_count: usize,
+    pub sample_count: usize,
+}
+
+/// A preset of a soundfont and the MIDI bank and program that select it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PresetSummary {
+    pub bank: i32,
+    pub program: i32,
+    pub name: String,
+    /// Number of distinct samples the preset can play, across all of its instruments
+    pub sample_count: usize,
+}
+
+impl SoundFontSummary {
+    pub fn new(sound_font: &SoundFont) -> Self {
+        let instruments = sound_font.get_instruments();
+        let mut presets: Vec<PresetSummary> = sound_font.get_presets().iter().map(|preset| {
+            let samples: BTreeSet<usize> = preset.get_regions().iter()
+                .filter_map(|region| instruments.get(region.get_instrument_id()))
+                .flat_map(|instrument| instrument.get_regions().iter().map(|region| region.get_sample_id()))
+                .collect();
+            PresetSummary {
+                bank: preset.get_bank_number(),
+                program: preset.get_patch_number(),
+                name: preset.get_name().to_string(),
+                sample_count: samples.len(),
+            }
+        }).collect();
+        presets.sort_by_key(|preset| (preset.bank, preset.program));
+        SoundFontSummary {
+            name: sound_font.get_info().get_bank_name().to_string(),
+            presets,
+            instrument_count: instruments.len(),
+            sample_count: sound_font.get_sample_headers().len(),
+        }
+    }
+}
+
+/// The structure of a MIDI file and what it actually plays, for catching surprises before rendering
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MidiSummary {
+    pub format: u16,
+    pub track_count: usize,
+    /// Ticks per quarter note, `None` when the file uses SMPTE timing
+    pub ticks_per_quarter: Option<u16>,
+    /// Every tempo change, starting with the initial tempo at tick 0
+    pub tempo_changes: Vec<TempoChange>,
+    /// Length in seconds as `rustysynth` plays it
+    pub duration: f64,
+    /// Channels that play at least one note, in order
+    pub channels: Vec<ChannelSummary>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TempoChange {
+    pub tick: u64,
+    pub seconds: f64,
+    pub bpm: f64,
+}
+
+/// Use of a single MIDI channel
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ChannelSummary {
+    /// Channel number from 1 to 16, as shown by sequencers; 10 is the drum channel in General MIDI
+    pub channel: u8,
+    pub note_count: usize,
+    /// Programs selected when a note starts, 0 for notes played before any program change
+    pub programs: Vec<u8>,
+}
+
+impl MidiSummary {
+    pub fn new(midi_file: &MidiFile, smf: &Smf) -> Self {
+        let tempo_map = smf.tempo_map();
+        let tempo_changes = tempo_map.changes().map(|(tick, tempo)| TempoChange {
+            tick,
+            seconds: tempo_map.seconds_at(tick),
+            bpm: 60_000_000.0 / tempo as f64,
+        }).collect();
+
+        let mut current_programs = [0_u8; 16];
+        let mut note_counts = [0_usize; 16];
+        let mut programs: [BTreeSet<u8>; 16] = Default::default();
+        for (_, event) in smf.timeline() {
+            if let EventKind::Midi { status, data1, data2 } = event.kind {
+                let channel = (status & 0x0F) as usize;
+                match status & 0xF0 {
+                    0xC0 => current_programs[channel] = data1,
+                    // A note-on with velocity 0 is a note-off
+                    0x90 if data2 > 0 => {
+                        note_counts[channel] += 1;
+                        programs[channel].insert(current_programs[channel]);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let channels = (0..16).filter(|&channel| note_counts[channel] > 0).map(|channel| ChannelSummary {
+            channel: channel as u8 + 1,
+            note_count: note_counts[channel],
+            programs: programs[channel].iter().copied().collect(),
+        }).collect();
+
+        MidiSummary {
+            format: smf.format,
+            track_count: smf.tracks.len(),
+            ticks_per_quarter: smf.ticks_per_quarter(),
+            tempo_changes,
+            duration: midi_file.get_length(),
+            channels,
+        }
+    }
+}
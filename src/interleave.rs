@@ -0,0 +1,37 @@
+/// Interleaves `left` and `right` into `l0, r0, l1, r1, ...`, up to the end of the shorter one
+pub fn to_interleaved(left: &[f32], right: &[f32]) -> Vec<f32> {
+    left.iter().zip(right).flat_map(|(&l, &r)| [l, r]).collect()
+}
+
+/// Splits interleaved stereo `samples` back into left and right channels, the inverse of [`to_interleaved`]
+/// 
+/// A trailing sample without a partner is dropped.
+pub fn from_interleaved(samples: &[f32]) -> (Vec<f32>, Vec<f32>) {
+    samples.chunks_exact(2).map(|frame| (frame[0], frame[1])).unzip()
+}
+
+/// Interleaves any number of `channels` frame by frame, up to the end of the shortest one
+pub fn interleave(channels: &[&[f32]]) -> Vec<f32> {
+    let frames = channels.iter().map(|channel| channel.len()).min().unwrap_or(0);
+    let mut samples = Vec::with_capacity(frames * channels.len());
+    for frame in 0..frames {
+        samples.extend(channels.iter().map(|channel| channel[frame]));
+    }
+    samples
+}
+
+/// Splits `samples` with `channels` interleaved channels into one `Vec` per channel, the inverse of [`interleave`]
+/// 
+/// A trailing partial frame is dropped, and 0 channels give no `Vec`s at all.
+pub fn deinterleave(samples: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    if channels == 0 {
+        return Vec::new();
+    }
+    let mut planar = vec![Vec::with_capacity(samples.len() / channels); channels];
+    for frame in samples.chunks_exact(channels) {
+        for (channel, &x) in planar.iter_mut().zip(frame) {
+            channel.push(x);
+        }
+    }
+    planar
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+use rustysynth::{SoundFont, MidiFile, MidiFileSequencer, Synthesizer, SynthesizerSettings};
+
+use crate::error::RenderError;
+use crate::midi::{Smf, EventKind};
+
+/// A soundfont that plays some of the MIDI channels of a render in place of the main soundfont
+/// 
+/// `rustysynth` only loads a single soundfont per synthesizer, so every layer runs a synthesizer of its own
+/// on just its channels of the file, and the outputs are mixed back together.
+#[derive(Clone)]
+pub struct SoundFontLayer {
+    pub sound_font: Arc<SoundFont>,
+    /// MIDI channels (1 to 16) played through `sound_font`
+    pub channels: Vec<u8>,
+}
+
+/// A MIDI file and the soundfont to play it through, one of the parts of a layered render
+pub type LayerFile = (Arc<SoundFont>, Arc<MidiFile>);
+
+/// Splits `smf` into a file for each of `layers` with only its channels, along with a file of the remaining channels for `sound_font`
+/// 
+/// A channel given to several layers plays through the first of them. Without layers, the only file is `midi_file` itself.
+pub(crate) fn split_layers(sound_font: &Arc<SoundFont>, layers: &[SoundFontLayer], midi_file: &Arc<MidiFile>, smf: &Smf) -> Result<Vec<LayerFile>, RenderError> {
+    if layers.is_empty() {
+        return Ok(vec![(sound_font.clone(), midi_file.clone())]);
+    }
+    let layer_of = |channel: u8| layers.iter().position(|layer| layer.channels.contains(&channel));
+    let only = |layer: Option<usize>| -> Result<Arc<MidiFile>, RenderError> {
+        let mut smf = smf.clone();
+        smf.retain_events(|kind| match kind {
+            EventKind::Midi { status, .. } => layer_of((status & 0x0F) + 1) == layer,
+            _ => true,
+        });
+        smf.to_midi_file()
+    };
+    let mut files = vec![(sound_font.clone(), only(None)?)];
+    for (index, layer) in layers.iter().enumerate() {
+        files.push((layer.sound_font.clone(), only(Some(index))?));
+    }
+    Ok(files)
+}
+
+/// Several sequencers playing at once, mixed into a single output
+pub(crate) struct LayeredSequencer {
+    sequencers: Vec<MidiFileSequencer>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl LayeredSequencer {
+    /// Starts a synthesizer with `settings` playing each of `files`, which is a single `looping` pass through them unless told otherwise
+    pub fn start(files: &[LayerFile], settings: &SynthesizerSettings, looping: bool) -> Result<Self, RenderError> {
+        let mut sequencers = Vec::with_capacity(files.len());
+        for (sound_font, midi_file) in files {
+            let synthesizer = Synthesizer::new(sound_font, settings)?;
+            let mut sequencer = MidiFileSequencer::new(synthesizer);
+            sequencer.play(midi_file, looping);
+            sequencers.push(sequencer);
+        }
+        Ok(LayeredSequencer { sequencers, left: Vec::new(), right: Vec::new() })
+    }
+
+    pub fn render(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let (first, rest) = self.sequencers.split_first_mut().expect("there is always at least one sequencer");
+        first.render(left, right);
+        self.left.resize(left.len(), 0.0);
+        self.right.resize(right.len(), 0.0);
+        for sequencer in rest {
+            sequencer.render(&mut self.left, &mut self.right);
+            for (x, y) in left.iter_mut().zip(self.left.iter()) {
+                *x += y;
+            }
+            for (x, y) in right.iter_mut().zip(self.right.iter()) {
+                *x += y;
+            }
+        }
+    }
+}
+
+/// Plays layered files either once through a [`LayeredSequencer`] or several times in a row, crossfading from each repetition into the next
+/// 
+/// Every crossfaded repetition starts from a fresh set of synthesizers right at the end of the one before it,
+/// whose release tail then fades out over the crossfade while the new one fades in, both with equal power.
+/// The repetitions after the first can play files of their own, such as just the loop of a file that has an intro.
+pub(crate) struct RepeatingSequencer {
+    /// Files that each repetition after the first starts playing
+    files: Vec<LayerFile>,
+    sample_rate: i32,
+    maximum_polyphony: usize,
+    reverb_and_chorus: bool,
+    passes: usize,
+    /// Length of the first repetition, and of every one after it
+    first_frames: usize,
+    pass_frames: usize,
+    crossfade: usize,
+    position: usize,
+    started: usize,
+    current: LayeredSequencer,
+    fading: Option<LayeredSequencer>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl RepeatingSequencer {
+    pub fn new(files: &[LayerFile], settings: &SynthesizerSettings, looping: bool) -> Result<Self, RenderError> {
+        Ok(RepeatingSequencer {
+            files: files.to_vec(),
+            sample_rate: settings.sample_rate,
+            maximum_polyphony: settings.maximum_polyphony,
+            reverb_and_chorus: settings.enable_reverb_and_chorus,
+            passes: 1,
+            first_frames: 0,
+            pass_frames: 0,
+            crossfade: 0,
+            position: 0,
+            started: 1,
+            current: LayeredSequencer::start(files, settings, looping)?,
+            fading: None,
+            left: Vec::new(),
+            right: Vec::new(),
+        })
+    }
+
+    /// Plays `passes` repetitions of `pass_frames` frames each, starting with the one already playing, with crossfades of `crossfade` frames
+    /// 
+    /// A crossfade lasts at most a repetition, so that it is over before the next one starts.
+    pub fn with_crossfade(mut self, passes: usize, pass_frames: usize, crossfade: usize) -> Self {
+        self.passes = passes;
+        self.first_frames = pass_frames;
+        self.pass_frames = pass_frames;
+        self.crossfade = crossfade.clamp(1, pass_frames.max(1));
+        self
+    }
+
+    /// Plays `loop_files` instead of the files already playing for every repetition after the first, which then lasts `first_frames` frames
+    /// 
+    /// Called after [`RepeatingSequencer::with_crossfade`], whose `pass_frames` are the length of the repetitions of `loop_files`.
+    pub fn with_loop(mut self, loop_files: &[LayerFile], first_frames: usize) -> Self {
+        self.files = loop_files.to_vec();
+        self.first_frames = first_frames;
+        self
+    }
+
+    /// Frame at which the repetition `pass` starts
+    fn pass_start(&self, pass: usize) -> usize {
+        match pass {
+            0 => 0,
+            pass => self.first_frames + (pass - 1) * self.pass_frames,
+        }
+    }
+
+    pub fn render(&mut self, left: &mut [f32], right: &mut [f32]) -> Result<(), RenderError> {
+        let mut done = 0;
+        while done < left.len() {
+            if self.started < self.passes && self.position == self.pass_start(self.started) {
+                let mut settings = SynthesizerSettings::new(self.sample_rate);
+                settings.maximum_polyphony = self.maximum_polyphony;
+                settings.enable_reverb_and_chorus = self.reverb_and_chorus;
+                let next = LayeredSequencer::start(&self.files, &settings, false)?;
+                self.fading = Some(std::mem::replace(&mut self.current, next));
+                self.started += 1;
+            }
+            // Render up to the next point where a repetition starts or a crossfade ends
+            let mut frames = left.len() - done;
+            if self.started < self.passes {
+                frames = frames.min(self.pass_start(self.started) - self.position);
+            }
+            let fade_start = self.pass_start(self.started - 1);
+            if self.fading.is_some() {
+                frames = frames.min(fade_start + self.crossfade - self.position);
+            }
+            let (left, right) = (&mut left[done..done + frames], &mut right[done..done + frames]);
+            self.current.render(left, right);
+            if let Some(fading) = self.fading.as_mut() {
+                self.left.resize(frames, 0.0);
+                self.right.resize(frames, 0.0);
+                fading.render(&mut self.left, &mut self.right);
+                for i in 0..frames {
+                    let t = (self.position + i - fade_start) as f32 / self.crossfade as f32 * std::f32::consts::FRAC_PI_2;
+                    let (fade_in, fade_out) = (t.sin(), t.cos());
+                    left[i] = left[i] * fade_in + self.left[i] * fade_out;
+                    right[i] = right[i] * fade_in + self.right[i] * fade_out;
+                }
+                if self.position + frames == fade_start + self.crossfade {
+                    self.fading = None;
+                }
+            }
+            self.position += frames;
+            done += frames;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Renders MIDI files through an SF2 soundfont using a modified version of `rustysynth` with no sample interpolation
+//! and custom bitcrushing to emulate the audio systems of the Nintendo DS.
+//!
+//! The command-line tool is a thin wrapper over this library; [`Renderer`] is the main entry point.
+
+use std::{sync::Arc, path::Path, io::{Write, Seek}};
+use rustysynth::{SoundFont, MidiFile};
+
+mod adpcm;
+mod config;
+mod dither;
+mod dls;
+mod dsp;
+mod error;
+mod fade;
+mod image;
+mod inspect;
+mod interleave;
+mod layer;
+mod load;
+mod manifest;
+mod midi;
+mod mix;
+mod normalize;
+mod output;
+mod play;
+mod quantize;
+mod render;
+mod resample;
+mod riff;
+mod samples;
+mod sf3;
+mod silence;
+mod stats;
+#[cfg(test)]
+mod testing;
+mod transform;
+
+pub use adpcm::{AdpcmEncoder, AdpcmFileWriter, encode_nds_adpcm, ADPCM_STEP_TABLE, ADPCM_INDEX_TABLE};
+pub use config::{RenderConfig, DEFAULT_TAIL, DEFAULT_REVERB_TAIL, DitherMode, QuantizationMode, RoundingMode, OverflowMode, MonoMode};
+pub use dither::{DitherRng, DEFAULT_SEED};
+pub use dls::{is_dls, convert_dls};
+pub use dsp::{Biquad, BiquadChain, EqBand, EqShape, SampleHold, StereoFilter, butterworth_lowpass, nds_dac_filter, soft_clip, stereo_width, MAX_STEREO_WIDTH, NDS_DAC_LOWPASS_HZ, NDS_DAC_HIGHPASS_HZ};
+pub use error::RenderError;
+pub use fade::FadeCurve;
+pub use image::{write_waveform_png, write_spectrogram_png, FftWindow, WAVEFORM_WIDTH, WAVEFORM_HEIGHT, SPECTROGRAM_WIDTH, SPECTROGRAM_HEIGHT};
+pub use inspect::{SoundFontSummary, PresetSummary, MidiSummary, TempoChange, ChannelSummary};
+pub use interleave::{to_interleaved, from_interleaved, interleave, deinterleave};
+pub use layer::{SoundFontLayer, LayerFile};
+pub use load::{load_sound_font, load_sound_font_from_bytes, load_midi_file, load_midi_file_with_events, load_midi_file_from_bytes, load_embedded_dls, load_wav};
+pub use manifest::{ManifestEntry, write_manifest};
+pub use midi::{Smf, TrackEvent, EventKind, TempoMap, META_TEMPO, META_MARKER, META_END_OF_TRACK, DRUM_CHANNEL, LOOP_START_MARKER, LOOP_END_MARKER, LOOP_START_CONTROLLER, is_marker_named};
+pub use mix::downmix_mono;
+pub use normalize::{NormalizeMode, db_to_gain};
+pub use output::{SampleFormat, Codec, Endianness, AudioWriter, WavFileWriter, FlacFileWriter, OggFileWriter, RawFileWriter, WavStreamWriter, write_wav, write_wav_to, write_audio, write_atomically, to_signed_int, to_unsigned_u8, raw_bits_per_sample};
+pub use play::play_file;
+pub use quantize::{quantize_to_bitdepth, n_half_for_bitdepth, quantize_with_mode, quantize_f32, quantize_f32_mid_rise, quantize_f32_asymmetric, quantize_to_output_bits, Quantizer, QuantizeExt, QuantizeToBitdepth, NOISE_SHAPING_COEFFICIENTS};
+pub use render::{render, render_from_bytes, render_with_progress, render_layered_with_progress, render_concat_with_progress, ConcatJoin, render_note, render_file_streaming_with_progress, render_wav_to_writer, render_layered_wav_to_writer, render_to_stream, render_to_buffers, render_to_interleaved, render_streaming, render_streaming_interleaved, render_streaming_with_progress, render_layered_streaming_with_progress, DEFAULT_CHUNK_SIZE};
+pub use resample::{resample, resample_zoh, ResampleMode, ChannelResampler, StereoResampler, StereoDecimator};
+pub use riff::{append_chunk, smpl_chunk, smpl_chunk_with_unity_note, unwrap_rmid};
+pub use samples::dump_samples;
+pub use sf3::{is_sf3, decode_sf3};
+pub use silence::SilenceStop;
+pub use stats::RenderStats;
+pub use transform::{transform_midi, BankMode, VelocityCurve, VoiceStealing};
+
+/// A loaded soundfont together with the settings used to render MIDI files through it
+pub struct Renderer {
+    sound_font: Arc<SoundFont>,
+    layers: Vec<SoundFontLayer>,
+    config: RenderConfig,
+}
+
+impl Renderer {
+    pub fn new(sound_font: Arc<SoundFont>, config: RenderConfig) -> Self {
+        Renderer { sound_font, layers: Vec::new(), config }
+    }
+
+    /// Plays the channels of each of `layers` through its own soundfont when rendering files, see [`SoundFontLayer`]
+    pub fn with_layers(mut self, layers: Vec<SoundFontLayer>) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn sound_font(&self) -> &Arc<SoundFont> {
+        &self.sound_font
+    }
+
+    pub fn layers(&self) -> &[SoundFontLayer] {
+        &self.layers
+    }
+
+    pub fn config(&self) -> &RenderConfig {
+        &self.config
+    }
+
+    /// Renders the MIDI file at `input_file_path` into an audio file at `output_file_path`
+    pub fn render_file<P: AsRef<Path>>(&self, input_file_path: P, output_file_path: P) -> Result<RenderStats, RenderError> {
+        self.render_file_with_progress(input_file_path, output_file_path, |_| {})
+    }
+
+    /// Same as [`Renderer::render_file`], but reports the fraction of the file rendered so far to `progress`
+    pub fn render_file_with_progress<P, G>(&self, input_file_path: P, output_file_path: P, progress: G) -> Result<RenderStats, RenderError>
+    where
+        P: AsRef<Path>,
+        G: FnMut(f32),
+    {
+        render_layered_with_progress(self.sound_font.clone(), &self.layers, input_file_path, output_file_path, &self.config, progress)
+    }
+
+    /// Renders the MIDI files at `input_file_paths` one after the other into a single audio file, see [`render_concat_with_progress`]
+    pub fn render_concat_with_progress<P, Q, G>(&self, input_file_paths: &[P], output_file_path: Q, join: ConcatJoin, progress: G) -> Result<Vec<RenderStats>, RenderError>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        G: FnMut(f32),
+    {
+        render_concat_with_progress(self.sound_font.clone(), &self.layers, input_file_paths, output_file_path, &self.config, join, progress)
+    }
+
+    /// Plays the MIDI file at `input_file_path` on the default output device, see [`play_file`]
+    pub fn play_file<P, G>(&self, input_file_path: P, looping: bool, progress: G) -> Result<RenderStats, RenderError>
+    where
+        P: AsRef<Path>,
+        G: FnMut(f32),
+    {
+        play_file(&self.sound_font, &self.layers, input_file_path, &self.config, looping, progress)
+    }
+
+    /// Renders the MIDI file at `input_file_path` in blocks, see [`render_file_streaming_with_progress`]
+    pub fn render_file_streaming_with_progress<P, F, G>(&self, input_file_path: P, callback: F, progress: G) -> Result<RenderStats, RenderError>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&[f32], &[f32]),
+        G: FnMut(f32),
+    {
+        render_file_streaming_with_progress(&self.sound_font, &self.layers, input_file_path, &self.config, DEFAULT_CHUNK_SIZE, callback, progress)
+    }
+
+    /// Renders the MIDI file at `input_file_path` as a wave-file into `sink`, see [`render_wav_to_writer`]
+    pub fn render_wav_to<P, W, G>(&self, input_file_path: P, sink: W, progress: G) -> Result<RenderStats, RenderError>
+    where
+        P: AsRef<Path>,
+        W: Write + Seek,
+        G: FnMut(f32),
+    {
+        render_layered_wav_to_writer(self.sound_font.clone(), &self.layers, input_file_path, sink, &self.config, progress)
+    }
+
+    /// Renders the MIDI file at `input_file_path` into `sink` as it goes, see [`render_to_stream`]
+    pub fn render_to_stream<P, W, G>(&self, input_file_path: P, sink: W, progress: G) -> Result<RenderStats, RenderError>
+    where
+        P: AsRef<Path>,
+        W: Write,
+        G: FnMut(f32),
+    {
+        render_to_stream(self.sound_font.clone(), &self.layers, input_file_path, sink, &self.config, progress)
+    }
+
+    /// Renders `midi_file` into in-memory left and right channels
+    pub fn render_buffers(&self, midi_file: &Arc<MidiFile>) -> Result<(Vec<f32>, Vec<f32>), RenderError> {
+        render_to_buffers(&self.sound_font, midi_file, &self.config)
+    }
+
+    /// Renders `midi_file` into a single in-memory buffer of interleaved frames, see [`render_to_interleaved`]
+    pub fn render_interleaved(&self, midi_file: &Arc<MidiFile>) -> Result<Vec<f32>, RenderError> {
+        render_to_interleaved(&self.sound_font, midi_file, &self.config)
+    }
+
+    /// Renders `midi_file` in blocks of at most `chunk_size` frames, see [`render_streaming`]
+    pub fn render_streaming<F>(&self, midi_file: &Arc<MidiFile>, chunk_size: usize, callback: F) -> Result<RenderStats, RenderError>
+    where
+        F: FnMut(&[f32], &[f32]),
+    {
+        render_streaming(&self.sound_font, midi_file, &self.config, chunk_size, callback)
+    }
+
+    /// Renders `midi_file` in blocks of at most `chunk_size` interleaved frames, see [`render_streaming_interleaved`]
+    pub fn render_streaming_interleaved<F>(&self, midi_file: &Arc<MidiFile>, chunk_size: usize, callback: F) -> Result<RenderStats, RenderError>
+    where
+        F: FnMut(&[f32]),
+    {
+        render_streaming_interleaved(&self.sound_font, midi_file, &self.config, chunk_size, callback)
+    }
+}
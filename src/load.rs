@@ -0,0 +1,86 @@
+use std::{io::Cursor, sync::Arc, path::Path};
+use rustysynth::{SoundFont, MidiFile};
+
+use crate::dls::{is_dls, convert_dls};
+use crate::error::RenderError;
+use crate::midi::Smf;
+use crate::riff::unwrap_rmid;
+use crate::sf3::{is_sf3, decode_sf3};
+
+/// Loads the `.sf2`, `.sf3` or `.dls` soundfont at `path`
+/// 
+/// A `.dls` extension in any case selects the DLS loader, see [`convert_dls`].
+pub fn load_sound_font<P: AsRef<Path>>(path: P) -> Result<Arc<SoundFont>, RenderError> {
+    let is_dls_file = path.as_ref().extension().is_some_and(|extension| extension.eq_ignore_ascii_case("dls"));
+    let bytes = std::fs::read(path)?;
+    if is_dls_file {
+        return Ok(Arc::new(SoundFont::new(&mut Cursor::new(convert_dls(&bytes)?))?));
+    }
+    load_sound_font_from_bytes(&bytes)
+}
+
+/// Loads a soundfont from the bytes of an `.sf2`, `.sf3` or `.dls` file, for when it does not come from the filesystem
+/// 
+/// The compressed samples of an SF3 soundfont are decoded up front, see [`decode_sf3`], and DLS banks are recognized by their RIFF form type
+/// and converted, see [`convert_dls`].
+pub fn load_sound_font_from_bytes(bytes: &[u8]) -> Result<Arc<SoundFont>, RenderError> {
+    if is_dls(bytes) {
+        return Ok(Arc::new(SoundFont::new(&mut Cursor::new(convert_dls(bytes)?))?));
+    }
+    if is_sf3(bytes) {
+        return Ok(Arc::new(SoundFont::new(&mut Cursor::new(decode_sf3(bytes)?))?));
+    }
+    Ok(Arc::new(SoundFont::new(&mut Cursor::new(bytes))?))
+}
+
+/// Loads and parses the MIDI file at `path`, which may also be wrapped in an RMID file
+/// 
+/// Files with SMPTE-based timing or without an initial tempo are rewritten first, see [`Smf::make_timing_explicit`].
+pub fn load_midi_file<P: AsRef<Path>>(path: P) -> Result<Arc<MidiFile>, RenderError> {
+    Ok(load_midi_file_with_events(path)?.0)
+}
+
+/// Loads the MIDI file at `path`, both parsed for playback and as its raw events
+pub fn load_midi_file_with_events<P: AsRef<Path>>(path: P) -> Result<(Arc<MidiFile>, Smf), RenderError> {
+    load_midi_file_from_bytes(&std::fs::read(path)?)
+}
+
+/// Loads a MIDI file from its bytes, both parsed for playback and as its raw events
+/// 
+/// An RMID file is unwrapped to the Standard MIDI File inside it; see [`load_embedded_dls`] for the bank it may come with.
+/// The timing is made explicit for playback with [`Smf::make_timing_explicit`], so that the length and playback of SMPTE-timed files come out right,
+/// while the raw events are returned as they are in the file.
+pub fn load_midi_file_from_bytes(bytes: &[u8]) -> Result<(Arc<MidiFile>, Smf), RenderError> {
+    let bytes = unwrap_rmid(bytes).map_or(bytes, |(smf, _)| smf);
+    let smf = Smf::parse(bytes)?;
+    let midi_file = if smf.needs_explicit_timing() { smf.to_midi_file()? } else { Arc::new(MidiFile::new(&mut Cursor::new(bytes))?) };
+    Ok((midi_file, smf))
+}
+
+/// Loads the DLS bank embedded in the RMID file at `path`, or `None` if it is not an RMID file or does not embed one
+pub fn load_embedded_dls<P: AsRef<Path>>(path: P) -> Result<Option<Arc<SoundFont>>, RenderError> {
+    let bytes = std::fs::read(path)?;
+    match unwrap_rmid(&bytes) {
+        Some((_, Some(dls))) => Ok(Some(Arc::new(SoundFont::new(&mut Cursor::new(convert_dls(&dls)?))?))),
+        _ => Ok(None),
+    }
+}
+
+/// Loads the wave-file at `path` as left and right channels in the range [-1.0, 1.0], along with its format
+/// 
+/// A mono file comes out with the same samples in both channels, and only the first two channels of anything wider are kept.
+pub fn load_wav<P: AsRef<Path>>(path: P) -> Result<(Vec<f32>, Vec<f32>, hound::WavSpec), RenderError> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|sample| sample.map(|x| x as f32 / scale)).collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let left = samples.iter().step_by(channels).copied().collect();
+    let right = samples.iter().skip(channels.min(2) - 1).step_by(channels).copied().collect();
+    Ok((left, right, spec))
+}
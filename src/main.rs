@@ -1,166 +1,1591 @@
-use std::{fs::File, sync::Arc, path::{self, Path}, ffi::OsStr, error::Error, process::ExitCode, f32::consts::E};
-use rustysynth::{SoundFont, SynthesizerSettings, Synthesizer, MidiFileSequencer, MidiFile};
-use hound;
+use std::{path::Path, io::{Write, BufWriter}, sync::{Arc, Mutex}};
+use std::ffi::{OsStr, OsString};
 use std::path::PathBuf;
-use clap::{Parser};
+use std::process::ExitCode;
+use clap::{Parser, Subcommand, Args, CommandFactory, FromArgMatches};
+use log::{Level, LevelFilter};
+use rayon::prelude::*;
 use glob::glob;
+use rustysynth::SoundFont;
+use nds_sound_render::{Renderer, RenderConfig, DEFAULT_TAIL, DEFAULT_REVERB_TAIL, DEFAULT_SEED, DRUM_CHANNEL, RenderError, RenderStats, DitherMode, QuantizationMode, RoundingMode, OverflowMode, MonoMode, NormalizeMode, FadeCurve, SilenceStop, ResampleMode, EqBand, SampleFormat, Endianness, Codec, load_sound_font, load_midi_file_with_events, load_embedded_dls, load_wav, write_audio, dump_samples, render_note, ConcatJoin, SoundFontSummary, PresetSummary, MidiSummary, BankMode, VelocityCurve, VoiceStealing, SoundFontLayer, FftWindow, ManifestEntry, write_manifest};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Sets the path to the `.sf2` Soundfont file
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Rendering is the default when no command is given
+    #[command(flatten)]
+    render: RenderArgs,
+
+    /// Print nothing but errors, for scripts and CI
+    #[arg(short = 'q', long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print the details of every render, such as the events muted and the gain applied
+    /// 
+    /// `RUST_LOG` filters the messages further, e.g. `RUST_LOG=nds_sound_render=debug` is the same.
+    #[arg(short = 'v', long, global = true)]
+    verbose: bool,
+
+    /// Take the defaults of the options from this TOML file instead of `nds_sound_render.toml` in the current folder
+    /// 
+    /// Keys are the names of options as on the command line, e.g. `bitdepth = 8`, `output-rate = 48000` or `mute = [10]`,
+    /// and options given on the command line override them.
+    #[arg(long, value_name = "FILE", global = true)]
+    #[allow(dead_code)] // Read before parsing, see `config_file_path`
+    config: Option<PathBuf>,
+}
+
+/// Config file that is read from the current folder when there is no `--config`
+const CONFIG_FILE_NAME: &str = "nds_sound_render.toml";
+
+/// The config file that the command-line arguments `args` ask for, or the one in the current folder if there is one
+fn config_file_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    let path = PathBuf::from(CONFIG_FILE_NAME);
+    path.is_file().then_some(path)
+}
+
+/// Makes the options set by the TOML config file at `path` the defaults of `command` and of its commands that render
+fn apply_config_file(mut command: clap::Command, path: &Path) -> Result<clap::Command, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read the config file {}: {}", path.display(), e))?;
+    let table: toml::Table = text.parse().map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+    for (key, value) in table {
+        let to_string = |value: toml::Value| match value {
+            toml::Value::String(s) => Ok(s),
+            toml::Value::Integer(n) => Ok(n.to_string()),
+            toml::Value::Float(x) => Ok(x.to_string()),
+            toml::Value::Boolean(b) => Ok(b.to_string()),
+            value => Err(format!("`{}` in the config file {} cannot be {}", key, path.display(), value.type_str())),
+        };
+        let values = match value {
+            toml::Value::Array(items) => items.into_iter().map(to_string).collect::<Result<Vec<_>, _>>()?,
+            value => vec![to_string(value)?],
+        };
+        // Only options, the soundfont and the inputs are always given on the command line
+        let long = key.replace('_', "-");
+        let Some(id) = command.get_arguments().find(|arg| arg.get_long() == Some(long.as_str())).map(|arg| arg.get_id().to_string()) else {
+            return Err(format!("unknown option `{}` in the config file {}", key, path.display()).into());
+        };
+        command = command.mut_arg(&id, |arg| arg.default_values(values.clone()));
+        for name in ["render", "play"] {
+            command = command.mut_subcommand(name, |subcommand| {
+                // Global options are only copied into the commands once they are built
+                if subcommand.get_arguments().any(|arg| arg.get_id() == id.as_str()) {
+                    subcommand.mut_arg(&id, |arg| arg.default_values(values.clone()))
+                } else {
+                    subcommand
+                }
+            });
+        }
+    }
+    Ok(command)
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Render MIDI files through a soundfont (the default command)
+    Render(RenderArgs),
+    /// Play MIDI files through a soundfont on the default audio device, with the same options as rendering
+    Play(PlayArgs),
+    /// Print information about a soundfont or a MIDI file
+    Inspect(InspectArgs),
+    /// Convert files between formats
+    #[command(subcommand)]
+    Convert(ConvertCommand),
+}
+
+#[derive(Args)]
+struct PlayArgs {
+    /// Keep playing each file from the start until interrupted
+    #[arg(long = "loop")]
+    looping: bool,
+
+    #[command(flatten)]
+    render: RenderArgs,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// Soundfont (`.sf2`) to list the presets of, or MIDI file (`.mid`, `.midi`) to describe
+    #[arg(value_name = "FILE")]
+    file: PathBuf,
+
+    /// Print the information as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+
+    /// MIDI channel that the file puts its drums on, from 1 to 16 (10 by General MIDI), as for rendering
+    #[arg(long, value_name = "CHANNEL", default_value_t = DRUM_CHANNEL, value_parser = clap::value_parser!(u8).range(1..=16))]
+    drum_channel: u8,
+}
+
+#[derive(Subcommand)]
+enum ConvertCommand {
+    /// Re-encode a wave-file with another sample format or codec, without any further processing
+    Audio(ConvertAudioArgs),
+    /// Extract every sample of a soundfont as a wave-file at its own sample rate, named after the sample
+    DumpSamples(DumpSamplesArgs),
+    /// Render single notes of a soundfont preset through the NDS pipeline into one-shot wave-files, e.g. for sample packs
+    InstrumentWavs(InstrumentWavsArgs),
+}
+
+#[derive(Args)]
+struct InstrumentWavsArgs {
+    /// Soundfont to play the preset of
+    #[arg(value_name = "SF2")]
+    sf2: PathBuf,
+
+    /// Program of the preset to play
+    #[arg(short = 'p', long, value_parser = clap::value_parser!(u8).range(0..=127))]
+    program: u8,
+
+    /// Bank of the preset to play, 128 for drum kits
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=128))]
+    bank: u8,
+
+    /// Note to play, or a range of notes like `48-72` to render one file each
+    #[arg(short = 'n', long, value_name = "NOTES", default_value = "60", value_parser = parse_note_range)]
+    notes: (u8, u8),
+
+    /// Velocity of the notes
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(1..=127))]
+    velocity: u8,
+
+    /// Seconds to hold each note for before releasing it
+    #[arg(long, value_name = "SECONDS", default_value_t = 1.0)]
+    length: f64,
+
+    /// Seconds to keep rendering after the release
+    #[arg(long, value_name = "SECONDS", default_value_t = 1.0)]
+    tail: f64,
+
+    /// Target bit-depth for bit reduction, from 1 to 32 (set to 0 to disable)
+    #[arg(short = 'b', long, default_value_t = 10)]
+    bitdepth: u8,
+
+    /// Internal sample rate to synthesize at
+    #[arg(short = 's', long, default_value_t = 32729)]
+    nds_rate: u32,
+
+    /// Folder to write the wave-files into, named like `Piano.C4.wav` (defaults to the current folder)
+    #[arg(short = 'o', long, value_name = "OUT_DIR")]
+    output_folder: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct DumpSamplesArgs {
+    /// Soundfont to extract the samples of
     #[arg(value_name = "SF2")]
     sf2: PathBuf,
 
-    /// Sets the path of the MIDI-file to be rendered
+    /// Folder to write the wave-files into, which is created if needed
+    #[arg(value_name = "OUT_DIR")]
+    output_folder: PathBuf,
+}
+
+#[derive(Args)]
+struct ConvertAudioArgs {
+    /// Wave-file to convert
     #[arg(value_name = "INPUT")]
-    input_glob: String,
+    input: PathBuf,
+
+    /// Path of the converted file
+    #[arg(value_name = "OUTPUT")]
+    output: PathBuf,
+
+    /// Sample format of the converted file: f32, u8, s16, s24 or raw
+    #[arg(short = 'f', long, value_name = "FORMAT", default_value_t = SampleFormat::Int16)]
+    format: SampleFormat,
+
+    /// Byte order of `raw` samples: little or big
+    #[arg(long, value_name = "ORDER", default_value_t = Endianness::Little)]
+    endian: Endianness,
+
+    /// Codec of the converted file: wav, flac, ogg or adpcm
+    #[arg(short = 'c', long, value_name = "CODEC", default_value_t = Codec::Wav)]
+    codec: Codec,
 
-    /// Sets the folder to output rendered wave-files in
+    /// Quality of Ogg Vorbis output, from -2 (smallest) to 10 (best)
+    #[arg(long, value_name = "QUALITY", default_value_t = 6.0, allow_negative_numbers = true)]
+    vorbis_quality: f32,
+}
+
+#[derive(Args)]
+struct RenderArgs {
+    /// Sets the path to the `.sf2` Soundfont file (`.sf3` and `.dls` work as well), or a glob pattern matching several to render every input with each of them
+    /// 
+    /// With more than one soundfont, outputs are named after the soundfont as well, e.g. `song.MySoundfont.wav`.
+    #[arg(value_name = "SF2", required = true)]
+    sf2: Option<OsString>,
+
+    /// Another soundfont to render every input with, see `SF2`; can be given multiple times
+    #[arg(long = "sf2", value_name = "SF2")]
+    extra_sf2: Vec<PathBuf>,
+
+    /// Sets the path of the MIDI-file to be rendered, or a glob pattern matching several
+    /// 
+    /// The path of an existing file is taken as it is, so names with characters like `[` need no escaping.
+    #[arg(value_name = "INPUT", required = true)]
+    input_glob: Option<OsString>,
+
+    /// Number of files to render at the same time (defaults to one per CPU core)
+    #[arg(short = 'j', long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Sets the folder to output rendered wave-files in, which is created if needed (`-` writes to stdout, see `--stdout`)
     #[arg(short = 'o', long, value_name = "OUTPUT")]
     output_folder: Option<PathBuf>,
 
-    /// Target bit-depth for bit reduction (set to 0 to disable)
+    /// Mirror the folder structure of the inputs under the output folder
+    /// 
+    /// Output paths are taken relative to the deepest folder containing every matched file, so that with a recursive pattern
+    /// like `"music/**/*.mid"` same-named files in different subfolders do not overwrite each other.
+    #[arg(long)]
+    mirror: bool,
+
+    /// Skip files whose output already exists, e.g. to resume an interrupted batch
+    #[arg(long, overrides_with = "force")]
+    skip_existing: bool,
+
+    /// Re-render and overwrite existing outputs (the default), undoing an earlier `--skip-existing`
+    #[arg(long, overrides_with = "skip_existing")]
+    force: bool,
+
+    /// Print where every input would be rendered to, as `INPUT -> OUTPUT` lines, without loading the soundfonts or writing anything
+    /// 
+    /// Checks the pattern, `--mirror` and `--name-template` cheaply before a big batch. Stems are not named after presets.
+    #[arg(long, conflicts_with = "print_hash")]
+    dry_run: bool,
+
+    /// Write a single render as a wave-file to stdout instead of creating files, for piping into other tools
+    /// 
+    /// Only one input file may match, and progress messages go to stderr instead. `--format raw` writes the bare samples,
+    /// and the other codecs cannot be streamed.
+    #[arg(long)]
+    stdout: bool,
+
+    /// Target bit-depth for bit reduction, from 1 to 32 (set to 0 to disable)
     /// 
     /// NDS supports 16-bit audio, but in reality it seems that the internal processing could end up reducing the output bit-depth to 10-bits.
     /// Source: https://www.reddit.com/r/emulation/comments/ru5nld/i_really_love_the_sound_of_the_nintendo_ds/
-    #[arg(short = 'b', long, default_value_t = 10)]
+    /// This is the internal depth, hence `--internal-bits`; `--output-bits` sets the depth it is stored at.
+    #[arg(short = 'b', long, visible_alias = "internal-bits", default_value_t = 10)]
     bitdepth: u8,
 
-    /// Target sample rate for zero-interpolation resampling
+    /// Bit depth to quantize the output to once more after bit reduction and resampling, e.g. 16
+    /// 
+    /// The file then carries the coarse internal resolution of `--bitdepth` stored at this depth, whatever `--format` it is written in.
+    #[arg(long, value_name = "BITS")]
+    output_bits: Option<u8>,
+
+    /// Hold every output sample for this many frames, a lo-fi stepping that keeps the sample rate of the file
+    #[arg(long, value_name = "DIVISOR")]
+    crush_rate: Option<u32>,
+
+    /// Internal sample rate to synthesize at, which is the target for zero-interpolation resampling
     /// 
     /// The Nintendo DS's audio systems do not do any interpolation on resampling of audio samples, which means sound coming out of the NDS tend to contain a lot more high-frequency content, a sort of a ringing effect that is awesome, and so to recreate it the audio can be resampled the same way here inside the patched `rustysynth` SF2 player.
     /// Sources indicate different sample rates, but here the one suggested by Wenting Zhang, 32728.5 Hz, is used. https://www.zephray.me/post/nds_3ds_sound_quality/
     /// There is also 32768 Hz, suggested by Justme from https://retrocomputing.stackexchange.com/questions/24952/is-sound-generation-on-the-nintendo-ds-always-clipped-to-10-bits
-    #[arg(short = 's', long, default_value_t = 32729)]
-    sample_rate: u32,
+    #[arg(short = 's', long, alias = "sample-rate", default_value_t = 32729)]
+    nds_rate: u32,
+
+    /// Synthesize at this multiple of the NDS rate and decimate back down, keeping the synthesizer's own aliasing out of the render
+    /// 
+    /// The NDS character then comes only from the stages after synthesis. Rendering takes about this many times longer.
+    #[arg(long, value_name = "FACTOR", default_value_t = 1)]
+    oversample: u32,
 
-    /// How many times to repeat the midi files
+    /// Sample rate of the written files (defaults to the NDS rate)
+    /// 
+    /// Rendering at the NDS rate and resampling to e.g. 44100 or 48000 Hz afterwards keeps the authentic aliasing while producing files at a common distribution rate.
+    #[arg(long, value_name = "RATE")]
+    output_rate: Option<u32>,
+
+    /// How many times to play the midi files in a row, e.g. 1.5 to end halfway through the second time
+    /// 
+    /// Files with a loop, from `loopStart`/`loopEnd` markers, a CC111 or `--loop-region`, play their intro once and repeat only the loop.
     #[arg(short = 'r', long, default_value_t = 1.0)]
-    repeat: f64
+    repeat: f64,
+
+    /// Seconds to keep rendering past the end of the MIDI file, so that sustained final notes can release naturally
+    /// 
+    /// When repeating, the tail follows the last repetition.
+    /// Defaults to 0.5 seconds, or 3 seconds with `--reverb` so that the reverb can ring out.
+    #[arg(long, value_name = "SECONDS")]
+    tail: Option<f64>,
+
+    /// Render until the output has stayed silent for a while instead of for the length of the MIDI file
+    /// 
+    /// Avoids both cut-off release tails and long trailing silence. Silence only counts once something has been heard,
+    /// so rests longer than `--silence-hold` in the middle of a song end it early. Ignores `--repeat` and `--tail`.
+    #[arg(long)]
+    trim_silence: bool,
+
+    /// Remove silence from the start and end of every render
+    /// 
+    /// Useful for sound-effect style files whose sound does not start right away. Works on the bit-reduced signal,
+    /// independently of how the render ends.
+    #[arg(long)]
+    trim: bool,
+
+    /// Level in dBFS below which `--trim-silence` and `--trim` consider the output silent
+    #[arg(long, value_name = "DBFS", default_value_t = SilenceStop::default().threshold_dbfs, allow_negative_numbers = true)]
+    silence_threshold: f32,
+
+    /// How long the output has to stay silent before `--trim-silence` stops, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 1000.0)]
+    silence_hold: f64,
+
+    /// Longest render `--trim-silence` produces in seconds, for patches that sustain forever
+    #[arg(long, value_name = "SECONDS", default_value_t = SilenceStop::default().max_duration)]
+    max_duration: f64,
+
+    /// Normalize the level of every render before bit reduction: none, peak or lufs
+    /// 
+    /// Evens out the wildly different output levels of different soundfonts. The whole file is synthesized twice, once to measure it.
+    /// `lufs` matches the integrated EBU R128 loudness instead of the peak, for consistent volume across an album, but loud peaks can clip.
+    #[arg(long, value_name = "MODE", default_value_t = NormalizeMode::None)]
+    normalize: NormalizeMode,
+
+    /// Level in dBFS that `--normalize peak` brings the largest sample to
+    #[arg(long, value_name = "DBFS", default_value_t = -1.0, allow_negative_numbers = true)]
+    target_peak: f32,
+
+    /// Integrated loudness in LUFS that `--normalize lufs` brings every render to
+    #[arg(long, value_name = "LUFS", default_value_t = -14.0, allow_negative_numbers = true)]
+    target_lufs: f32,
+
+    /// Master gain in dB applied before bit reduction, e.g. `--gain -3` or `--gain 6`
+    /// 
+    /// Applied on top of `--normalize`; samples boosted past full scale are clamped or wrapped according to `--overflow`.
+    #[arg(long, value_name = "DB", default_value_t = 0.0, allow_negative_numbers = true)]
+    gain: f32,
+
+    /// Fade in over the first SECONDS of every render
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0)]
+    fade_in: f64,
+
+    /// Fade out over the last SECONDS of every render, ending in silence at its very last sample
+    /// 
+    /// Lets renders with high repeat counts end gracefully instead of cutting off mid-phrase.
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0)]
+    fade_out: f64,
+
+    /// Shape of the fades: linear or equal-power
+    #[arg(long, value_name = "CURVE", default_value_t = FadeCurve::Linear)]
+    fade_curve: FadeCurve,
+
+    /// Dither to apply before bit reduction: none, tpdf or shaped
+    /// 
+    /// `tpdf` adds ±1 LSB of triangular noise before rounding, which trades the correlated distortion of plain rounding for a low, steady noise floor.
+    /// `shaped` additionally feeds the quantization error back through a high-pass filter so that the noise moves towards less audible high frequencies.
+    #[arg(long, value_name = "MODE", default_value_t = DitherMode::None)]
+    dither_mode: DitherMode,
+
+    /// Dither with `tpdf`, the same as `--dither-mode tpdf`
+    #[arg(long, conflicts_with = "dither_mode")]
+    dither: bool,
+
+    /// Seed of the dither noise (defaults to a fixed one, so renders are reproducible either way)
+    #[arg(long, value_name = "SEED", default_value_t = DEFAULT_SEED)]
+    seed: u64,
+
+    /// Quantizer used for bit reduction: mid-tread or mid-rise
+    /// 
+    /// `mid-tread` has an exact zero level like the integer mixer of the NDS, while `mid-rise` places its levels half a step off zero.
+    #[arg(long, value_name = "MODE", default_value_t = QuantizationMode::MidTread)]
+    quantization: QuantizationMode,
+
+    /// Rounding onto the levels of bit reduction: nearest, half-up, toward-zero or floor
+    /// 
+    /// `nearest` rounds halfway cases away from zero, `half-up` rounds them up, `toward-zero` truncates and `floor` rounds down like a shift right.
+    /// Mid-rise quantization always rounds to the nearest level.
+    #[arg(long, value_name = "MODE", default_value_t = RoundingMode::Nearest)]
+    rounding: RoundingMode,
+
+    /// Reproduce the asymmetric clipping of the NDS
+    /// 
+    /// Quantizes onto the two's complement range of the target bit-depth, e.g. [-512, 511] at 10 bits, rather than the symmetric [-511, 511].
+    #[arg(long)]
+    nds_clip: bool,
+
+    /// What bit reduction does with samples beyond full scale: clamp or wrap
+    /// 
+    /// `wrap` lets samples roll over to the other end of the range like an overflowing integer, which intentionally produces harsh aliasing-style artifacts.
+    #[arg(long, value_name = "MODE", default_value_t = OverflowMode::Clamp)]
+    overflow: OverflowMode,
+
+    /// Soft-clip peaks above this level in dBFS before bit reduction (`--limit` on its own starts at -1 dBFS)
+    /// 
+    /// Peaks bend smoothly towards full scale instead of clipping, so a hot render never reaches `--overflow`.
+    #[arg(long, value_name = "DBFS", num_args = 0..=1, default_missing_value = "-1", allow_negative_numbers = true)]
+    limit: Option<f32>,
+
+    /// Attenuate by this many dB right before bit reduction, to keep peaks from clipping in the quantizer
+    /// 
+    /// Comes after normalization and `--limit`, so a normalized render is written this far below its target.
+    #[arg(long, value_name = "DB", default_value_t = 0.0)]
+    headroom: f32,
+
+    /// Interpolation used when resampling to the output rate: zoh, linear or cubic
+    /// 
+    /// `zoh` keeps the aliasing images of zero-interpolation resampling that give the NDS its sound, while `linear` and `cubic` (Catmull-Rom) attenuate them for a cleaner comparison.
+    #[arg(long, value_name = "MODE", default_value_t = ResampleMode::Zoh)]
+    resample: ResampleMode,
+
+    /// Low-pass the audio before downsampling to the output rate
+    /// 
+    /// Off by default, since the folded-back aliasing is part of the NDS character, but cleans up decimated renders.
+    #[arg(long)]
+    antialias: bool,
+
+    /// Cutoff of the anti-aliasing low-pass as a fraction of the output Nyquist frequency
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.9)]
+    antialias_cutoff: f32,
+
+    /// Downmix to a single channel: average or sum-3db (`--mono` on its own averages)
+    /// 
+    /// `average` can never clip but is 6 dB quieter for sounds panned hard to one side, while `sum-3db` keeps the loudness of wide material
+    /// but can clip on loud centred sounds. Halves the size of the written files.
+    #[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "average")]
+    mono: Option<MonoMode>,
+
+    /// Downmix to mono before bit reduction instead of after it
+    /// 
+    /// Downmixing afterwards (the default) averages two independently bit-reduced channels, which leaves the result between the quantization levels.
+    #[arg(long, requires = "mono")]
+    mono_before_quantization: bool,
+
+    /// Sample format of the written files: f32, u8, s16, s24 or raw
+    /// 
+    /// Since the output is already reduced to ~10 bits, `s16` is the natural container and is half the size of `f32`.
+    /// `u8` is 8-bit unsigned PCM, which together with a low bit-depth target makes for genuinely tiny retro files.
+    /// `raw` writes headerless `.raw` files for hardware flashers and custom players: interleaved frames of a left then a right sample,
+    /// each a two's complement signed integer of the bit-depth rounded up to whole bytes (16 bits when bit reduction is disabled),
+    /// full scale at 2^(bits-1), in the byte order given by `--endian`. A 10-bit render is thus 4 bytes per frame with the lowest 6 bits zero.
+    #[arg(short = 'f', long, value_name = "FORMAT", default_value_t = SampleFormat::Float32)]
+    format: SampleFormat,
+
+    /// Byte order of `raw` samples: little or big
+    #[arg(long, value_name = "ORDER", default_value_t = Endianness::Little)]
+    endian: Endianness,
+
+    /// Codec of the written files: wav, flac, ogg or adpcm
+    /// 
+    /// FLAC is lossless and compresses bit-reduced audio extremely well; it stores integers, so `f32` is written as 16-bit.
+    /// Ogg Vorbis is lossy and adds its own artifacts on top of the intentional ones. It is fed the bit-reduced signal,
+    /// so for the cleanest encode set `--bitdepth 0` to hand it the full-precision render instead.
+    /// ADPCM is the 4-bit IMA-ADPCM of the NDS in a wave-file, or with `--format raw` as headerless NDS streams, the right channel after the left.
+    #[arg(short = 'c', long, value_name = "CODEC", default_value_t = Codec::Wav)]
+    codec: Codec,
+
+    /// Quality of Ogg Vorbis output, from -2 (smallest) to 10 (best)
+    #[arg(long, value_name = "QUALITY", default_value_t = 6.0, allow_negative_numbers = true)]
+    vorbis_quality: f32,
+
+    /// Also draw the waveform of every render as a PNG into this folder, named after the output file, to spot silent, clipped or short renders
+    #[arg(long, value_name = "DIR")]
+    waveform: Option<PathBuf>,
+
+    /// Also draw the spectrogram of every render as a PNG into this folder, e.g. to see the aliasing of the resampling modes
+    #[arg(long, value_name = "DIR")]
+    spectrogram: Option<PathBuf>,
+
+    /// Samples per FFT of `--spectrogram`, a power of two
+    #[arg(long, value_name = "SAMPLES", default_value_t = 2048, requires = "spectrogram")]
+    fft_size: usize,
+
+    /// Taper of each FFT of `--spectrogram`: rectangular, hann, hamming or blackman
+    #[arg(long, value_name = "WINDOW", default_value_t = FftWindow::Hann, requires = "spectrogram")]
+    fft_window: FftWindow,
+
+    /// Leave out the given MIDI channels, as a comma-separated list of numbers from 1 to 16 (10 is the General MIDI drum channel)
+    #[arg(long, value_name = "CHANNELS", value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=16))]
+    mute: Vec<u8>,
+
+    /// Render only the given MIDI channels, as a comma-separated list of numbers from 1 to 16; `--mute` still applies on top
+    #[arg(long, value_name = "CHANNELS", value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=16))]
+    solo: Vec<u8>,
+
+    /// Render every channel that plays notes to a file of its own, e.g. `song.ch03.wav`, instead of the full mix
+    /// 
+    /// `--mute` and `--solo` choose which channels get a stem.
+    #[arg(long, conflicts_with = "stdout")]
+    stems: bool,
+
+    /// Also name stems after the soundfont preset of the first program their channel plays, e.g. `song.ch03.Harp.wav`
+    #[arg(long, requires = "stems")]
+    stem_names: bool,
+
+    /// Hold a MIDI channel at a program whatever the file selects, as CHANNEL:PROGRAM with channels from 1 to 16 and programs from 0 to 127;
+    /// can be given several times
+    /// 
+    /// Handy for auditioning a part on different presets of the soundfont; bank selects of the file still apply.
+    #[arg(long = "program", value_name = "CHANNEL:PROGRAM", value_parser = parse_program_override)]
+    programs: Vec<(u8, u8)>,
+
+    /// MIDI channel that the file puts its drums on, from 1 to 16 (10 by General MIDI)
+    /// 
+    /// The synthesizer only plays drums on channel 10, so the channel trades places with it; other options still use the channels of the file.
+    #[arg(long, value_name = "CHANNEL", default_value_t = DRUM_CHANNEL, value_parser = clap::value_parser!(u8).range(1..=16))]
+    drum_channel: u8,
+
+    /// Play notes of the drum channel as other notes, as a comma-separated list of FROM=TO note numbers, e.g. `35=36,40=38`
+    /// 
+    /// For files written for a different drum map than the soundfont has; by default the notes pick drums by their General MIDI key numbers.
+    #[arg(long, value_name = "MAP", value_delimiter = ',', value_parser = parse_drum_mapping)]
+    drum_map: Vec<(u8, u8)>,
+
+    /// Shift every note by this many semitones, leaving the drum channel untouched
+    /// 
+    /// Notes pushed outside the MIDI range of 0 to 127 are dropped.
+    #[arg(long, value_name = "SEMITONES", default_value_t = 0, allow_negative_numbers = true)]
+    transpose: i32,
+
+    /// Speed up every tempo of the MIDI file by this factor, e.g. 0.5 for half speed; the length of the render follows
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    tempo_scale: f64,
+
+    /// Play the whole MIDI file at this tempo in BPM, replacing its own tempo changes; `--tempo-scale` applies on top
+    #[arg(long, value_name = "BPM")]
+    bpm: Option<f64>,
+
+    /// Start rendering this many seconds into the MIDI file, skipping ahead instead of rendering and discarding the start
+    /// 
+    /// Controllers and program changes before it still apply, but notes that start earlier are not heard.
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0)]
+    start: f64,
+
+    /// Stop rendering this many seconds into the MIDI file; no tail is added after it
+    #[arg(long, value_name = "SECONDS")]
+    end: Option<f64>,
+
+    /// Scale note velocities by this factor, clamping them to 1 to 127
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    velocity_scale: f64,
+
+    /// Remap note velocities before scaling them: linear, exponential (wider dynamics) or logarithmic (narrower dynamics)
+    #[arg(long, value_name = "CURVE", default_value_t = VelocityCurve::Linear)]
+    velocity_curve: VelocityCurve,
+
+    /// How bank selects choose soundfont banks: gm (ignored), gs (CC0 only) or xg (CC32 when CC0 is 0)
+    /// 
+    /// XG drum kits (CC0 127) only play on channel 10, which always plays drums from bank 128.
+    #[arg(long, value_name = "MODE", default_value_t = BankMode::Gs)]
+    bank_mode: BankMode,
+
+    /// Pitch bend range in semitones for every channel, overriding the range the MIDI files set with RPN 0 (2 by default)
+    #[arg(long, value_name = "SEMITONES")]
+    bend_range: Option<f64>,
+
+    /// Ignore the GM or GS master volume SysEx message a MIDI file starts with, which otherwise scales the render
+    #[arg(long)]
+    ignore_master_volume: bool,
+
+    /// Pan like the NDS: its 128 pan steps have linear gains rather than the equal-power law of the synthesizer
+    /// 
+    /// Pan controller (CC10) positions are moved to match the balance the NDS gives them; the pan of soundfont instruments is left as is.
+    #[arg(long)]
+    nds_pan: bool,
+
+    /// Most voices that sound at once, 16 like the NDS hardware; from 8 to 256
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    max_voices: usize,
+
+    /// Which voice is stolen for a new one when `--max-voices` are sounding: quietest or oldest
+    #[arg(long, value_name = "POLICY", default_value_t = VoiceStealing::Quietest)]
+    voice_stealing: VoiceStealing,
+
+    /// Low-pass the render before bit reduction like the output path of the NDS rolls off the treble
+    #[arg(long)]
+    dac_filter: bool,
+
+    /// Also roll off the bass below about 30 Hz like the output path of the NDS does
+    #[arg(long, requires = "dac_filter")]
+    dac_highpass: bool,
+
+    /// Low-pass the render at this cutoff before bit reduction, 12 dB per octave
+    #[arg(long, value_name = "HZ")]
+    lowpass: Option<f64>,
+
+    /// High-pass the render at this cutoff before bit reduction, 12 dB per octave
+    #[arg(long, value_name = "HZ")]
+    highpass: Option<f64>,
+
+    /// Parametric EQ before bit reduction, as a comma-separated list of FREQ:GAIN:Q peaks, e.g. `--eq 80:4:0.7,6000:-3:2`
+    /// 
+    /// A band can also be a shelf, written `lowshelf:FREQ:GAIN:Q` or `highshelf:FREQ:GAIN:Q`. The bands run after `--lowpass` and `--highpass`
+    /// and before quantization, so they shape what gets bit-reduced, e.g. to tame harsh resampling artifacts or boost the bass.
+    #[arg(long, value_name = "BANDS", value_delimiter = ',', allow_negative_numbers = true)]
+    eq: Vec<EqBand>,
+
+    /// Subtract the DC offset of each channel over the whole render before bit reduction
+    #[arg(long)]
+    remove_dc: bool,
+
+    /// Stereo width by mid/side processing before bit reduction: 0 is mono, 1 unchanged and up to 4 wider
+    /// 
+    /// Narrowing helps with soundfonts that pan hard, and 0 is a quick check of how a render holds up in mono.
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    width: f32,
+
+    /// Swap the left and right channel, for soundfonts or MIDI files that pan the wrong way round
+    #[arg(long)]
+    swap_channels: bool,
+
+    /// Apply the reverb and chorus effects of the synthesizer, as set by the MIDI file's effect controllers
+    /// 
+    /// The synthesizer can only turn both on or off together, so this is the same as `--chorus`. The reverb rings out for
+    /// a while, so the default `--tail` gets longer; `--trim-silence` is an alternative that renders exactly until it has faded.
+    #[arg(long)]
+    reverb: bool,
+
+    /// Same as `--reverb`, since the synthesizer couples the two effects
+    #[arg(long)]
+    chorus: bool,
+
+    /// Play some MIDI channels through another soundfont, e.g. `10=drums.sf2` or `1-4,6=strings.sf2`; can be given multiple times
+    /// 
+    /// Every soundfont runs a synthesizer of its own and their outputs are mixed. Channels without a layer play through `SF2`.
+    #[arg(long, value_name = "CHANNELS=SF2", value_parser = parse_layer)]
+    layer: Vec<(Vec<u8>, PathBuf)>,
+
+    /// Render `.rmi` files that embed a DLS bank with that bank in place of `SF2`
+    #[arg(long)]
+    embedded_dls: bool,
+
+    /// Milliseconds to crossfade over between repetitions of `--repeat`
+    /// 
+    /// Every repetition is rendered separately, and the release tail of one fades out while the start of the next fades in,
+    /// which smooths over the seam of files that do not end where they start.
+    #[arg(long, value_name = "MS", default_value_t = 0.0)]
+    loop_crossfade: f64,
+
+    /// Loop of the MIDI files as START:END in seconds, replacing the loop they mark with `loopStart`/`loopEnd` markers or a CC111
+    /// 
+    /// The loop is what `--repeat` repeats and what the `smpl` chunk of a wave-file loops over.
+    #[arg(long, value_name = "START:END", value_parser = parse_loop_region)]
+    loop_region: Option<(f64, f64)>,
+
+    /// Render every matched MIDI file one after the other into this single file instead of a file each, in the order of their paths
+    #[arg(long, value_name = "OUTPUT", conflicts_with_all = ["stdout", "stems"])]
+    concat: Option<PathBuf>,
+
+    /// Seconds of silence between consecutive files of `--concat`
+    #[arg(long, value_name = "SECONDS", requires = "concat", default_value_t = 0.0)]
+    concat_gap: f64,
+
+    /// Seconds over which each file of `--concat` crossfades into the next one, instead of leaving a gap
+    #[arg(long, value_name = "SECONDS", requires = "concat", conflicts_with = "concat_gap")]
+    concat_crossfade: Option<f64>,
+
+    /// Print a hash of the rendered samples of every file instead of writing it, e.g. to compare renders across versions
+    /// 
+    /// Renders are deterministic, dither included, so the hash only changes when the output would.
+    #[arg(long, conflicts_with_all = ["stdout", "concat"])]
+    print_hash: bool,
+
+    /// Keep a record of the batch in this file, rewritten after every render: CSV if it ends in `.csv`, JSON otherwise
+    /// 
+    /// Every render gets its input, output, length, levels and whether it succeeded.
+    #[arg(long, value_name = "FILE", conflicts_with = "stdout")]
+    manifest: Option<PathBuf>,
+
+    /// Name outputs after this template instead of the input, without the extension, e.g. `{stem}.{sf2}.{bits}bit`
+    /// 
+    /// Placeholders are `{stem}` (the name of the input), `{sf2}` (the name of the soundfont), `{bits}` (the bit depth) and `{rate}` (the output sample rate);
+    /// `{{` and `}}` are literal braces.
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_name_template)]
+    name_template: Option<NameTemplate>
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+/// A validated `--name-template`
+#[derive(Clone)]
+struct NameTemplate(String);
+
+impl NameTemplate {
+    /// The file name the template gives the output of the input named `stem` when rendered through the soundfont named `sf2` with `config`
+    fn expand(&self, stem: &str, sf2: &str, config: &RenderConfig) -> String {
+        let mut name = String::new();
+        for_each_template_part(&self.0, |part| {
+            match part {
+                TemplatePart::Text(text) => name.push_str(text),
+                TemplatePart::Placeholder("stem") => name.push_str(stem),
+                TemplatePart::Placeholder("sf2") => name.push_str(sf2),
+                TemplatePart::Placeholder("bits") => name.push_str(&config.bitdepth.to_string()),
+                // `rate`, the only one left
+                TemplatePart::Placeholder(_) => name.push_str(&config.output_sample_rate().to_string()),
+            }
+            Ok(())
+        }).expect("the template was validated when parsed");
+        name
+    }
 
-    let mut sf2 = File::open(cli.sf2)?;
-    let sound_font = Arc::new(SoundFont::new(&mut sf2)?);
+    fn uses(&self, placeholder: &str) -> bool {
+        self.0.contains(&format!("{{{}}}", placeholder))
+    }
+}
 
-    let output_folder;
-    if let Some(custom_output_folder) = cli.output_folder {
-        if std::fs::metadata(&custom_output_folder)?.is_dir() {
-            output_folder = custom_output_folder;
+enum TemplatePart<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits `template` into text and placeholders and hands them to `f` in order, failing on unknown placeholders and stray braces
+fn for_each_template_part<'a, F>(template: &'a str, mut f: F) -> Result<(), String>
+where
+    F: FnMut(TemplatePart<'a>) -> Result<(), String>,
+{
+    let mut rest = template;
+    while let Some(brace) = rest.find(['{', '}']) {
+        f(TemplatePart::Text(&rest[..brace]))?;
+        let after = &rest[brace + 1..];
+        if after.starts_with(&rest[brace..brace + 1]) {
+            // A doubled brace is a literal one
+            f(TemplatePart::Text(&rest[brace..brace + 1]))?;
+            rest = &after[1..];
+        } else if rest[brace..].starts_with('}') {
+            return Err(format!("unmatched `}}` in name template `{}`, write `}}}}` for a literal brace", template));
         } else {
-            return Err("Output path must be a folder!".into());
+            let end = after.find('}').ok_or_else(|| format!("unclosed `{{` in name template `{}`", template))?;
+            let placeholder = &after[..end];
+            if !["stem", "sf2", "bits", "rate"].contains(&placeholder) {
+                return Err(format!("unknown placeholder `{{{}}}` in name template (expected {{stem}}, {{sf2}}, {{bits}} or {{rate}})", placeholder));
+            }
+            f(TemplatePart::Placeholder(placeholder))?;
+            rest = &after[end + 1..];
+        }
+    }
+    f(TemplatePart::Text(rest))
+}
+
+/// Parses a `--name-template` argument, see [`NameTemplate`]
+fn parse_name_template(s: &str) -> Result<NameTemplate, String> {
+    for_each_template_part(s, |_| Ok(()))?;
+    if s.contains(['/', '\\']) {
+        return Err(format!("name template `{}` names a file, so it cannot contain a path separator", s));
+    }
+    Ok(NameTemplate(s.to_string()))
+}
+
+/// The deepest folder that contains all of `paths`
+fn common_root(paths: &[PathBuf]) -> Option<PathBuf> {
+    let mut root = paths.first()?.parent()?.to_path_buf();
+    for path in &paths[1..] {
+        let parent = path.parent()?;
+        while !parent.starts_with(&root) {
+            if !root.pop() {
+                return None;
+            }
         }
+    }
+    Some(root)
+}
+
+/// Creates the folder an output file goes into, for mirrored folder structures
+fn create_parent_folder(output_file_path: &Path) -> Result<(), RenderError> {
+    if let Some(parent) = output_file_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// One-line summary of the levels of a finished render
+fn describe(stats: &RenderStats) -> String {
+    let mut summary = format!("peak {:.1} dBFS, RMS {:.1} dBFS, {} clipped samples", stats.peak_dbfs(), stats.rms_dbfs(), stats.clipped_samples);
+    if stats.gain_db != 0.0 {
+        summary += &format!(", normalized by {:+.1} dB", stats.gain_db);
+    }
+    if stats.trimmed_start + stats.trimmed_end != 0 {
+        summary += &format!(", trimmed {} + {} silent frames", stats.trimmed_start, stats.trimmed_end);
+    }
+    summary
+}
+
+/// Sends the messages of the tool through `log` at the verbosity `cli` asks for, to stderr when stdout carries the audio
+fn init_logging(cli: &Cli) {
+    let render = match &cli.command {
+        Some(Command::Render(args)) => Some(args),
+        Some(Command::Play(args)) => Some(&args.render),
+        Some(_) => None,
+        None => Some(&cli.render),
+    };
+    let to_stdout = render.is_some_and(|args| args.stdout || args.output_folder.as_deref() == Some(Path::new("-")));
+    let mut builder = env_logger::Builder::new();
+    if cli.quiet {
+        builder.filter_level(LevelFilter::Error);
+    } else {
+        builder.filter_level(LevelFilter::Info).parse_default_env();
+        if cli.verbose {
+            builder.filter_module("nds_sound_render", LevelFilter::Debug);
+        }
+    }
+    builder
+        .target(if to_stdout { env_logger::Target::Stderr } else { env_logger::Target::Stdout })
+        // The messages are meant for people, not log files
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .init();
+}
+
+/// Whether progress is shown, which is everything but `--quiet`
+fn show_status() -> bool {
+    log::log_enabled!(Level::Info)
+}
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let mut command = Cli::command();
+    if let Some(path) = config_file_path(&args) {
+        command = apply_config_file(command, &path)?;
+    }
+    let cli = Cli::from_arg_matches(&command.get_matches_from(args)).unwrap_or_else(|e| e.exit());
+    init_logging(&cli);
+
+    match cli.command {
+        Some(Command::Render(args)) => render_command(args),
+        Some(Command::Play(args)) => play_command(args),
+        Some(Command::Inspect(args)) => inspect_command(args),
+        Some(Command::Convert(ConvertCommand::Audio(args))) => convert_audio_command(args),
+        Some(Command::Convert(ConvertCommand::DumpSamples(args))) => dump_samples_command(args),
+        Some(Command::Convert(ConvertCommand::InstrumentWavs(args))) => instrument_wavs_command(args),
+        None => render_command(cli.render),
+    }
+}
+
+fn play_command(args: PlayArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let cli = args.render;
+    let config = render_config(&cli);
+    config.validate()?;
+    let sf2_glob = cli.sf2.expect("the soundfont is a required argument");
+    let input_glob = cli.input_glob.expect("the input pattern is a required argument");
+    // Only a single soundfont can be heard at a time
+    let sf2 = matching_paths(&sf2_glob)?.into_iter().filter_map(Result::ok).next().unwrap_or_else(|| PathBuf::from(&sf2_glob));
+    let sound_font = load_sound_font(&sf2)?;
+    let mut layers = Vec::new();
+    for (channels, sf2) in cli.layer {
+        layers.push(SoundFontLayer { sound_font: load_sound_font(&sf2)?, channels });
+    }
+
+    let inputs: Vec<PathBuf> = matching_paths(&input_glob)?.into_iter().filter_map(Result::ok).filter(|path| path.is_file() && is_midi_file(path)).collect();
+    if inputs.is_empty() {
+        log::error!("No MIDI files (.mid, .midi or .rmi) matched the pattern `{}`, check it for typos and that it is quoted so the shell does not expand it", input_glob.to_string_lossy());
+        return Ok(ExitCode::FAILURE);
+    }
+    for input in &inputs {
+        let embedded = if cli.embedded_dls { load_embedded_dls(input)? } else { None };
+        let renderer = Renderer::new(embedded.unwrap_or_else(|| sound_font.clone()), config.clone()).with_layers(layers.clone());
+        let display = input.display().to_string();
+        let mut last_percent = None;
+        let stats = renderer.play_file(input, args.looping, |fraction| {
+            let percent = (fraction * 100.0) as u32;
+            if show_status() && last_percent != Some(percent) {
+                last_percent = Some(percent);
+                print!("\rPlaying {}... {:>3}%", display, percent);
+                let _ = std::io::stdout().flush();
+            }
+        })?;
+        log::info!("\rPlaying {}... done! ({})", display, describe(&stats));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn inspect_command(args: InspectArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    if is_midi_file(&args.file) {
+        let (midi_file, smf) = load_midi_file_with_events(&args.file)?;
+        let summary = MidiSummary::new(&midi_file, &smf);
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            return Ok(ExitCode::SUCCESS);
+        }
+        println!("{}: format {} MIDI file with {} tracks, {:.1} seconds long", args.file.display(), summary.format, summary.track_count, summary.duration);
+        if summary.format == 2 {
+            println!("Format 2 files hold independent sequences, which are played back one after another");
+        }
+        match summary.ticks_per_quarter {
+            Some(ticks) => println!("{} ticks per quarter note", ticks),
+            None => println!("SMPTE timing, tempo changes do not apply"),
+        }
+        println!();
+        println!("{:>8}  {:>9}  {:>7}", "Tick", "Time", "BPM");
+        for change in &summary.tempo_changes {
+            println!("{:>8}  {:>8.2}s  {:>7.2}", change.tick, change.seconds, change.bpm);
+        }
+        println!();
+        println!("{:>7}  {:>5}  Programs", "Channel", "Notes");
+        for channel in &summary.channels {
+            let programs: Vec<String> = channel.programs.iter().map(|program| program.to_string()).collect();
+            let drums = if channel.channel == args.drum_channel { " (drums)" } else { "" };
+            println!("{:>7}  {:>5}  {}{}", channel.channel, channel.note_count, programs.join(", "), drums);
+        }
+    } else {
+        let summary = SoundFontSummary::new(&*load_sound_font(&args.file)?);
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            return Ok(ExitCode::SUCCESS);
+        }
+        println!("{}: soundfont \"{}\" with {} presets, {} instruments and {} samples", args.file.display(), summary.name, summary.presets.len(), summary.instrument_count, summary.sample_count);
+        println!();
+        println!("{:>4}  {:>7}  {:>7}  Name", "Bank", "Program", "Samples");
+        for preset in &summary.presets {
+            println!("{:>4}  {:>7}  {:>7}  {}", preset.bank, preset.program, preset.sample_count, preset.name);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn convert_audio_command(args: ConvertAudioArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let (left, right, spec) = load_wav(&args.input)?;
+    let config = RenderConfig {
+        // Raw output keeps the width of the source samples
+        bitdepth: match spec.sample_format {
+            hound::SampleFormat::Int => spec.bits_per_sample.min(32) as u8,
+            hound::SampleFormat::Float => 0,
+        },
+        mono: (spec.channels == 1).then_some(MonoMode::Average),
+        format: args.format,
+        endian: args.endian,
+        codec: args.codec,
+        vorbis_quality: args.vorbis_quality,
+        ..RenderConfig::default()
+    };
+    config.validate()?;
+    write_audio(&args.output, &left, &right, spec.sample_rate, &config)?;
+    log::info!("Converted {} to {}", args.input.display(), args.output.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn dump_samples_command(args: DumpSamplesArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let sound_font = load_sound_font(&args.sf2)?;
+    let paths = dump_samples(&sound_font, &args.output_folder)?;
+    log::info!("Extracted {} samples from {} into {}", paths.len(), args.sf2.display(), args.output_folder.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn instrument_wavs_command(args: InstrumentWavsArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let sound_font = load_sound_font(&args.sf2)?;
+    let presets = SoundFontSummary::new(&sound_font).presets;
+    let Some(preset) = presets.iter().find(|preset| (preset.bank, preset.program) == (args.bank as i32, args.program as i32)) else {
+        log::error!("{} has no preset at bank {} program {}, see `inspect` for the ones it has", args.sf2.display(), args.bank, args.program);
+        return Ok(ExitCode::FAILURE);
+    };
+    let config = RenderConfig { bitdepth: args.bitdepth, nds_rate: args.nds_rate, tail: args.tail, format: SampleFormat::Int16, ..RenderConfig::default() };
+    config.validate()?;
+    let output_folder = match args.output_folder {
+        Some(output_folder) => output_folder,
+        None => std::env::current_dir()?,
+    };
+    std::fs::create_dir_all(&output_folder)?;
+
+    let name: String = preset.name.trim().chars().map(|c| if c.is_alphanumeric() || " -_".contains(c) { c } else { '_' }).collect();
+    let (low, high) = args.notes;
+    for key in low..=high {
+        let (left, right, _) = render_note(&sound_font, args.bank, args.program, key, args.velocity, args.length, &config)?;
+        let output = output_folder.join(format!("{}.{}.wav", name, note_name(key)));
+        write_audio(&output, &left, &right, config.output_sample_rate(), &config)?;
+        log::info!("Rendered {}", output.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Scientific name of the MIDI note `key`, where 60 is `C4`
+fn note_name(key: u8) -> String {
+    const NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    format!("{}{}", NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+/// Parses a `--notes` argument, a single MIDI note or an inclusive range like `48-72`
+fn parse_note_range(s: &str) -> Result<(u8, u8), String> {
+    let parse_note = |note: &str| match note.trim().parse::<u8>() {
+        Ok(note) if note <= 127 => Ok(note),
+        _ => Err(format!("`{}` is not a MIDI note from 0 to 127", note)),
+    };
+    let (low, high) = match s.split_once('-') {
+        Some((low, high)) => (parse_note(low)?, parse_note(high)?),
+        None => (parse_note(s)?, parse_note(s)?),
+    };
+    if low > high {
+        return Err(format!("the range `{}` runs backwards", s));
+    }
+    Ok((low, high))
+}
+
+/// Parses a `--loop-region` argument, a start and an end in seconds separated by a colon, like `12.5:80`
+fn parse_loop_region(s: &str) -> Result<(f64, f64), String> {
+    let (start, end) = s.split_once(':').ok_or_else(|| format!("expected START:END, got `{}`", s))?;
+    let parse_seconds = |seconds: &str| seconds.trim().parse::<f64>().map_err(|_| format!("`{}` is not a number of seconds", seconds));
+    let (start, end) = (parse_seconds(start)?, parse_seconds(end)?);
+    if !(start >= 0.0 && start < end) {
+        return Err(format!("the loop `{}` has to start at or after 0 and end after it starts", s));
+    }
+    Ok((start, end))
+}
+
+/// Parses a `--program` argument, a MIDI channel and the program to hold it at separated by a colon, like `1:40`
+fn parse_program_override(s: &str) -> Result<(u8, u8), String> {
+    let (channel, program) = s.split_once(':').ok_or_else(|| format!("expected CHANNEL:PROGRAM, got `{}`", s))?;
+    let channel = match channel.trim().parse::<u8>() {
+        Ok(channel) if (1..=16).contains(&channel) => channel,
+        _ => return Err(format!("`{}` is not a MIDI channel from 1 to 16", channel)),
+    };
+    let program = match program.trim().parse::<u8>() {
+        Ok(program) if program <= 127 => program,
+        _ => return Err(format!("`{}` is not a MIDI program from 0 to 127", program)),
+    };
+    Ok((channel, program))
+}
+
+/// Parses an item of a `--drum-map` argument, two MIDI notes separated by `=`
+fn parse_drum_mapping(s: &str) -> Result<(u8, u8), String> {
+    let (from, to) = s.split_once('=').ok_or_else(|| format!("expected FROM=TO, got `{}`", s))?;
+    let parse_note = |note: &str| match note.trim().parse::<u8>() {
+        Ok(note) if note <= 127 => Ok(note),
+        _ => Err(format!("`{}` is not a MIDI note from 0 to 127", note)),
+    };
+    Ok((parse_note(from)?, parse_note(to)?))
+}
+
+/// Parses a `--layer` argument, a comma-separated list of channels and channel ranges followed by `=` and the path of a soundfont
+fn parse_layer(s: &str) -> Result<(Vec<u8>, PathBuf), String> {
+    let (list, sf2) = s.split_once('=').ok_or_else(|| format!("expected CHANNELS=SF2, got `{}`", s))?;
+    let parse_channel = |channel: &str| match channel.trim().parse::<u8>() {
+        Ok(channel) if (1..=16).contains(&channel) => Ok(channel),
+        _ => Err(format!("`{}` is not a MIDI channel from 1 to 16", channel)),
+    };
+    let mut channels = Vec::new();
+    for item in list.split(',') {
+        match item.split_once('-') {
+            Some((first, last)) => channels.extend(parse_channel(first)?..=parse_channel(last)?),
+            None => channels.push(parse_channel(item)?),
+        }
+    }
+    Ok((channels, PathBuf::from(sf2)))
+}
+
+/// A single file to render: `input` with the renderer at index `renderer`, optionally as the stem of `channel` only
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+    renderer: usize,
+    channel: Option<u8>,
+}
+
+/// Output paths of the stems of the MIDI file at `input_file_path`, one for every channel that plays notes and is audible under `config`
+/// 
+/// Stems are named after `output_file_path` and their channel, and after the preset of the first program of their channel when it is in `presets`.
+fn stem_outputs(input_file_path: &Path, output_file_path: &Path, config: &RenderConfig, presets: &[PresetSummary]) -> Result<Vec<(PathBuf, Option<u8>)>, RenderError> {
+    let (midi_file, smf) = load_midi_file_with_events(input_file_path)?;
+    let summary = MidiSummary::new(&midi_file, &smf);
+    let stem = output_file_path.file_stem().unwrap_or_default();
+    Ok(summary.channels.iter().filter(|channel| config.is_channel_audible(channel.channel)).map(|channel| {
+        let mut file_name = stem.to_os_string();
+        file_name.push(format!(".ch{:02}", channel.channel));
+        // Soundfonts keep General MIDI drum kits in bank 128
+        let bank = if channel.channel == config.drum_channel { 128 } else { 0 };
+        let preset = channel.programs.first().and_then(|&program| presets.iter().find(|preset| preset.bank == bank && preset.program == program as i32));
+        if let Some(preset) = preset.filter(|preset| !preset.name.trim().is_empty()) {
+            let name: String = preset.name.trim().chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+            file_name.push(".");
+            file_name.push(name);
+        }
+        file_name.push(".");
+        file_name.push(config.output_extension());
+        (output_file_path.with_file_name(file_name), Some(channel.channel))
+    }).collect())
+}
+
+/// Whether `path` has the extension of a MIDI file, `.mid`, `.midi` or `.rmi` in any case
+/// 
+/// The rest of the name can be anything, including bytes that are not UTF-8.
+fn is_midi_file(path: &Path) -> bool {
+    matches!(path.extension(), Some(extension) if ["mid", "midi", "rmi"].iter().any(|midi| extension.eq_ignore_ascii_case(midi)))
+}
+
+/// Output path of the render of `input` into `output_folder`, in the folders it is in below `root` if there is one
+/// 
+/// Only the extension changes, so names that are not UTF-8 or that look like patterns come out as they went in.
+fn output_path_for(input: &Path, output_folder: &Path, root: Option<&Path>, extension: &str) -> Option<PathBuf> {
+    let input_file_name = input.file_name()?;
+    let mut output_path = output_folder.to_path_buf();
+    if let Some(relative) = root.and_then(|root| input.parent()?.strip_prefix(root).ok()) {
+        output_path.push(relative);
+    }
+    output_path.push(input_file_name);
+    output_path.set_extension(extension);
+    Some(output_path)
+}
+
+/// `output_path` named after the soundfont `sound_font_name` as well, e.g. `song.GS.wav` for `song.wav`
+fn with_sound_font_name(output_path: &Path, sound_font_name: &str, extension: &str) -> PathBuf {
+    let mut file_name = output_path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}.{}", sound_font_name, extension));
+    output_path.with_file_name(file_name)
+}
+
+/// Paths matching the glob `pattern`, or just `pattern` when it is the path of an existing file
+/// 
+/// Ripped files often have names with characters like `[` that a pattern takes as wildcards, or that are not even UTF-8,
+/// which patterns cannot be; such files still work when named on their own.
+fn matching_paths(pattern: &OsStr) -> Result<Vec<Result<PathBuf, glob::GlobError>>, glob::PatternError> {
+    let path = Path::new(pattern);
+    if path.is_file() {
+        return Ok(vec![Ok(path.to_path_buf())]);
+    }
+    match pattern.to_str() {
+        Some(pattern) => Ok(glob(pattern)?.collect()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The pipeline that the options of `cli` ask for
+fn render_config(cli: &RenderArgs) -> RenderConfig {
+    let reverb_and_chorus = cli.reverb || cli.chorus;
+    RenderConfig {
+        bitdepth: cli.bitdepth,
+        output_bitdepth: cli.output_bits,
+        crush_rate: cli.crush_rate,
+        nds_rate: cli.nds_rate,
+        oversample: cli.oversample,
+        output_rate: cli.output_rate,
+        resample: cli.resample,
+        antialias: cli.antialias.then_some(cli.antialias_cutoff),
+        repeat: cli.repeat,
+        loop_crossfade: cli.loop_crossfade / 1000.0,
+        loop_region: cli.loop_region,
+        tail: cli.tail.unwrap_or(if reverb_and_chorus { DEFAULT_REVERB_TAIL } else { DEFAULT_TAIL }),
+        stop_on_silence: cli.trim_silence.then_some(SilenceStop {
+            threshold_dbfs: cli.silence_threshold,
+            hold: cli.silence_hold / 1000.0,
+            max_duration: cli.max_duration,
+        }),
+        trim: cli.trim.then_some(cli.silence_threshold),
+        normalize: cli.normalize,
+        target_peak_dbfs: cli.target_peak,
+        target_lufs: cli.target_lufs,
+        gain_db: cli.gain,
+        fade_in: cli.fade_in,
+        fade_out: cli.fade_out,
+        fade_curve: cli.fade_curve,
+        dac_filter: cli.dac_filter,
+        dac_highpass: cli.dac_highpass,
+        lowpass: cli.lowpass,
+        highpass: cli.highpass,
+        eq: cli.eq.clone(),
+        remove_dc: cli.remove_dc,
+        width: cli.width,
+        swap_channels: cli.swap_channels,
+        dither: if cli.dither { DitherMode::Tpdf } else { cli.dither_mode },
+        seed: cli.seed,
+        quantization: cli.quantization,
+        rounding: cli.rounding,
+        nds_clip: cli.nds_clip,
+        overflow: cli.overflow,
+        limit: cli.limit,
+        headroom_db: cli.headroom,
+        mono: cli.mono,
+        mono_before_quantization: cli.mono_before_quantization,
+        format: cli.format,
+        endian: cli.endian,
+        codec: cli.codec,
+        vorbis_quality: cli.vorbis_quality,
+        stream: cli.stdout || cli.output_folder.as_deref() == Some(Path::new("-")),
+        waveform: cli.waveform.clone(),
+        spectrogram: cli.spectrogram.clone(),
+        spectrogram_fft_size: cli.fft_size,
+        spectrogram_window: cli.fft_window,
+        mute: cli.mute.clone(),
+        solo: cli.solo.clone(),
+        programs: cli.programs.clone(),
+        drum_channel: cli.drum_channel,
+        drum_map: cli.drum_map.clone(),
+        transpose: cli.transpose,
+        tempo_scale: cli.tempo_scale,
+        bpm: cli.bpm,
+        start: cli.start,
+        end: cli.end,
+        velocity_scale: cli.velocity_scale,
+        velocity_curve: cli.velocity_curve,
+        bank_mode: cli.bank_mode,
+        bend_range: cli.bend_range,
+        master_volume: !cli.ignore_master_volume,
+        nds_pan: cli.nds_pan,
+        max_voices: cli.max_voices,
+        voice_stealing: cli.voice_stealing,
+        reverb_and_chorus,
+    }
+}
+
+fn render_command(cli: RenderArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let config = render_config(&cli);
+    // Before anything is loaded or created
+    config.validate()?;
+    let sf2_glob = cli.sf2.expect("the soundfont is a required argument");
+    let input_glob = cli.input_glob.expect("the input pattern is a required argument");
+
+    let mut sf2_paths: Vec<PathBuf> = matching_paths(&sf2_glob)?.into_iter().filter_map(Result::ok).collect();
+    sf2_paths.extend(cli.extra_sf2);
+    if sf2_paths.is_empty() {
+        // Not a pattern, so let loading it report what is wrong with the path
+        sf2_paths.push(PathBuf::from(&sf2_glob));
+    }
+    // Every soundfont is only rendered with once, however many times it was given
+    let mut sf2_files: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for sf2 in sf2_paths {
+        let canonical = sf2.canonicalize().unwrap_or_else(|_| sf2.clone());
+        if !sf2_files.iter().any(|(known, _)| *known == canonical) {
+            sf2_files.push((canonical, sf2));
+        }
+    }
+    let sound_font_names: Vec<String> = sf2_files.iter().map(|(_, sf2)| sf2.file_stem().unwrap_or_default().to_string_lossy().into_owned()).collect();
+    // Loading waits until the files to render are known, and `--dry-run` skips it
+    let load_renderers = || -> Result<Vec<Renderer>, RenderError> {
+        // Every soundfont is only loaded once, layers included
+        let mut loaded: Vec<(PathBuf, Arc<SoundFont>)> = Vec::new();
+        let mut load_cached = |sf2: &Path| -> Result<Arc<SoundFont>, RenderError> {
+            let canonical = sf2.canonicalize().unwrap_or_else(|_| sf2.to_path_buf());
+            if let Some((_, sound_font)) = loaded.iter().find(|(path, _)| *path == canonical) {
+                return Ok(sound_font.clone());
+            }
+            let sound_font = load_sound_font(sf2)?;
+            loaded.push((canonical, sound_font.clone()));
+            Ok(sound_font)
+        };
+        let mut sound_fonts = Vec::new();
+        for (_, sf2) in &sf2_files {
+            sound_fonts.push(load_cached(sf2)?);
+        }
+        let mut layers = Vec::new();
+        for (channels, sf2) in &cli.layer {
+            layers.push(SoundFontLayer { sound_font: load_cached(sf2)?, channels: channels.clone() });
+        }
+        Ok(sound_fonts.into_iter().map(|sound_font| Renderer::new(sound_font, config.clone()).with_layers(layers.clone())).collect())
+    };
+
+    // The messages go to stderr then, see `init_logging`
+    let to_stdout = config.stream;
+
+    let output_folder;
+    if to_stdout {
+        output_folder = PathBuf::new();
+    } else if let Some(custom_output_folder) = cli.output_folder {
+        if custom_output_folder.exists() && !custom_output_folder.is_dir() {
+            return Err(RenderError::OutputNotADirectory(custom_output_folder).into());
+        }
+        if !cli.dry_run {
+            std::fs::create_dir_all(&custom_output_folder)?;
+        }
+        output_folder = custom_output_folder;
     } else {
         output_folder = std::env::current_dir()?;
     }
 
-    fn valid_midi_file<P: AsRef<Path>>(path: P) -> bool {
-            if let Ok(file_metadata) = std::fs::metadata(&path) {
-                let is_file = file_metadata.is_file();
-                let extension = path.as_ref().extension();
-                if let Some(extension) = extension {
-                    if let Some(extension) = extension.to_str() {
-                        is_file && extension == "mid"
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
+    /// Why `path` is not a MIDI file that can be rendered, if it is not
+    fn skip_reason<P: AsRef<Path>>(path: P) -> Option<&'static str> {
+            match std::fs::metadata(&path) {
+                Ok(file_metadata) if !file_metadata.is_file() => return Some("not a file"),
+                Ok(_) => {},
+                Err(_) => return Some("cannot be read"),
+            }
+            if is_midi_file(path.as_ref()) {
+                None
             } else {
-                false
+                Some("not a .mid, .midi or .rmi file")
             }
     }
-    let input_file_paths: Vec<(PathBuf, PathBuf)> = glob(&cli.input_glob).expect("Failed to read glob pattern").into_iter().filter_map(|entry| {
+    let inputs: Vec<PathBuf> = matching_paths(&input_glob)?.into_iter().filter_map(|entry| {
         match entry {
             Ok(path) => {
-                if !valid_midi_file(&path) {
-                    println!("Skipping {}!", path.display());
+                if let Some(reason) = skip_reason(&path) {
+                    log::info!("Skipping {}, {}", path.display(), reason);
                     None
                 } else {
-                    if let Some(input_file_name) = path.file_name() {
-                        let mut output_path = output_folder.clone();
-                        PathBuf::push(&mut output_path, input_file_name);
-                        output_path.set_extension("wav");
-                        Some((path, output_path))
-                    } else {
-                        None
-                    }
+                    Some(path)
                 }
             },
             Err(e) => {
-                println!("{:?}", e);
+                log::error!("{:?}", e);
                 None
             }
         }
     }).collect();
 
-    // sound_font - Loaded Soundfont
-    // input_file_paths - MIDI files to render and where to render them to
-    // output_folder - Output path
-    // bitdepth - Target bit-depth for bit reduction
-    // sample_rate - Target sample rate for zero-interpolation resampling
+    if inputs.is_empty() {
+        log::error!("No MIDI files (.mid, .midi or .rmi) matched the pattern `{}`, check it for typos and that it is quoted so the shell does not expand it", input_glob.to_string_lossy());
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let renderers = if cli.dry_run { Vec::new() } else { load_renderers()? };
 
-    for (input_file_path, output_file_path) in input_file_paths {
-        print!("Rendering {}... ", input_file_path.display());
-        render(sound_font.clone(), input_file_path, output_file_path, cli.bitdepth, cli.sample_rate, cli.repeat)?;
-        println!("done!");
+    if let Some(concat) = cli.concat {
+        if sf2_files.len() != 1 {
+            return Err(RenderError::InvalidConfig("--concat renders through a single soundfont".to_string()).into());
+        }
+        if cli.dry_run {
+            for input in &inputs {
+                println!("{} -> {}", input.display(), concat.display());
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        let renderer = &renderers[0];
+        let join = cli.concat_crossfade.map_or(ConcatJoin::Gap(cli.concat_gap), ConcatJoin::Crossfade);
+        let display = concat.display().to_string();
+        let mut last_percent = None;
+        create_parent_folder(&concat)?;
+        let stats = renderer.render_concat_with_progress(&inputs, &concat, join, |fraction| {
+            let percent = (fraction * 100.0) as u32;
+            if show_status() && last_percent != Some(percent) {
+                last_percent = Some(percent);
+                print!("\rRendering {} files into {}... {:>3}%", inputs.len(), display, percent);
+                let _ = std::io::stdout().flush();
+            }
+        })?;
+        log::info!("\rRendering {} files into {}... done!", inputs.len(), display);
+        if let Some(path) = &cli.manifest {
+            let entries: Vec<ManifestEntry> = inputs.iter().zip(stats.iter()).map(|(input, stats)| {
+                ManifestEntry::new(input, &concat, None, None, &Ok(stats.clone()), config.nds_rate)
+            }).collect();
+            write_manifest(path, &entries)?;
+        }
+        for (input, stats) in inputs.iter().zip(stats.iter()) {
+            log::info!("  {} ({})", input.display(), describe(stats));
+        }
+        return Ok(ExitCode::SUCCESS);
     }
+    let name_by_sound_font = sf2_files.len() > 1;
+    if let Some(template) = cli.name_template.as_ref().filter(|template| name_by_sound_font && !template.uses("sf2")) {
+        return Err(RenderError::InvalidConfig(format!("name template `{}` needs {{sf2}} to tell the renders of several soundfonts apart", template.0)).into());
+    }
+    // Stems are named after presets as each soundfont has them, which `--dry-run` leaves out since nothing is loaded
+    let presets: Vec<Vec<PresetSummary>> = (0..sf2_files.len()).map(|index| match renderers.get(index) {
+        Some(renderer) if cli.stem_names => SoundFontSummary::new(renderer.sound_font()).presets,
+        _ => Vec::new(),
+    }).collect();
+    // Mirrored outputs keep their folders relative to the deepest folder that contains every input
+    let root = if cli.mirror { common_root(&inputs) } else { None };
+    let input_file_paths: Vec<Job> = inputs.into_iter().flat_map(|path| {
+        let Some(output_path) = output_path_for(&path, &output_folder, root.as_deref(), config.output_extension()) else { return Vec::new() };
+        let mut jobs = Vec::new();
+        for (renderer, sound_font_name) in sound_font_names.iter().enumerate() {
+            let mut output_path = output_path.clone();
+            if let Some(template) = &cli.name_template {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                output_path.set_file_name(format!("{}.{}", template.expand(&stem, sound_font_name, &config), config.output_extension()));
+            } else if name_by_sound_font {
+                output_path = with_sound_font_name(&output_path, sound_font_name, config.output_extension());
+            }
+            let outputs = if cli.stems {
+                // A file that cannot be read is rendered as a whole to report the error like any other failure
+                stem_outputs(&path, &output_path, &config, &presets[renderer]).unwrap_or_else(|_| vec![(output_path, None)])
+            } else {
+                vec![(output_path, None)]
+            };
+            for (output_path, channel) in outputs {
+                if cli.skip_existing && !cli.force && !to_stdout && !cli.print_hash && output_path.exists() {
+                    log::info!("Skipping {}, {} already exists", path.display(), output_path.display());
+                    continue;
+                }
+                jobs.push(Job { input: path.clone(), output: output_path, renderer, channel });
+            }
+        }
+        jobs
+    }).collect();
 
-    println!("\nFriendly Friends!~ Keep up your training!\n\n");
+    if cli.dry_run {
+        for job in &input_file_paths {
+            let output = if to_stdout { "stdout".to_string() } else { job.output.display().to_string() };
+            println!("{} -> {}", job.input.display(), output);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    Ok(())
-}
+    if to_stdout {
+        if input_file_paths.len() != 1 {
+            return Err(RenderError::StdoutNeedsSingleInput(input_file_paths.len()).into());
+        }
+        let job = &input_file_paths[0];
+        let display = job.input.display().to_string();
+        let mut last_percent = None;
+        let stdout = BufWriter::new(std::io::stdout().lock());
+        let stats = renderers[job.renderer].render_to_stream(&job.input, stdout, |fraction| {
+            let percent = (fraction * 100.0) as u32;
+            if show_status() && last_percent != Some(percent) {
+                last_percent = Some(percent);
+                eprint!("\rRendering {}... {:>3}%", display, percent);
+            }
+        })?;
+        log::info!("\rRendering {}... done! ({})", display, describe(&stats));
+        return Ok(ExitCode::SUCCESS);
+    }
 
-pub fn render<P: AsRef<Path>>(sound_font: Arc<SoundFont>, input_file_path: P, output_file_path: P, bitdepth: u8, sample_rate: u32, repeat: f64) -> Result<(), Box<dyn std::error::Error>> {
-    let mut mid = File::open(input_file_path)?;
-    let midi_file = Arc::new(MidiFile::new(&mut mid)?);
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(cli.jobs.unwrap_or(0)).build()?;
+    // Progress of several files at once would garble the line it is printed on, so only a sequential batch shows it
+    let show_progress = show_status() && (pool.current_num_threads() == 1 || input_file_paths.len() == 1);
+    let manifest = Mutex::new(Vec::new());
+    let results: Vec<Result<RenderStats, RenderError>> = pool.install(|| input_file_paths.par_iter().map(|job| {
+        let (sound_font_name, renderer) = (&sound_font_names[job.renderer], &renderers[job.renderer]);
+        let mut display = job.input.display().to_string();
+        if name_by_sound_font {
+            display = format!("{} with {}", display, sound_font_name);
+        }
+        if let Some(channel) = job.channel {
+            display = format!("{} (channel {})", display, channel);
+        }
+        let mut last_percent = None;
+        let progress = |fraction: f32| {
+            let percent = (fraction * 100.0) as u32;
+            if show_progress && last_percent != Some(percent) {
+                last_percent = Some(percent);
+                print!("\rRendering {}... {:>3}%", display, percent);
+                let _ = std::io::stdout().flush();
+            }
+        };
+        // Hashing writes nothing
+        let prepared = if cli.print_hash { Ok(()) } else { create_parent_folder(&job.output) };
+        let result = prepared.and_then(|_| {
+            let embedded = if cli.embedded_dls { load_embedded_dls(&job.input)? } else { None };
+            let mut config = renderer.config().clone();
+            if let Some(channel) = job.channel {
+                config.solo = vec![channel];
+            }
+            let sound_font = embedded.unwrap_or_else(|| renderer.sound_font().clone());
+            let renderer = Renderer::new(sound_font, config).with_layers(renderer.layers().to_vec());
+            if cli.print_hash {
+                renderer.render_file_streaming_with_progress(&job.input, |_, _| {}, progress)
+            } else {
+                renderer.render_file_with_progress(&job.input, &job.output, progress)
+            }
+        });
+        match &result {
+            Ok(stats) if cli.print_hash => println!("\r{:016x}  {}", stats.hash, display),
+            Ok(stats) => log::info!("\rRendering {}... done! ({})", display, describe(stats)),
+            Err(e) => log::error!("\rRendering {}... failed: {}", display, e),
+        }
+        if let Some(path) = &cli.manifest {
+            let mut entries = manifest.lock().expect("no panics while holding the lock");
+            let sound_font = name_by_sound_font.then(|| sound_font_name.clone());
+            entries.push(ManifestEntry::new(&job.input, &job.output, sound_font, job.channel, &result, config.nds_rate));
+            if let Err(e) = write_manifest(path, &entries) {
+                log::error!("Failed to write the manifest {}: {}", path.display(), e);
+            }
+        }
+        result
+    }).collect());
+    let failed_count = results.iter().filter(|result| result.is_err()).count();
+    if failed_count > 0 {
+        // Stems and several soundfonts make more than one render of an input, which is only listed once
+        let mut failed: Vec<&PathBuf> = Vec::new();
+        for (job, _) in input_file_paths.iter().zip(results.iter()).filter(|(_, result)| result.is_err()) {
+            if !failed.contains(&&job.input) {
+                failed.push(&job.input);
+            }
+        }
+        log::error!("\nRendered {} of {} files, {} failed, from the inputs:", input_file_paths.len() - failed_count, input_file_paths.len(), failed_count);
+        for input_file_path in failed {
+            log::error!("  {}", input_file_path.display());
+        }
+        return Ok(ExitCode::FAILURE);
+    }
 
-    let mut settings = SynthesizerSettings::new(sample_rate as i32);
-    settings.enable_reverb_and_chorus = false;
-    let synthesizer = Synthesizer::new(&sound_font, &settings)?;
-    let mut sequencer = MidiFileSequencer::new(synthesizer);
+    log::info!("\nFriendly Friends!~ Keep up your training!\n\n");
 
-    sequencer.play(&midi_file, if repeat == 1.0 { false } else { true });
+    Ok(ExitCode::SUCCESS)
+}
 
-    let sample_count = (settings.sample_rate as f64 * midi_file.get_length() * repeat) as usize;
-    let mut left: Vec<f32> = vec![0_f32; sample_count];
-    let mut right: Vec<f32> = vec![0_f32; sample_count];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    sequencer.render(&mut left, &mut right);
+    /// A fresh folder for the files of the test `name`
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("nds_sound_render_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
 
-    let spec = hound::WavSpec {
-        channels: 2,
-        sample_rate: sample_rate,
-        bits_per_sample: 32,
-        sample_format: hound::SampleFormat::Float,
-    };
-    let mut writer = hound::WavWriter::create(output_file_path, spec)?;
-    for (&(mut l), &(mut r)) in left.iter().zip(right.iter()) {
-        if bitdepth != 0 {
-            l = quantize_to_bitdepth(l, bitdepth);
-            r = quantize_to_bitdepth(r, bitdepth);
+    #[test]
+    fn output_paths_keep_unusual_names() {
+        let output_folder = Path::new("out");
+        for (input, output) in [
+            ("music/My Song.mid", "out/My Song.wav"),
+            ("music/track [1].MID", "out/track [1].wav"),
+            ("music/star*.rmi", "out/star*.wav"),
+            ("music/v1.2 [final].midi", "out/v1.2 [final].wav"),
+        ] {
+            assert_eq!(output_path_for(Path::new(input), output_folder, None, "wav"), Some(PathBuf::from(output)));
         }
-        writer.write_sample(l)?;
-        writer.write_sample(r)?;
+        let mirrored = output_path_for(Path::new("music/Disc [1]/Some Song.mid"), output_folder, Some(Path::new("music")), "flac");
+        assert_eq!(mirrored, Some(PathBuf::from("out/Disc [1]/Some Song.flac")));
+        assert_eq!(with_sound_font_name(Path::new("out/track [1].wav"), "GS Sound Set", "wav"), PathBuf::from("out/track [1].GS Sound Set.wav"));
     }
 
-    Ok(())
-}
+    #[cfg(unix)]
+    #[test]
+    fn output_paths_keep_names_that_are_not_utf8() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+        let input = PathBuf::from(OsString::from_vec(b"music/caf\xe9 [1].mid".to_vec()));
+        assert!(input.to_str().is_none());
+        assert!(is_midi_file(&input));
+        let output = output_path_for(&input, Path::new("out"), None, "wav").unwrap();
+        assert_eq!(output.as_os_str().as_bytes(), b"out/caf\xe9 [1].wav");
+        let named = with_sound_font_name(&output, "GS", "wav");
+        assert_eq!(named.as_os_str().as_bytes(), b"out/caf\xe9 [1].GS.wav");
+    }
 
-pub fn quantize_to_bitdepth(x: f32, bitdepth: u8) -> f32 {
-    quantize_f32(x, 2_u32.pow(bitdepth as u32 - 1) - 1)
-}
+    #[test]
+    fn matching_paths_takes_existing_files_literally() {
+        let folder = test_folder("literal_names");
+        let names = if cfg!(windows) { vec!["My Song.mid", "track [1].mid", "track 1.mid"] } else { vec!["My Song.mid", "track [1].mid", "track 1.mid", "star*.mid"] };
+        for name in &names {
+            std::fs::write(folder.join(name), b"").unwrap();
+        }
+        let matches = |pattern: &Path| -> Vec<PathBuf> {
+            let mut paths: Vec<PathBuf> = matching_paths(pattern.as_os_str()).unwrap().into_iter().map(Result::unwrap).collect();
+            paths.sort();
+            paths
+        };
+        // As a pattern `[1]` would only match `track 1.mid`, and `*` every file
+        for name in &names {
+            assert_eq!(matches(&folder.join(name)), vec![folder.join(name)]);
+        }
+        // Escaped, the folder can have any name and the file name is still a pattern
+        let pattern = PathBuf::from(format!("{}/track [[]*", glob::Pattern::escape(&folder.to_string_lossy())));
+        assert_eq!(matches(&pattern), vec![folder.join("track [1].mid")]);
+        let mut every_file: Vec<PathBuf> = names.iter().map(|name| folder.join(name)).collect();
+        every_file.sort();
+        assert_eq!(matches(&folder.join("*.mid")), every_file);
+        let _ = std::fs::remove_dir_all(&folder);
+    }
 
-/// A simple linear quantization of a floating-point number `x` within a range of [-1.0, 1.0] by projecting the number onto a range of integers [-`n_half`, `n_half`]
-/// 
-/// Note
-/// ====
-/// For quantizing a 32-bit floating point number to an `n`-bit floating point number, set `n_half` to be 
-/// `n_half = 2^(n-1) - 1`
-pub fn quantize_f32(x: f32, n_half: u32) -> f32 {
-    (x * n_half as f32).round() / n_half as f32
+    // APFS only takes UTF-8 names
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn matching_paths_finds_files_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+        let folder = test_folder("non_utf8_names");
+        let mut name = folder.clone().into_os_string().into_vec();
+        name.extend_from_slice(b"/caf\xe9 [1].mid");
+        let path = PathBuf::from(OsString::from_vec(name));
+        std::fs::write(&path, b"").unwrap();
+        let matches: Vec<PathBuf> = matching_paths(path.as_os_str()).unwrap().into_iter().map(Result::unwrap).collect();
+        assert_eq!(matches, vec![path]);
+        // A pattern that is not UTF-8 itself matches nothing rather than failing
+        let mut pattern = folder.clone().into_os_string().into_vec();
+        pattern.extend_from_slice(b"/caf\xe9*");
+        assert!(matching_paths(&OsString::from_vec(pattern)).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&folder);
+    }
 }
@@ -0,0 +1,94 @@
+use std::{fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}};
+use serde::Serialize;
+
+use crate::error::RenderError;
+use crate::output::write_atomically;
+use crate::stats::RenderStats;
+
+/// One render of a batch, for the record that [`write_manifest`] writes
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ManifestEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Name of the soundfont the input was rendered through, when a batch uses several
+    pub sound_font: Option<String>,
+    /// The channel of a stem, or `None` for a render of every channel
+    pub channel: Option<u8>,
+    pub success: bool,
+    /// Why the render failed, if it did
+    pub error: Option<String>,
+    /// Length of the written audio in seconds
+    pub duration: f64,
+    /// Number of frames written at the NDS rate, i.e. before resampling
+    pub frames: usize,
+    pub peak_dbfs: f32,
+    pub rms_dbfs: f32,
+    pub clipped_samples: usize,
+}
+
+impl ManifestEntry {
+    /// The entry of rendering `input` into `output` with `result`, at an NDS rate of `nds_rate`
+    pub fn new(input: &Path, output: &Path, sound_font: Option<String>, channel: Option<u8>, result: &Result<RenderStats, RenderError>, nds_rate: u32) -> Self {
+        let stats = result.as_ref().ok().cloned().unwrap_or_default();
+        // The stats count the frames before trimming
+        let frames = stats.frames.saturating_sub(stats.trimmed_start + stats.trimmed_end);
+        ManifestEntry {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            sound_font,
+            channel,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            duration: frames as f64 / nds_rate as f64,
+            frames,
+            peak_dbfs: stats.peak_dbfs(),
+            rms_dbfs: stats.rms_dbfs(),
+            clipped_samples: stats.clipped_samples,
+        }
+    }
+}
+
+/// Writes `entries` to `path` as CSV with a header row if it ends in `.csv`, and as a JSON array otherwise
+/// 
+/// Levels of silent renders are -inf dBFS, which JSON has no number for, so they are written as `null` there.
+pub fn write_manifest<P: AsRef<Path>>(path: P, entries: &[ManifestEntry]) -> Result<(), RenderError> {
+    let csv = path.as_ref().extension().is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    write_atomically(path, |path| write_entries(path, entries, csv))
+}
+
+fn write_entries(path: &Path, entries: &[ManifestEntry], csv: bool) -> Result<(), RenderError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    if csv {
+        writeln!(file, "input,output,sound_font,channel,success,error,duration,frames,peak_dbfs,rms_dbfs,clipped_samples")?;
+        for entry in entries {
+            let fields = [
+                csv_field(&entry.input.to_string_lossy()),
+                csv_field(&entry.output.to_string_lossy()),
+                csv_field(entry.sound_font.as_deref().unwrap_or_default()),
+                entry.channel.map(|channel| channel.to_string()).unwrap_or_default(),
+                entry.success.to_string(),
+                csv_field(entry.error.as_deref().unwrap_or_default()),
+                entry.duration.to_string(),
+                entry.frames.to_string(),
+                entry.peak_dbfs.to_string(),
+                entry.rms_dbfs.to_string(),
+                entry.clipped_samples.to_string(),
+            ];
+            writeln!(file, "{}", fields.join(","))?;
+        }
+    } else {
+        serde_json::to_writer_pretty(&mut file, entries).map_err(|e| RenderError::Encode(e.to_string()))?;
+        writeln!(file)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// `field` quoted for CSV if it needs to be
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,567 @@
+use std::{io::Cursor, sync::Arc};
+use rustysynth::MidiFile;
+
+use crate::error::RenderError;
+
+/// A Standard MIDI File, parsed down to its raw events
+/// 
+/// `rustysynth` only exposes a MIDI file as something to play, so anything that needs to look at the events themselves,
+/// such as finding loop markers, goes through this instead.
+#[derive(Clone, Debug)]
+pub struct Smf {
+    /// 0 for a single track, 1 for simultaneous tracks and 2 for independent sequences, which are played one after another
+    pub format: u16,
+    /// The raw division field of the header, see [`Smf::ticks_per_quarter`]
+    pub division: u16,
+    pub tracks: Vec<Vec<TrackEvent>>,
+}
+
+/// An event of a track, `delta` ticks after the one before it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackEvent {
+    pub delta: u32,
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// A channel message; `data2` is unused (and 0) for program change and channel pressure
+    Midi { status: u8, data1: u8, data2: u8 },
+    /// A system exclusive message with its leading `0xF0` or `0xF7`
+    SysEx { status: u8, data: Vec<u8> },
+    /// A meta event of type `kind`
+    Meta { kind: u8, data: Vec<u8> },
+}
+
+/// Meta event type of a tempo change
+pub const META_TEMPO: u8 = 0x51;
+/// Meta event type of a marker
+pub const META_MARKER: u8 = 0x06;
+/// Meta event type of the end of a track
+pub const META_END_OF_TRACK: u8 = 0x2F;
+
+/// Name of the marker at the start of a loop
+pub const LOOP_START_MARKER: &str = "loopStart";
+/// Name of the marker at the end of a loop
+pub const LOOP_END_MARKER: &str = "loopEnd";
+/// Controller that marks the start of a loop when there is no marker, see [`Smf::loop_points`]
+pub const LOOP_START_CONTROLLER: u8 = 111;
+
+/// Whether the marker `text` is `name`, ignoring case and anything but letters and digits, so that `loop_start` and `Loop Start` are `loopStart`
+pub fn is_marker_named(text: &str, name: &str) -> bool {
+    let simplify = |text: &str| -> String { text.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect() };
+    simplify(text) == simplify(name)
+}
+
+/// Channel (1 to 16) that General MIDI reserves for percussion
+pub const DRUM_CHANNEL: u8 = 10;
+
+/// Tempo of a file that never sets one, 120 BPM
+pub const DEFAULT_MICROSECONDS_PER_QUARTER: u32 = 500_000;
+
+impl Smf {
+    pub fn parse(bytes: &[u8]) -> Result<Self, RenderError> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(4)? != b"MThd" {
+            return Err(invalid("missing MThd header"));
+        }
+        let header_length = reader.u32()? as usize;
+        if header_length < 6 {
+            return Err(invalid("header is too short"));
+        }
+        let format = reader.u16()?;
+        let track_count = reader.u16()?;
+        let division = reader.u16()?;
+        reader.take(header_length - 6)?;
+
+        let mut tracks = Vec::with_capacity(track_count as usize);
+        while tracks.len() < track_count as usize && !reader.is_empty() {
+            let id = reader.take(4)?;
+            let length = reader.u32()? as usize;
+            let data = reader.take(length)?;
+            // Unknown chunks are to be skipped
+            if id == b"MTrk" {
+                tracks.push(parse_track(data)?);
+            }
+        }
+
+        Ok(Smf { format, division, tracks })
+    }
+
+    /// Serializes the file back into Standard MIDI File bytes
+    /// 
+    /// Every event is written with its status byte (no running status), and tracks get an end-of-track event if they are missing one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"MThd");
+        bytes.extend_from_slice(&6_u32.to_be_bytes());
+        bytes.extend_from_slice(&self.format.to_be_bytes());
+        bytes.extend_from_slice(&(self.tracks.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.division.to_be_bytes());
+        for track in &self.tracks {
+            let mut data = Vec::new();
+            for event in track {
+                write_variable_length(&mut data, event.delta);
+                match &event.kind {
+                    EventKind::Midi { status, data1, data2 } => {
+                        data.extend_from_slice(&[*status, *data1]);
+                        if !matches!(status & 0xF0, 0xC0 | 0xD0) {
+                            data.push(*data2);
+                        }
+                    }
+                    EventKind::SysEx { status, data: message } => {
+                        data.push(*status);
+                        write_variable_length(&mut data, message.len() as u32);
+                        data.extend_from_slice(message);
+                    }
+                    EventKind::Meta { kind, data: message } => {
+                        data.extend_from_slice(&[0xFF, *kind]);
+                        write_variable_length(&mut data, message.len() as u32);
+                        data.extend_from_slice(message);
+                    }
+                }
+            }
+            let terminated = matches!(track.last(), Some(TrackEvent { kind: EventKind::Meta { kind: META_END_OF_TRACK, .. }, .. }));
+            if !terminated {
+                data.extend_from_slice(&[0x00, 0xFF, META_END_OF_TRACK, 0x00]);
+            }
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&data);
+        }
+        bytes
+    }
+
+    /// Parses the events back into a [`MidiFile`] for playback, after they have been changed
+    /// 
+    /// The timing is made explicit on the way, see [`Smf::make_timing_explicit`], while `self` is left as it is.
+    pub fn to_midi_file(&self) -> Result<Arc<MidiFile>, RenderError> {
+        let mut playback = self.clone();
+        playback.make_timing_explicit();
+        Ok(Arc::new(MidiFile::new(&mut Cursor::new(playback.to_bytes()))?))
+    }
+
+    /// Removes every event for which `keep` returns `false`, moving the events after it so that their timing stays the same
+    pub fn retain_events<F: FnMut(&EventKind) -> bool>(&mut self, mut keep: F) {
+        for track in &mut self.tracks {
+            let mut carried = 0;
+            track.retain_mut(|event| {
+                if keep(&event.kind) {
+                    event.delta += carried;
+                    carried = 0;
+                    true
+                } else {
+                    carried += event.delta;
+                    false
+                }
+            });
+        }
+    }
+
+    /// The tick at which each track starts: 0 for all of them, except in a format 2 file, whose sequences have nothing to do with each other
+    /// and so are played one after another, each starting where the one before it ends
+    /// 
+    /// Tempo changes carry over from one sequence to the next, so a sequence that sets no tempo of its own plays at the one before it ended with.
+    pub fn sequence_starts(&self) -> Vec<u64> {
+        let mut start = 0;
+        self.tracks.iter().map(|track| {
+            let track_start = start;
+            if self.format == 2 {
+                start += track.iter().map(|event| event.delta as u64).sum::<u64>();
+            }
+            track_start
+        }).collect()
+    }
+
+    /// Turns a format 2 file into a format 1 file whose tracks are delayed to their [`Smf::sequence_starts`], returning whether it was format 2
+    /// 
+    /// `rustysynth` would otherwise play all the sequences at once, and anything working on the tracks side by side gets them in the right place.
+    pub fn chain_sequences(&mut self) -> bool {
+        if self.format != 2 {
+            return false;
+        }
+        let starts = self.sequence_starts();
+        for (track, start) in self.tracks.iter_mut().zip(starts) {
+            if let Some(first) = track.first_mut() {
+                first.delta = (first.delta as u64 + start).min(u32::MAX as u64) as u32;
+            }
+        }
+        self.format = 1;
+        true
+    }
+
+    /// Rewrites the timing of the file into a form that plays back the same everywhere, returning whether anything changed
+    /// 
+    /// SMPTE-based timing becomes ticks per quarter note with a single fixed tempo that gives every tick its original length,
+    /// since `rustysynth` only understands the former, and any tempo events are dropped as SMPTE timing never followed them.
+    /// A file that does not set a tempo at its very start gets an explicit one of 120 BPM, the tempo the standard assumes there.
+    /// A format 2 file is chained into a format 1 one first, see [`Smf::chain_sequences`].
+    pub fn make_timing_explicit(&mut self) -> bool {
+        let chained = self.chain_sequences();
+        let tempo = if self.ticks_per_quarter().is_none() {
+            let ticks_per_second = smpte_ticks_per_second(self.division);
+            // A quarter note of about a second, which keeps the division within its 15 bits for any frame rate and ticks per frame
+            self.division = (ticks_per_second.round() as u16).clamp(1, 0x7FFF);
+            self.retain_events(|kind| !matches!(kind, EventKind::Meta { kind: META_TEMPO, .. }));
+            (1_000_000.0 * self.division as f64 / ticks_per_second).round() as u32
+        } else if self.needs_explicit_timing() {
+            DEFAULT_MICROSECONDS_PER_QUARTER
+        } else {
+            return chained;
+        };
+        if self.tracks.is_empty() {
+            self.tracks.push(Vec::new());
+        }
+        self.tracks[0].insert(0, TrackEvent { delta: 0, kind: EventKind::Meta { kind: META_TEMPO, data: tempo.to_be_bytes()[1..].to_vec() } });
+        true
+    }
+
+    /// Whether [`Smf::make_timing_explicit`] would change anything
+    pub fn needs_explicit_timing(&self) -> bool {
+        let sets_initial_tempo = self.timeline().into_iter()
+            .take_while(|&(tick, _)| tick == 0)
+            .any(|(_, event)| matches!(event.kind, EventKind::Meta { kind: META_TEMPO, .. }));
+        self.format == 2 || self.ticks_per_quarter().is_none() || !sets_initial_tempo
+    }
+
+    /// Ticks per quarter note, or `None` for SMPTE-based timing
+    pub fn ticks_per_quarter(&self) -> Option<u16> {
+        (self.division & 0x8000 == 0).then_some(self.division)
+    }
+
+    /// Every event of every track at its absolute tick, ordered by time and then by track
+    /// 
+    /// The independent sequences of a format 2 file follow each other, see [`Smf::sequence_starts`].
+    pub fn timeline(&self) -> Vec<(u64, &TrackEvent)> {
+        let mut events: Vec<(u64, usize, &TrackEvent)> = Vec::new();
+        for ((index, track), start) in self.tracks.iter().enumerate().zip(self.sequence_starts()) {
+            let mut tick = start;
+            for event in track {
+                tick += event.delta as u64;
+                events.push((tick, index, event));
+            }
+        }
+        // Stable, so events keep their order within a track
+        events.sort_by_key(|&(tick, index, _)| (tick, index));
+        events.into_iter().map(|(tick, _, event)| (tick, event)).collect()
+    }
+
+    /// Maps absolute ticks to seconds, following every tempo change of the file
+    pub fn tempo_map(&self) -> TempoMap {
+        let changes = self.timeline().into_iter().filter_map(|(tick, event)| match &event.kind {
+            EventKind::Meta { kind: META_TEMPO, data } if data.len() >= 3 => {
+                Some((tick, u32::from_be_bytes([0, data[0], data[1], data[2]])))
+            }
+            _ => None,
+        }).collect();
+        TempoMap::new(self.division, changes)
+    }
+
+    /// The text of every marker meta event at its absolute tick
+    pub fn markers(&self) -> Vec<(u64, String)> {
+        self.timeline().into_iter().filter_map(|(tick, event)| match &event.kind {
+            EventKind::Meta { kind: META_MARKER, data } => Some((tick, String::from_utf8_lossy(data).into_owned())),
+            _ => None,
+        }).collect()
+    }
+
+    /// Level set by the last GM or GS master volume SysEx message before the first note, from 0.0 to 1.0
+    pub fn master_volume(&self) -> Option<f64> {
+        let mut volume = None;
+        for (_, event) in self.timeline() {
+            match &event.kind {
+                EventKind::Midi { status, data2, .. } if status & 0xF0 == 0x90 && *data2 > 0 => break,
+                EventKind::SysEx { status: 0xF0, data } => match data[..] {
+                    // Universal real time, any device: the level is 14 bits, LSB first
+                    [0x7F, _, 0x04, 0x01, lsb, msb, ..] => volume = Some(((msb as u16) << 7 | lsb as u16) as f64 / 16383.0),
+                    // Roland, any device, GS data set to the master volume parameter
+                    [0x41, _, 0x42, 0x12, 0x40, 0x00, 0x04, level, ..] => volume = Some(level.min(127) as f64 / 127.0),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        volume
+    }
+
+    /// Position of the `loopStart` and `loopEnd` markers in ticks, if the file has a start marker
+    /// 
+    /// Marker names are matched case-insensitively and with or without a separator, e.g. `loop_start` or `Loop End`.
+    /// A missing end marker means the loop runs to the end of the file.
+    pub fn loop_markers(&self) -> Option<(u64, Option<u64>)> {
+        let start = self.find_marker(LOOP_START_MARKER)?;
+        Some((start, self.loop_end_after(start)))
+    }
+
+    /// Position of the loop in ticks like [`Smf::loop_markers`], falling back to the first CC111 as the loop start when there is no start marker
+    /// 
+    /// CC111 is how RPG Maker and a good few game soundtracks mark the point to loop back to; the end is still taken from a `loopEnd` marker.
+    pub fn loop_points(&self) -> Option<(u64, Option<u64>)> {
+        if let Some(points) = self.loop_markers() {
+            return Some(points);
+        }
+        let start = self.timeline().into_iter().find_map(|(tick, event)| match event.kind {
+            EventKind::Midi { status, data1: LOOP_START_CONTROLLER, .. } if status & 0xF0 == 0xB0 => Some(tick),
+            _ => None,
+        })?;
+        Some((start, self.loop_end_after(start)))
+    }
+
+    fn loop_end_after(&self, start: u64) -> Option<u64> {
+        self.find_marker(LOOP_END_MARKER).filter(|&end| end > start)
+    }
+
+    /// Tick of the first marker named `name`, see [`is_marker_named`]
+    fn find_marker(&self, name: &str) -> Option<u64> {
+        self.markers().into_iter().find(|(_, text)| is_marker_named(text, name)).map(|(tick, _)| tick)
+    }
+}
+
+/// Converts ticks into seconds using the tempo changes of a file
+#[derive(Clone, Debug)]
+pub struct TempoMap {
+    division: u16,
+    /// Tick, microseconds per quarter note and the time in seconds at that tick, starting at tick 0
+    segments: Vec<(u64, u32, f64)>,
+}
+
+impl TempoMap {
+    fn new(division: u16, changes: Vec<(u64, u32)>) -> Self {
+        let mut segments = vec![(0, DEFAULT_MICROSECONDS_PER_QUARTER, 0.0)];
+        for (tick, tempo) in changes {
+            let seconds = Self::seconds_since(division, segments[segments.len() - 1], tick);
+            if segments[segments.len() - 1].0 == tick {
+                segments.pop();
+            }
+            segments.push((tick, tempo, seconds));
+        }
+        TempoMap { division, segments }
+    }
+
+    fn seconds_since(division: u16, (start, tempo, seconds): (u64, u32, f64), tick: u64) -> f64 {
+        let ticks = (tick - start) as f64;
+        if division & 0x8000 != 0 {
+            seconds + ticks / smpte_ticks_per_second(division)
+        } else {
+            seconds + ticks * tempo as f64 / 1_000_000.0 / division.max(1) as f64
+        }
+    }
+
+    /// Tempo changes as ticks and microseconds per quarter note, starting with the initial tempo at tick 0
+    pub fn changes(&self) -> impl Iterator<Item = (u64, u32)> + '_ {
+        self.segments.iter().map(|&(tick, tempo, _)| (tick, tempo))
+    }
+
+    /// The (fractional) tick at `seconds` into the file, the inverse of [`TempoMap::seconds_at`]
+    pub fn tick_at(&self, seconds: f64) -> f64 {
+        let index = self.segments.partition_point(|&(_, _, start)| start <= seconds).max(1) - 1;
+        let (start, tempo, start_seconds) = self.segments[index];
+        let ticks_per_second = if self.division & 0x8000 != 0 {
+            1.0 / Self::seconds_since(self.division, (0, tempo, 0.0), 1)
+        } else {
+            1_000_000.0 * self.division.max(1) as f64 / tempo as f64
+        };
+        start as f64 + (seconds - start_seconds).max(0.0) * ticks_per_second
+    }
+
+    pub fn seconds_at(&self, tick: u64) -> f64 {
+        let index = self.segments.partition_point(|&(start, _, _)| start <= tick) - 1;
+        Self::seconds_since(self.division, self.segments[index], tick)
+    }
+}
+
+/// Length of a second in ticks under the SMPTE-based `division`, frames per second times ticks per frame, which does not depend on the tempo
+fn smpte_ticks_per_second(division: u16) -> f64 {
+    let frames_per_second = match -((division >> 8) as i8) {
+        29 => 29.97,
+        fps => fps.max(1) as f64,
+    };
+    frames_per_second * (division & 0xFF).max(1) as f64
+}
+
+fn parse_track(data: &[u8]) -> Result<Vec<TrackEvent>, RenderError> {
+    let mut reader = Reader { bytes: data, position: 0 };
+    let mut events = Vec::new();
+    let mut running_status = None;
+    while !reader.is_empty() {
+        let delta = reader.variable_length()?;
+        let mut status = reader.u8()?;
+        let kind = match status {
+            0xFF => {
+                let kind = reader.u8()?;
+                let length = reader.variable_length()? as usize;
+                let data = reader.take(length)?.to_vec();
+                let end_of_track = kind == META_END_OF_TRACK;
+                events.push(TrackEvent { delta, kind: EventKind::Meta { kind, data } });
+                if end_of_track {
+                    break;
+                }
+                continue;
+            }
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let length = reader.variable_length()? as usize;
+                EventKind::SysEx { status, data: reader.take(length)?.to_vec() }
+            }
+            _ => {
+                // Running status: the data byte just read belongs to a message with the previous status
+                let data1 = if status < 0x80 {
+                    let data1 = status;
+                    status = running_status.ok_or_else(|| invalid("data byte without a status"))?;
+                    data1
+                } else {
+                    running_status = Some(status);
+                    reader.u8()?
+                };
+                let data2 = match status & 0xF0 {
+                    0xC0 | 0xD0 => 0,
+                    _ => reader.u8()?,
+                };
+                EventKind::Midi { status, data1, data2 }
+            }
+        };
+        events.push(TrackEvent { delta, kind });
+    }
+    Ok(events)
+}
+
+fn write_variable_length(bytes: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+fn invalid(message: &str) -> RenderError {
+    RenderError::InvalidMidi(message.to_string())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], RenderError> {
+        let end = self.position.checked_add(length).filter(|&end| end <= self.bytes.len()).ok_or_else(|| invalid("unexpected end of file"))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, RenderError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, RenderError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, RenderError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn variable_length(&mut self) -> Result<u32, RenderError> {
+        let mut value = 0_u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("variable-length quantity is too long"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bytes of a format 0 file with `division` and a single track of `events`
+    fn file(division: u16, events: &[u8]) -> Vec<u8> {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend_from_slice(&6_u32.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 1]);
+        bytes.extend_from_slice(&division.to_be_bytes());
+        bytes.extend_from_slice(b"MTrk");
+        bytes.extend_from_slice(&(events.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(events);
+        bytes
+    }
+
+    /// A note lasting 480 ticks, with a tempo of 120 BPM first if `tempo` is set
+    fn note(tempo: bool) -> Vec<u8> {
+        let mut events = Vec::new();
+        if tempo {
+            events.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]);
+        }
+        events.extend_from_slice(&[0x00, 0x90, 60, 100, 0x83, 0x60, 0x80, 60, 0, 0x00, 0xFF, 0x2F, 0x00]);
+        events
+    }
+
+    #[test]
+    fn explicit_tempo_is_left_alone() {
+        let mut smf = Smf::parse(&file(480, &note(true))).unwrap();
+        assert!(!smf.needs_explicit_timing());
+        assert!(!smf.make_timing_explicit());
+        assert_eq!(smf.division, 480);
+    }
+
+    #[test]
+    fn tempo_less_file_gets_the_default_tempo() {
+        let mut smf = Smf::parse(&file(480, &note(false))).unwrap();
+        assert!(smf.needs_explicit_timing());
+        assert!(smf.make_timing_explicit());
+        assert_eq!(smf.division, 480);
+        let tempo_map = smf.tempo_map();
+        assert_eq!(tempo_map.changes().collect::<Vec<_>>(), vec![(0, DEFAULT_MICROSECONDS_PER_QUARTER)]);
+        assert_eq!(tempo_map.seconds_at(480), 0.5);
+        assert!(!smf.needs_explicit_timing());
+    }
+
+    #[test]
+    fn smpte_ticks_per_second_of_each_frame_rate() {
+        let division = |fps: i8, ticks_per_frame: u8| ((-fps) as u8 as u16) << 8 | ticks_per_frame as u16;
+        assert_eq!(smpte_ticks_per_second(division(24, 4)), 96.0);
+        assert_eq!(smpte_ticks_per_second(division(25, 40)), 1000.0);
+        assert_eq!(smpte_ticks_per_second(division(29, 80)), 29.97 * 80.0);
+        assert_eq!(smpte_ticks_per_second(division(30, 100)), 3000.0);
+    }
+
+    #[test]
+    fn smpte_timing_becomes_a_fixed_tempo() {
+        // 25 fps at 40 ticks per frame, with a tempo that SMPTE timing ignores
+        let mut smf = Smf::parse(&file(0xE728, &note(true))).unwrap();
+        assert_eq!(smf.ticks_per_quarter(), None);
+        assert!(smf.make_timing_explicit());
+        assert_eq!(smf.division, 1000);
+        let tempo_map = smf.tempo_map();
+        assert_eq!(tempo_map.changes().collect::<Vec<_>>(), vec![(0, 1_000_000)]);
+        assert_eq!(tempo_map.seconds_at(480), 0.48);
+        assert_eq!(tempo_map.seconds_at(2500), 2.5);
+    }
+
+    #[test]
+    fn drop_frame_smpte_timing_keeps_its_tick_length() {
+        // 29.97 fps at 80 ticks per frame, 2397.6 ticks per second
+        let mut smf = Smf::parse(&file(0xE350, &note(false))).unwrap();
+        assert!(smf.make_timing_explicit());
+        assert_eq!(smf.division, 2398);
+        let tempo_map = smf.tempo_map();
+        assert_eq!(tempo_map.changes().collect::<Vec<_>>(), vec![(0, 1_000_167)]);
+        for tick in [480, 2398, 23976, 239760] {
+            let seconds = tick as f64 / 2397.6;
+            assert!((tempo_map.seconds_at(tick) - seconds).abs() < seconds * 1e-6, "tick {} at {} s", tick, tempo_map.seconds_at(tick));
+        }
+    }
+}
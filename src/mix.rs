@@ -0,0 +1,16 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use crate::config::MonoMode;
+
+/// Downmixes a stereo block to mono in place, leaving the mix in both `left` and `right`
+pub fn downmix_mono(left: &mut [f32], right: &mut [f32], mode: MonoMode) {
+    let gain = match mode {
+        MonoMode::Average => 0.5,
+        MonoMode::Sum3dB => FRAC_1_SQRT_2,
+    };
+    for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+        let mixed = (*l + *r) * gain;
+        *l = mixed;
+        *r = mixed;
+    }
+}
@@ -0,0 +1,106 @@
+use std::{fmt, str::FromStr};
+use ebur128::{EbuR128, Mode};
+
+use crate::error::RenderError;
+
+/// How the level of a render is normalized before bit reduction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NormalizeMode {
+    /// Leave the level as the soundfont produces it
+    #[default]
+    None,
+    /// Scale so that the largest sample hits [`crate::RenderConfig::target_peak_dbfs`]
+    Peak,
+    /// Scale so that the integrated EBU R128 loudness hits [`crate::RenderConfig::target_lufs`]
+    /// 
+    /// This evens out perceived loudness across tracks, but can push loud peaks past full scale.
+    Lufs,
+}
+
+impl FromStr for NormalizeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(NormalizeMode::None),
+            "peak" => Ok(NormalizeMode::Peak),
+            "lufs" | "loudness" => Ok(NormalizeMode::Lufs),
+            _ => Err(format!("unknown normalization `{}` (expected none, peak or lufs)", s)),
+        }
+    }
+}
+
+impl fmt::Display for NormalizeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NormalizeMode::None => "none",
+            NormalizeMode::Peak => "peak",
+            NormalizeMode::Lufs => "lufs",
+        })
+    }
+}
+
+/// Converts a level in decibels into a linear gain factor
+pub fn db_to_gain(db: f32) -> f32 {
+    10_f32.powf(db / 20.0)
+}
+
+/// Measures a render block by block to find the gain that normalizes it, and the DC offset of each channel
+pub(crate) struct LevelMeter {
+    lowest: [f32; 2],
+    highest: [f32; 2],
+    sum: [f64; 2],
+    frames: u64,
+    loudness: Option<EbuR128>,
+}
+
+impl LevelMeter {
+    pub fn new(mode: NormalizeMode, sample_rate: u32) -> Result<Self, RenderError> {
+        let loudness = match mode {
+            NormalizeMode::Lufs => Some(EbuR128::new(2, sample_rate, Mode::I)?),
+            _ => None,
+        };
+        Ok(LevelMeter { lowest: [0.0; 2], highest: [0.0; 2], sum: [0.0; 2], frames: 0, loudness })
+    }
+
+    pub fn add(&mut self, left: &[f32], right: &[f32]) -> Result<(), RenderError> {
+        for (channel, samples) in [left, right].into_iter().enumerate() {
+            for &x in samples {
+                self.lowest[channel] = self.lowest[channel].min(x);
+                self.highest[channel] = self.highest[channel].max(x);
+                self.sum[channel] += x as f64;
+            }
+        }
+        self.frames += left.len() as u64;
+        if let Some(loudness) = self.loudness.as_mut() {
+            loudness.add_frames_planar_f32(&[left, right])?;
+        }
+        Ok(())
+    }
+
+    /// Mean of the left and right channel over everything measured so far
+    pub fn dc_offset(&self) -> [f64; 2] {
+        let frames = self.frames.max(1) as f64;
+        self.sum.map(|sum| sum / frames)
+    }
+
+    /// Gain that brings everything measured so far to the target of `mode`, or 1.0 if there is nothing to normalize
+    /// 
+    /// With `remove_dc`, the peak is the one left after subtracting [`LevelMeter::dc_offset`]; loudness is unaffected by DC either way.
+    pub fn gain(&self, mode: NormalizeMode, target_peak_dbfs: f32, target_lufs: f32, remove_dc: bool) -> f64 {
+        let offset = if remove_dc { self.dc_offset() } else { [0.0; 2] };
+        let peak = (0..2)
+            .map(|channel| (self.highest[channel] as f64 - offset[channel]).max(offset[channel] - self.lowest[channel] as f64))
+            .fold(0_f64, f64::max);
+        match mode {
+            NormalizeMode::None => 1.0,
+            NormalizeMode::Peak if peak > 0.0 => db_to_gain(target_peak_dbfs) as f64 / peak,
+            NormalizeMode::Peak => 1.0,
+            NormalizeMode::Lufs => match self.loudness.as_ref().and_then(|loudness| loudness.loudness_global().ok()) {
+                // Silence measures as -inf LUFS
+                Some(lufs) if lufs.is_finite() => 10_f64.powf((target_lufs as f64 - lufs) / 20.0),
+                _ => 1.0,
+            },
+        }
+    }
+}
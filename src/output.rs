@@ -0,0 +1,547 @@
+use std::{fmt, str::FromStr, ffi::OsString, fs::File, io::{BufWriter, Write, Seek}, path::Path, num::{NonZeroU32, NonZeroU8}};
+use flacenc::component::BitRepr;
+use flacenc::error::Verify;
+use vorbis_rs::{VorbisEncoder, VorbisEncoderBuilder, VorbisBitrateManagementStrategy};
+
+use crate::adpcm::AdpcmFileWriter;
+use crate::config::RenderConfig;
+use crate::error::RenderError;
+
+/// Sample encoding of the written files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// 32-bit floating-point
+    #[default]
+    Float32,
+    /// 8-bit unsigned integer PCM with the usual offset of 128 for silence, for genuinely tiny retro files
+    UInt8,
+    /// 16-bit signed integer PCM, the natural container for the ~10-bit output of the NDS
+    Int16,
+    /// 24-bit signed integer PCM
+    Int24,
+    /// Headerless signed integer PCM at the bit depth of the render, see [`RawFileWriter`] for the byte layout
+    Raw,
+}
+
+impl SampleFormat {
+    pub fn bits_per_sample(self) -> u16 {
+        match self {
+            SampleFormat::Float32 => 32,
+            SampleFormat::UInt8 => 8,
+            SampleFormat::Int16 => 16,
+            SampleFormat::Int24 => 24,
+            // Raw samples take their width from the bit depth instead, see `raw_bits_per_sample`
+            SampleFormat::Raw => 16,
+        }
+    }
+
+    pub fn wav_spec(self, channels: u16, sample_rate: u32) -> hound::WavSpec {
+        hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: self.bits_per_sample(),
+            sample_format: match self {
+                SampleFormat::Float32 => hound::SampleFormat::Float,
+                _ => hound::SampleFormat::Int,
+            },
+        }
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "f32" | "float" => Ok(SampleFormat::Float32),
+            "u8" | "8" => Ok(SampleFormat::UInt8),
+            "s16" | "16" => Ok(SampleFormat::Int16),
+            "s24" | "24" => Ok(SampleFormat::Int24),
+            "raw" => Ok(SampleFormat::Raw),
+            _ => Err(format!("unknown sample format `{}` (expected f32, u8, s16, s24 or raw)", s)),
+        }
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SampleFormat::Float32 => "f32",
+            SampleFormat::UInt8 => "u8",
+            SampleFormat::Int16 => "s16",
+            SampleFormat::Int24 => "s24",
+            SampleFormat::Raw => "raw",
+        })
+    }
+}
+
+/// Container and codec of the written files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// RIFF wave-file
+    #[default]
+    Wav,
+    /// Lossless FLAC, which compresses bit-reduced audio extremely well
+    /// 
+    /// FLAC only stores integers, so a floating-point [`SampleFormat`] is written as 16-bit.
+    Flac,
+    /// Lossy Ogg Vorbis for small distributable files, see [`crate::RenderConfig::vorbis_quality`]
+    /// 
+    /// Vorbis adds its own lossy artifacts on top of the intentional ones and cannot represent bit reduction exactly, so feeding it the
+    /// bit-reduced signal keeps the lo-fi sound only approximately. Disabling bit reduction instead hands it the full-precision render.
+    /// The [`SampleFormat`] does not apply.
+    Ogg,
+    /// 4-bit IMA-ADPCM as the NDS plays it, in a wave-file with format tag `0x0011`, see [`crate::AdpcmFileWriter`]
+    /// 
+    /// [`SampleFormat::Raw`] writes the headerless NDS streams instead; otherwise the [`SampleFormat`] does not apply.
+    Adpcm,
+}
+
+impl Codec {
+    /// File extension of files written with this codec
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Wav => "wav",
+            Codec::Flac => "flac",
+            Codec::Ogg => "ogg",
+            Codec::Adpcm => "wav",
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wav" => Ok(Codec::Wav),
+            "flac" => Ok(Codec::Flac),
+            "ogg" | "vorbis" => Ok(Codec::Ogg),
+            "adpcm" => Ok(Codec::Adpcm),
+            _ => Err(format!("unknown codec `{}` (expected wav, flac, ogg or adpcm)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Codec::Adpcm => "adpcm",
+            codec => codec.extension(),
+        })
+    }
+}
+
+/// Byte order of [`SampleFormat::Raw`] samples
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endianness {
+    /// Least significant byte first, as on the NDS and in wave-files
+    #[default]
+    Little,
+    /// Most significant byte first
+    Big,
+}
+
+impl FromStr for Endianness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "little" | "le" => Ok(Endianness::Little),
+            "big" | "be" => Ok(Endianness::Big),
+            _ => Err(format!("unknown byte order `{}` (expected little or big)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Endianness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Endianness::Little => "little",
+            Endianness::Big => "big",
+        })
+    }
+}
+
+/// Width of the samples written by [`RawFileWriter`] for a render at `bitdepth`, which is `bitdepth` rounded up to whole bytes
+/// 
+/// A `bitdepth` of 0 (no bit reduction) is written as 16-bit, and anything above 24 as 32-bit.
+pub fn raw_bits_per_sample(bitdepth: u8) -> u32 {
+    match bitdepth {
+        0 => 16,
+        bitdepth => (bitdepth.min(32) as u32).div_ceil(8) * 8,
+    }
+}
+
+/// Converts `x` from the range [-1.0, 1.0] to a signed `bits`-bit integer, rounding to the nearest value and clamping to the integer range
+/// 
+/// Full scale is 2^(`bits`-1), so the two's complement levels produced by [`crate::RenderConfig::nds_clip`] map onto integers exactly.
+pub fn to_signed_int(x: f32, bits: u32) -> i32 {
+    let scale = (1_i64 << (bits - 1)) as f32;
+    (x * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
+/// Converts `x` from the range [-1.0, 1.0] to an unsigned 8-bit sample, where 128 is silence
+pub fn to_unsigned_u8(x: f32) -> u8 {
+    (to_signed_int(x, 8) + 128) as u8
+}
+
+/// A mono or stereo wave-file that rendered blocks can be appended to
+/// 
+/// It writes to a buffered file by default, but any seekable sink works, see [`WavFileWriter::new`].
+pub struct WavFileWriter<W: Write + Seek = BufWriter<File>> {
+    writer: hound::WavWriter<W>,
+    channels: u16,
+    format: SampleFormat,
+}
+
+impl WavFileWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16, format: SampleFormat) -> Result<Self, RenderError> {
+        WavFileWriter::new(BufWriter::new(File::create(path)?), sample_rate, channels, format)
+    }
+}
+
+impl<W: Write + Seek> WavFileWriter<W> {
+    /// Starts a wave-file in `sink`, which has to be seekable so that the header sizes can be filled in by [`WavFileWriter::finalize`]
+    pub fn new(sink: W, sample_rate: u32, channels: u16, format: SampleFormat) -> Result<Self, RenderError> {
+        let writer = hound::WavWriter::new(sink, format.wav_spec(channels, sample_rate))?;
+        Ok(WavFileWriter { writer, channels, format })
+    }
+
+    /// Appends a block of the `left` and `right` channels; a mono file only takes `left`
+    pub fn write(&mut self, left: &[f32], right: &[f32]) -> Result<(), RenderError> {
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            self.write_sample(l)?;
+            if self.channels > 1 {
+                self.write_sample(r)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sample(&mut self, x: f32) -> Result<(), RenderError> {
+        match self.format {
+            SampleFormat::Float32 => self.writer.write_sample(x)?,
+            // hound stores 8-bit samples unsigned itself, adding the offset of 128 to the signed value given to it
+            SampleFormat::UInt8 => self.writer.write_sample(to_signed_int(x, 8) as i8)?,
+            // A wave-file always has a header, so raw samples fall back to its 16-bit layout
+            SampleFormat::Int16 | SampleFormat::Raw => self.writer.write_sample(to_signed_int(x, 16) as i16)?,
+            SampleFormat::Int24 => self.writer.write_sample(to_signed_int(x, 24))?,
+        }
+        Ok(())
+    }
+
+    /// Writes the final header sizes; the file is incomplete until this is called
+    pub fn finalize(self) -> Result<(), RenderError> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
+
+/// A mono or stereo wave-file written straight into a sink that cannot seek, such as a pipe
+/// 
+/// The length is not known up front, so the header gives the RIFF and data chunks the largest size there is,
+/// which tools reading from a pipe like ffmpeg, sox and aplay take as "until the end of the stream".
+pub struct WavStreamWriter<W: Write> {
+    writer: W,
+    channels: u16,
+    format: SampleFormat,
+}
+
+impl<W: Write> WavStreamWriter<W> {
+    /// Starts a wave-file in `sink` by writing its header
+    pub fn new(mut sink: W, sample_rate: u32, channels: u16, format: SampleFormat) -> Result<Self, RenderError> {
+        let bits_per_sample = format.bits_per_sample();
+        let block_align = channels * bits_per_sample / 8;
+        // WAVE_FORMAT_IEEE_FLOAT or WAVE_FORMAT_PCM
+        let format_tag: u16 = if format == SampleFormat::Float32 { 3 } else { 1 };
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16_u32.to_le_bytes());
+        header.extend_from_slice(&format_tag.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&bits_per_sample.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        sink.write_all(&header)?;
+        Ok(WavStreamWriter { writer: sink, channels, format })
+    }
+
+    /// Appends a block of the `left` and `right` channels; a mono file only takes `left`
+    pub fn write(&mut self, left: &[f32], right: &[f32]) -> Result<(), RenderError> {
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            self.write_sample(l)?;
+            if self.channels > 1 {
+                self.write_sample(r)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sample(&mut self, x: f32) -> Result<(), RenderError> {
+        match self.format {
+            SampleFormat::Float32 => self.writer.write_all(&x.to_le_bytes())?,
+            SampleFormat::UInt8 => self.writer.write_all(&[to_unsigned_u8(x)])?,
+            // Like `WavFileWriter`, raw samples fall back to the 16-bit layout of a wave-file
+            SampleFormat::Int16 | SampleFormat::Raw => self.writer.write_all(&(to_signed_int(x, 16) as i16).to_le_bytes())?,
+            SampleFormat::Int24 => self.writer.write_all(&to_signed_int(x, 24).to_le_bytes()[..3])?,
+        }
+        Ok(())
+    }
+
+    /// Flushes the samples written so far; the header needs nothing more
+    pub fn finalize(mut self) -> Result<(), RenderError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A headerless file of raw mono or stereo PCM that rendered blocks can be appended to
+/// 
+/// Byte layout
+/// ===========
+/// Frames follow each other directly with no header or padding, each being the left sample followed by the right one
+/// (or just the one sample of a mono file).
+/// Every sample is a two's complement signed integer of [`raw_bits_per_sample`] bits (1 to 4 bytes) in the chosen [`Endianness`],
+/// with full scale at 2^(bits-1) like in a wave-file. A 10-bit render is therefore stored as 16-bit samples whose lowest 6 bits are zero.
+/// 
+/// With nothing to fill in at the end, any sink works, pipes included, see [`RawFileWriter::new`].
+pub struct RawFileWriter<W: Write = BufWriter<File>> {
+    writer: W,
+    channels: u16,
+    bits_per_sample: u32,
+    endian: Endianness,
+}
+
+impl RawFileWriter {
+    pub fn create<P: AsRef<Path>>(path: P, channels: u16, bitdepth: u8, endian: Endianness) -> Result<Self, RenderError> {
+        Ok(RawFileWriter::new(BufWriter::new(File::create(path)?), channels, bitdepth, endian))
+    }
+}
+
+impl<W: Write> RawFileWriter<W> {
+    /// Writes the samples of a render at `bitdepth` into `sink`
+    pub fn new(sink: W, channels: u16, bitdepth: u8, endian: Endianness) -> Self {
+        RawFileWriter {
+            writer: sink,
+            channels,
+            bits_per_sample: raw_bits_per_sample(bitdepth),
+            endian,
+        }
+    }
+
+    /// Appends a block of the `left` and `right` channels; a mono file only takes `left`
+    pub fn write(&mut self, left: &[f32], right: &[f32]) -> Result<(), RenderError> {
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            self.write_sample(l)?;
+            if self.channels > 1 {
+                self.write_sample(r)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sample(&mut self, x: f32) -> Result<(), RenderError> {
+        let value = to_signed_int(x, self.bits_per_sample);
+        let bytes = (self.bits_per_sample / 8) as usize;
+        match self.endian {
+            Endianness::Little => self.writer.write_all(&value.to_le_bytes()[..bytes])?,
+            Endianness::Big => self.writer.write_all(&value.to_be_bytes()[4 - bytes..])?,
+        }
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> Result<(), RenderError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A mono or stereo FLAC file that rendered blocks can be appended to
+/// 
+/// The encoder works on the whole stream at once, so samples are collected in memory and only encoded by [`FlacFileWriter::finalize`].
+pub struct FlacFileWriter {
+    file: File,
+    sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u32,
+    samples: Vec<i32>,
+}
+
+impl FlacFileWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16, format: SampleFormat) -> Result<Self, RenderError> {
+        let bits_per_sample = match format {
+            SampleFormat::Float32 => 16,
+            format => format.bits_per_sample() as u32,
+        };
+        Ok(FlacFileWriter {
+            file: File::create(path)?,
+            sample_rate,
+            channels,
+            bits_per_sample,
+            samples: Vec::new(),
+        })
+    }
+
+    /// Appends a block of the `left` and `right` channels; a mono file only takes `left`
+    pub fn write(&mut self, left: &[f32], right: &[f32]) -> Result<(), RenderError> {
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            self.samples.push(to_signed_int(l, self.bits_per_sample));
+            if self.channels > 1 {
+                self.samples.push(to_signed_int(r, self.bits_per_sample));
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes everything written so far and writes it to the file
+    pub fn finalize(mut self) -> Result<(), RenderError> {
+        let config = flacenc::config::Encoder::default().into_verified().map_err(|(_, e)| RenderError::Encode(format!("{:?}", e)))?;
+        let source = flacenc::source::MemSource::from_samples(&self.samples, self.channels as usize, self.bits_per_sample as usize, self.sample_rate as usize);
+        let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size).map_err(|e| RenderError::Encode(format!("{:?}", e)))?;
+        let mut sink = flacenc::bitsink::ByteSink::new();
+        stream.write(&mut sink).map_err(|e| RenderError::Encode(format!("{:?}", e)))?;
+        std::io::Write::write_all(&mut self.file, sink.as_slice())?;
+        Ok(())
+    }
+}
+
+/// A mono or stereo Ogg Vorbis file that rendered blocks are encoded into as they arrive
+pub struct OggFileWriter {
+    encoder: VorbisEncoder<BufWriter<File>>,
+    channels: u16,
+}
+
+impl OggFileWriter {
+    /// Creates the file, encoding at `quality` on the usual Vorbis scale from -2 (smallest) to 10 (best)
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16, quality: f32) -> Result<Self, RenderError> {
+        let sample_rate = NonZeroU32::new(sample_rate).ok_or_else(|| RenderError::InvalidConfig("output sample rate must be positive".to_string()))?;
+        let channel_count = NonZeroU8::new(channels as u8).ok_or_else(|| RenderError::InvalidConfig("output needs at least one channel".to_string()))?;
+        let encoder = VorbisEncoderBuilder::new(sample_rate, channel_count, BufWriter::new(File::create(path)?))
+            .map_err(|e| RenderError::Encode(e.to_string()))?
+            .bitrate_management_strategy(VorbisBitrateManagementStrategy::QualityVbr { target_quality: quality / 10.0 })
+            .build()
+            .map_err(|e| RenderError::Encode(e.to_string()))?;
+        Ok(OggFileWriter { encoder, channels })
+    }
+
+    /// Encodes a block of the `left` and `right` channels; a mono file only takes `left`
+    pub fn write(&mut self, left: &[f32], right: &[f32]) -> Result<(), RenderError> {
+        if left.is_empty() {
+            return Ok(());
+        }
+        let block: &[&[f32]] = if self.channels > 1 { &[left, right] } else { &[left] };
+        self.encoder.encode_audio_block(block).map_err(|e| RenderError::Encode(e.to_string()))
+    }
+
+    /// Flushes the encoder and writes the end of the stream
+    pub fn finalize(self) -> Result<(), RenderError> {
+        self.encoder.finish().map_err(|e| RenderError::Encode(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// A writer for any of the supported [`Codec`]s
+pub enum AudioWriter {
+    Wav(WavFileWriter),
+    Flac(FlacFileWriter),
+    Ogg(OggFileWriter),
+    Adpcm(AdpcmFileWriter),
+    Raw(RawFileWriter),
+}
+
+impl AudioWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, config: &RenderConfig) -> Result<Self, RenderError> {
+        let raw = config.format == SampleFormat::Raw;
+        if raw && config.codec != Codec::Adpcm {
+            return Ok(AudioWriter::Raw(RawFileWriter::create(path, config.channels(), config.bitdepth, config.endian)?));
+        }
+        let channels = config.channels();
+        Ok(match config.codec {
+            Codec::Wav => AudioWriter::Wav(WavFileWriter::create(path, sample_rate, channels, config.format)?),
+            Codec::Flac => AudioWriter::Flac(FlacFileWriter::create(path, sample_rate, channels, config.format)?),
+            Codec::Ogg => AudioWriter::Ogg(OggFileWriter::create(path, sample_rate, channels, config.vorbis_quality)?),
+            Codec::Adpcm => AudioWriter::Adpcm(AdpcmFileWriter::create(path, sample_rate, channels, raw)?),
+        })
+    }
+
+    /// Appends a block of the `left` and `right` channels
+    pub fn write(&mut self, left: &[f32], right: &[f32]) -> Result<(), RenderError> {
+        match self {
+            AudioWriter::Wav(writer) => writer.write(left, right),
+            AudioWriter::Flac(writer) => writer.write(left, right),
+            AudioWriter::Ogg(writer) => writer.write(left, right),
+            AudioWriter::Adpcm(writer) => writer.write(left, right),
+            AudioWriter::Raw(writer) => writer.write(left, right),
+        }
+    }
+
+    /// Completes the file; it is not valid until this is called
+    pub fn finalize(self) -> Result<(), RenderError> {
+        match self {
+            AudioWriter::Wav(writer) => writer.finalize(),
+            AudioWriter::Flac(writer) => writer.finalize(),
+            AudioWriter::Ogg(writer) => writer.finalize(),
+            AudioWriter::Adpcm(writer) => writer.finalize(),
+            AudioWriter::Raw(writer) => writer.finalize(),
+        }
+    }
+}
+
+/// Writes the `left` and `right` channels into a stereo wave-file with samples encoded as `format`
+pub fn write_wav<P: AsRef<Path>>(output_file_path: P, left: &[f32], right: &[f32], sample_rate: u32, format: SampleFormat) -> Result<(), RenderError> {
+    let mut writer = WavFileWriter::create(output_file_path, sample_rate, 2, format)?;
+    writer.write(left, right)?;
+    writer.finalize()
+}
+
+/// Writes the `left` and `right` channels as a wave-file of `channels` channels into `sink` with samples encoded as `format`
+/// 
+/// A mono file only takes `left`.
+pub fn write_wav_to<W: Write + Seek>(sink: W, left: &[f32], right: &[f32], sample_rate: u32, channels: u16, format: SampleFormat) -> Result<(), RenderError> {
+    let mut writer = WavFileWriter::new(sink, sample_rate, channels, format)?;
+    writer.write(left, right)?;
+    writer.finalize()
+}
+
+/// Writes the `left` and `right` channels into a file using the codec, sample format and channel count of `config`
+/// 
+/// The file only appears at `output_file_path` once it is complete, see [`write_atomically`].
+pub fn write_audio<P: AsRef<Path>>(output_file_path: P, left: &[f32], right: &[f32], sample_rate: u32, config: &RenderConfig) -> Result<(), RenderError> {
+    write_atomically(output_file_path, |path| write_audio_in_place(path, left, right, sample_rate, config))
+}
+
+/// Same as [`write_audio`], but writing straight into `output_file_path`
+pub(crate) fn write_audio_in_place(output_file_path: &Path, left: &[f32], right: &[f32], sample_rate: u32, config: &RenderConfig) -> Result<(), RenderError> {
+    let mut writer = AudioWriter::create(output_file_path, sample_rate, config)?;
+    writer.write(left, right)?;
+    writer.finalize()
+}
+
+/// Runs `write` on a temporary file next to `output_file_path` and renames it to `output_file_path` once `write` succeeds
+/// 
+/// A write that fails is cleaned up after, and one that is killed leaves at most the hidden `.partial` file behind,
+/// so a file at `output_file_path` is always complete, even if the file it replaces was not.
+pub fn write_atomically<P, F>(output_file_path: P, write: F) -> Result<(), RenderError>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&Path) -> Result<(), RenderError>,
+{
+    let path = output_file_path.as_ref();
+    let file_name = path.file_name().ok_or_else(|| RenderError::InvalidConfig(format!("output path {} does not name a file", path.display())))?;
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(".partial");
+    let temporary = path.with_file_name(temporary_name);
+    let result = write(&temporary).and_then(|_| Ok(std::fs::rename(&temporary, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result
+}
@@ -0,0 +1,113 @@
+use std::{path::Path, sync::{Arc, Mutex, mpsc::{self, Receiver, TryRecvError}}, time::Duration};
+use cpal::{FromSample, SizedSample, StreamConfig, traits::{DeviceTrait, HostTrait, StreamTrait}};
+use rustysynth::SoundFont;
+
+use crate::config::RenderConfig;
+use crate::error::RenderError;
+use crate::interleave::to_interleaved;
+use crate::layer::SoundFontLayer;
+use crate::render::{render_file_streaming_with_progress, DEFAULT_CHUNK_SIZE};
+use crate::stats::RenderStats;
+
+/// Blocks rendered ahead of the device, which is how far playback lags behind changes to the render
+const QUEUED_BLOCKS: usize = 4;
+
+/// Renders the MIDI file at `input_file_path` like [`crate::render_file_streaming_with_progress`] and plays it on the default output device as it goes,
+/// returning once the device has played all of it, or only on an error when `looping`
+/// 
+/// The render is resampled to the rate of the device, since most of them cannot play it at the NDS rate. Rendering happens on the calling thread
+/// and is held back to stay a few blocks ahead of the device, so `progress` tracks playback rather than running ahead to 1.0.
+pub fn play_file<P, G>(sound_font: &Arc<SoundFont>, layers: &[SoundFontLayer], input_file_path: P, config: &RenderConfig, looping: bool, mut progress: G) -> Result<RenderStats, RenderError>
+where
+    P: AsRef<Path>,
+    G: FnMut(f32),
+{
+    let device = cpal::default_host().default_output_device().ok_or_else(|| RenderError::Playback("there is no audio output device".to_string()))?;
+    let supported = device.default_output_config().map_err(playback_error)?;
+    let sample_format = supported.sample_format();
+    let stream_config: StreamConfig = supported.into();
+    let config = RenderConfig { output_rate: Some(stream_config.sample_rate.0), ..config.clone() };
+
+    let (sender, receiver) = mpsc::sync_channel::<Vec<f32>>(QUEUED_BLOCKS);
+    let (finished_sender, finished) = mpsc::channel::<()>();
+    let failure = Arc::new(Mutex::new(None));
+    let mono = config.mono.is_some();
+    let stream = match sample_format {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &stream_config, receiver, mono, finished_sender, failure.clone()),
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &stream_config, receiver, mono, finished_sender, failure.clone()),
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, &stream_config, receiver, mono, finished_sender, failure.clone()),
+        format => return Err(RenderError::Playback(format!("the output device takes {} samples, which are not supported", format))),
+    }?;
+    stream.play().map_err(playback_error)?;
+
+    loop {
+        let stats = render_file_streaming_with_progress(sound_font, layers, &input_file_path, &config, DEFAULT_CHUNK_SIZE, |left, right| {
+            // Only fails once the stream is gone, which leaves the error to report below
+            let _ = sender.send(to_interleaved(left, right));
+        }, &mut progress)?;
+        if let Some(error) = failure.lock().expect("no panics while holding the lock").take() {
+            return Err(RenderError::Playback(error));
+        }
+        if !looping {
+            drop(sender);
+            // Also returns if the stream stops calling back, rather than waiting forever
+            let _ = finished.recv_timeout(Duration::from_secs_f64(QUEUED_BLOCKS as f64 * DEFAULT_CHUNK_SIZE as f64 / config.nds_rate as f64 + 1.0));
+            return Ok(stats);
+        }
+    }
+}
+
+/// An output stream on `device` playing the interleaved stereo blocks from `blocks`, filling in silence whenever rendering falls behind
+/// 
+/// Once `blocks` is closed and played out, `finished` is signalled. Errors of the device end up in `failure`.
+fn build_stream<T>(device: &cpal::Device, config: &StreamConfig, blocks: Receiver<Vec<f32>>, mono: bool, finished: mpsc::Sender<()>, failure: Arc<Mutex<Option<String>>>) -> Result<cpal::Stream, RenderError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut block: Vec<f32> = Vec::new();
+    let mut position = 0;
+    let mut closed = false;
+    let data = move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+        for frame in output.chunks_mut(channels) {
+            while position >= block.len() && !closed {
+                match blocks.try_recv() {
+                    Ok(next) => {
+                        block = next;
+                        position = 0;
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        closed = true;
+                        let _ = finished.send(());
+                    }
+                }
+            }
+            let (left, right) = match block.get(position..position + 2) {
+                Some(&[left, right]) => {
+                    position += 2;
+                    (left, right)
+                }
+                _ => (0.0, 0.0),
+            };
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                // A mono render has the same samples on both channels, which then go to every speaker
+                let x = match channel {
+                    0 => left,
+                    1 => right,
+                    _ if mono => left,
+                    _ => 0.0,
+                };
+                *sample = T::from_sample(x);
+            }
+        }
+    };
+    let error = move |error: cpal::StreamError| {
+        *failure.lock().expect("no panics while holding the lock") = Some(error.to_string());
+    };
+    device.build_output_stream(config, data, error, None).map_err(playback_error)
+}
+
+fn playback_error<E: std::error::Error>(error: E) -> RenderError {
+    RenderError::Playback(error.to_string())
+}
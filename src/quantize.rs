@@ -0,0 +1,366 @@
+use crate::config::{RenderConfig, DitherMode, QuantizationMode, OverflowMode, RoundingMode};
+use crate::dither::DitherRng;
+
+/// Quantizes `x` to a signed `bitdepth`-bit resolution while keeping it in floating-point
+/// 
+/// A `bitdepth` of 0 leaves `x` untouched, and 1 bit only keeps the sign of `x`.
+pub fn quantize_to_bitdepth(x: f32, bitdepth: u8, mode: QuantizationMode, rounding: RoundingMode) -> f32 {
+    if bitdepth == 0 {
+        return x;
+    }
+    quantize_with_mode(x, n_half_for_bitdepth(bitdepth), mode, rounding)
+}
+
+/// Number of positive quantization levels of a signed `bitdepth`-bit resolution, `n_half = 2^(bitdepth-1) - 1`
+/// 
+/// Bit depths above 32 are treated as 32, and both 0 and 1 give 0.
+pub fn n_half_for_bitdepth(bitdepth: u8) -> u32 {
+    if bitdepth == 0 {
+        return 0;
+    }
+    ((1_u64 << (bitdepth.min(32) - 1)) - 1) as u32
+}
+
+/// Quantizes `x` onto `n_half` steps per polarity with the level placement given by `mode`, rounding mid-tread levels with `rounding`
+pub fn quantize_with_mode(x: f32, n_half: u32, mode: QuantizationMode, rounding: RoundingMode) -> f32 {
+    match mode {
+        QuantizationMode::MidTread => quantize_f32(x, n_half, rounding),
+        QuantizationMode::MidRise => quantize_f32_mid_rise(x, n_half),
+    }
+}
+
+/// A simple linear quantization of a floating-point number `x` within a range of [-1.0, 1.0] by projecting the number onto a range of integers [-`n_half`, `n_half`]
+/// 
+/// Note
+/// ====
+/// For quantizing a 32-bit floating point number to an `n`-bit floating point number, set `n_half` to be 
+/// `n_half = 2^(n-1) - 1`
+/// 
+/// An `n_half` of 0 has no levels to project onto, so only the sign of `x` is kept. Values between two levels go to one of them by `rounding`.
+pub fn quantize_f32(x: f32, n_half: u32, rounding: RoundingMode) -> f32 {
+    if n_half == 0 {
+        return quantize_sign(x);
+    }
+    rounding.round(x * n_half as f32) / n_half as f32
+}
+
+/// The mid-rise counterpart of [`quantize_f32`], projecting `x` onto the half-integer levels [-`n_half` + ½, `n_half` - ½]
+/// 
+/// There is no zero level, so even silence comes out as ±½ LSB.
+pub fn quantize_f32_mid_rise(x: f32, n_half: u32) -> f32 {
+    if n_half == 0 {
+        return quantize_sign(x);
+    }
+    ((x * n_half as f32).floor() + 0.5) / n_half as f32
+}
+
+/// Two's complement style quantization of `x` onto the integers [-`n_negative`, `n_positive`], scaled so that -1.0 maps onto -`n_negative`
+/// 
+/// Note
+/// ====
+/// With `n_negative = 2^(n-1)` and `n_positive = 2^(n-1) - 1` this reproduces the asymmetric full scale of a signed `n`-bit integer,
+/// e.g. [-512, 511] for 10 bits. Values beyond either end are clipped to it.
+pub fn quantize_f32_asymmetric(x: f32, n_negative: u32, n_positive: u32) -> f32 {
+    if n_negative == 0 {
+        return quantize_sign(x);
+    }
+    (x * n_negative as f32).round().clamp(-(n_negative as f32), n_positive as f32) / n_negative as f32
+}
+
+/// Quantizes `samples` in place onto the levels of a signed `bits`-bit integer, as [`crate::to_signed_int`] would store them
+/// 
+/// This is the second stage of [`crate::RenderConfig::output_bitdepth`], which keeps the result in floating-point.
+pub fn quantize_to_output_bits(samples: &mut [f32], bits: u8) {
+    let n_negative = 1_u32 << (bits.clamp(2, 32) - 1).min(31);
+    for x in samples.iter_mut() {
+        *x = quantize_f32_asymmetric(*x, n_negative, n_negative - 1);
+    }
+}
+
+/// Lazy bit reduction of any iterator of samples, for chaining it with other processing without buffering in between
+pub trait QuantizeExt: Iterator<Item = f32> + Sized {
+    /// Quantizes each sample like [`quantize_to_bitdepth`] as it is yielded, mid-tread and rounding to the nearest level
+    /// unless changed with [`QuantizeToBitdepth::mode`] and [`QuantizeToBitdepth::rounding`]
+    fn quantize_to_bitdepth(self, bitdepth: u8) -> QuantizeToBitdepth<Self> {
+        QuantizeToBitdepth { samples: self, bitdepth, mode: QuantizationMode::MidTread, rounding: RoundingMode::Nearest }
+    }
+}
+
+impl<I: Iterator<Item = f32>> QuantizeExt for I {}
+
+/// The iterator of [`QuantizeExt::quantize_to_bitdepth`]
+#[derive(Clone, Debug)]
+pub struct QuantizeToBitdepth<I> {
+    samples: I,
+    bitdepth: u8,
+    mode: QuantizationMode,
+    rounding: RoundingMode,
+}
+
+impl<I> QuantizeToBitdepth<I> {
+    /// Places the levels by `mode` instead
+    pub fn mode(self, mode: QuantizationMode) -> Self {
+        QuantizeToBitdepth { mode, ..self }
+    }
+
+    /// Rounds onto the levels by `rounding` instead
+    pub fn rounding(self, rounding: RoundingMode) -> Self {
+        QuantizeToBitdepth { rounding, ..self }
+    }
+}
+
+impl<I: Iterator<Item = f32>> Iterator for QuantizeToBitdepth<I> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.samples.next().map(|x| quantize_to_bitdepth(x, self.bitdepth, self.mode, self.rounding))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.samples.size_hint()
+    }
+}
+
+impl<I: ExactSizeIterator<Item = f32>> ExactSizeIterator for QuantizeToBitdepth<I> {}
+
+/// 1-bit quantization, mapping `x` to -1.0 or 1.0 depending on its sign
+fn quantize_sign(x: f32) -> f32 {
+    if x < 0.0 { -1.0 } else { 1.0 }
+}
+
+/// Coefficients `c` of the error-feedback filter used by [`DitherMode::Shaped`]
+/// 
+/// The quantization error `e` of past samples is fed back as `c[0]·e[n-1] + c[1]·e[n-2]`, which shapes the noise spectrum by
+/// `1 + c[0]·z^-1 + c[1]·z^-2`. The default `(1 - z^-1)^2` is a second-order high-pass that moves the noise away from the low and mid frequencies.
+pub const NOISE_SHAPING_COEFFICIENTS: [f32; 2] = [-2.0, 1.0];
+
+/// Bit reduction as applied to the rendered blocks, carrying the dither and noise-shaping state from one block to the next
+pub struct Quantizer {
+    bitdepth: u8,
+    dither: DitherMode,
+    mode: QuantizationMode,
+    rounding: RoundingMode,
+    nds_clip: bool,
+    overflow: OverflowMode,
+    rng: DitherRng,
+    /// Past quantization errors of each channel, most recent first
+    errors: [[f32; 2]; 2],
+}
+
+impl Quantizer {
+    pub fn new(config: &RenderConfig) -> Self {
+        Quantizer {
+            bitdepth: config.bitdepth,
+            dither: config.dither,
+            mode: config.quantization,
+            rounding: config.rounding,
+            nds_clip: config.nds_clip,
+            overflow: config.overflow,
+            rng: DitherRng::new(config.seed),
+            errors: [[0.0; 2]; 2],
+        }
+    }
+
+    /// Quantizes both channels in place
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        if self.bitdepth == 0 {
+            return;
+        }
+        // 1 bit has no levels to round onto, so like `quantize_to_bitdepth` only the sign is kept
+        if n_half_for_bitdepth(self.bitdepth) == 0 {
+            for x in left.iter_mut().chain(right.iter_mut()) {
+                *x = quantize_sign(*x);
+            }
+            return;
+        }
+        let levels = self.levels();
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            *l = self.quantize(*l, &levels, 0);
+            *r = self.quantize(*r, &levels, 1);
+        }
+    }
+
+    fn levels(&self) -> Levels {
+        let n_half = n_half_for_bitdepth(self.bitdepth) as f32;
+        if self.nds_clip {
+            Levels { scale: n_half + 1.0, lowest: -(n_half + 1.0), highest: n_half, mid_rise: false, rounding: self.rounding }
+        } else {
+            match self.mode {
+                QuantizationMode::MidTread => Levels { scale: n_half, lowest: -n_half, highest: n_half, mid_rise: false, rounding: self.rounding },
+                QuantizationMode::MidRise => Levels { scale: n_half, lowest: 0.5 - n_half, highest: n_half - 0.5, mid_rise: true, rounding: self.rounding },
+            }
+        }
+    }
+
+    fn quantize(&mut self, x: f32, levels: &Levels, channel: usize) -> f32 {
+        let lsb = 1.0 / levels.scale;
+        let code = match self.dither {
+            DitherMode::None => levels.code(x),
+            DitherMode::Tpdf => {
+                let noise = self.rng.tpdf() * lsb;
+                levels.code(x + noise)
+            }
+            DitherMode::Shaped => {
+                let errors = self.errors[channel];
+                let v = x + NOISE_SHAPING_COEFFICIENTS[0] * errors[0] + NOISE_SHAPING_COEFFICIENTS[1] * errors[1];
+                let noise = self.rng.tpdf() * lsb;
+                let code = levels.code(v + noise);
+                // The error is taken before overflow handling, a clipped sample would otherwise feed back a huge error
+                self.errors[channel] = [code / levels.scale - v, errors[0]];
+                code
+            }
+        };
+        levels.handle_overflow(code, self.overflow) / levels.scale
+    }
+}
+
+/// The quantization levels of a [`Quantizer`], as integer (or half-integer for mid-rise) codes in `lowest..=highest` that are divided by `scale`
+struct Levels {
+    scale: f32,
+    lowest: f32,
+    highest: f32,
+    mid_rise: bool,
+    rounding: RoundingMode,
+}
+
+impl Levels {
+    fn code(&self, x: f32) -> f32 {
+        if self.mid_rise {
+            (x * self.scale).floor() + 0.5
+        } else {
+            self.rounding.round(x * self.scale)
+        }
+    }
+
+    fn handle_overflow(&self, code: f32, overflow: OverflowMode) -> f32 {
+        match overflow {
+            OverflowMode::Clamp => code.clamp(self.lowest, self.highest),
+            OverflowMode::Wrap => {
+                let width = self.highest - self.lowest + 1.0;
+                self.lowest + (code - self.lowest).rem_euclid(width)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [RoundingMode; 4] = [RoundingMode::Nearest, RoundingMode::HalfUp, RoundingMode::TowardZero, RoundingMode::Floor];
+
+    /// Runs `x` through a [`Quantizer`] for `bitdepth` bits with the level placement `mode` and `rounding`, returning the code it lands on
+    fn quantizer_code(x: f32, bitdepth: u8, mode: QuantizationMode, rounding: RoundingMode) -> f32 {
+        let config = RenderConfig { bitdepth, quantization: mode, rounding, ..Default::default() };
+        let (mut left, mut right) = ([x], [x]);
+        Quantizer::new(&config).process(&mut left, &mut right);
+        assert_eq!(left, right);
+        left[0] * n_half_for_bitdepth(bitdepth) as f32
+    }
+
+    #[test]
+    fn quantizer_matches_quantize_to_bitdepth() {
+        let samples: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.0137).sin() * 1.2).collect();
+        for bitdepth in [2, 5, 10, 16] {
+            for mode in [QuantizationMode::MidTread, QuantizationMode::MidRise] {
+                let config = RenderConfig { bitdepth, quantization: mode, ..Default::default() };
+                let (mut left, mut right) = (samples.clone(), samples.clone());
+                Quantizer::new(&config).process(&mut left, &mut right);
+                // Out of range samples clamp to the outermost level
+                let expected: Vec<f32> = samples.iter().map(|&x| {
+                    let n_half = n_half_for_bitdepth(bitdepth) as f32;
+                    let limit = if mode == QuantizationMode::MidRise { (n_half - 0.5) / n_half } else { 1.0 };
+                    quantize_to_bitdepth(x, bitdepth, mode, RoundingMode::Nearest).clamp(-limit, limit)
+                }).collect();
+                assert_eq!(left, expected, "{} bits {:?}", bitdepth, mode);
+                assert_eq!(right, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn quantizer_carries_its_state_across_blocks() {
+        let samples: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+        for dither in [DitherMode::Tpdf, DitherMode::Shaped] {
+            let config = RenderConfig { dither, ..Default::default() };
+            let (mut left, mut right) = (samples.clone(), samples.clone());
+            Quantizer::new(&config).process(&mut left, &mut right);
+            let mut quantizer = Quantizer::new(&config);
+            let (mut block_left, mut block_right) = (samples.clone(), samples.clone());
+            for (l, r) in block_left.chunks_mut(77).zip(block_right.chunks_mut(77)) {
+                quantizer.process(l, r);
+            }
+            assert_eq!(left, block_left, "{:?}", dither);
+            assert_eq!(right, block_right);
+            // Dithered renders are deterministic, but never exactly the undithered quantization
+            assert_ne!(left, samples.iter().map(|&x| quantize_to_bitdepth(x, config.bitdepth, config.quantization, config.rounding)).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn one_bit_keeps_the_sign() {
+        for mode in [QuantizationMode::MidTread, QuantizationMode::MidRise] {
+            let config = RenderConfig { bitdepth: 1, quantization: mode, ..Default::default() };
+            let mut left = [0.0, 0.001, -0.001, 0.6, -0.6, 1.0, -1.0];
+            let mut right = left;
+            let expected = left.map(|x| quantize_to_bitdepth(x, 1, mode, RoundingMode::Nearest));
+            assert_eq!(expected, [1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0]);
+            Quantizer::new(&config).process(&mut left, &mut right);
+            assert_eq!(left, expected);
+            assert_eq!(right, expected);
+        }
+    }
+
+    #[test]
+    fn mid_tread_rounding_modes() {
+        // Input in LSBs, then the code for Nearest, HalfUp, TowardZero and Floor
+        let table: [(f32, [f32; 4]); 8] = [
+            (0.5, [1.0, 1.0, 0.0, 0.0]),
+            (-0.5, [-1.0, 0.0, 0.0, -1.0]),
+            (1.5, [2.0, 2.0, 1.0, 1.0]),
+            (-1.5, [-2.0, -1.0, -1.0, -2.0]),
+            (1.25, [1.0, 1.0, 1.0, 1.0]),
+            (-1.25, [-1.0, -1.0, -1.0, -2.0]),
+            (2.75, [3.0, 3.0, 2.0, 2.0]),
+            (-2.75, [-3.0, -3.0, -2.0, -3.0]),
+        ];
+        for (lsbs, codes) in table {
+            for (rounding, code) in MODES.into_iter().zip(codes) {
+                assert_eq!(quantize_f32(lsbs / 4.0, 4, rounding), code / 4.0, "{} LSB rounded by {:?}", lsbs, rounding);
+                assert_eq!(quantize_with_mode(lsbs / 4.0, 4, QuantizationMode::MidTread, rounding), code / 4.0);
+            }
+        }
+        // Through a quantizer, at 2 bits where the ties are exactly ±0.5
+        for (x, codes) in [(0.5, [1.0, 1.0, 0.0, 0.0]), (-0.5, [-1.0, 0.0, 0.0, -1.0]), (0.25, [0.0; 4]), (-0.25, [0.0, 0.0, 0.0, -1.0])] {
+            for (rounding, code) in MODES.into_iter().zip(codes) {
+                assert_eq!(quantizer_code(x, 2, QuantizationMode::MidTread, rounding), code, "{} rounded by {:?}", x, rounding);
+            }
+        }
+    }
+
+    #[test]
+    fn mid_rise_ignores_the_rounding_mode() {
+        // Input in LSBs, then the half-integer code; ties between two levels go up
+        let table: [(f32, f32); 8] = [
+            (0.0, 0.5),
+            (1.0, 1.5),
+            (-1.0, -0.5),
+            (-2.0, -1.5),
+            (1.25, 1.5),
+            (-1.25, -1.5),
+            (2.75, 2.5),
+            (-2.75, -2.5),
+        ];
+        for (lsbs, code) in table {
+            for rounding in MODES {
+                assert_eq!(quantize_with_mode(lsbs / 4.0, 4, QuantizationMode::MidRise, rounding), code / 4.0, "{} LSB rounded by {:?}", lsbs, rounding);
+            }
+        }
+        // At 2 bits the only levels are ±½
+        for (x, code) in [(0.0, 0.5), (-0.25, -0.5), (0.25, 0.5), (-1.0, -0.5), (1.0, 0.5)] {
+            for rounding in MODES {
+                assert_eq!(quantizer_code(x, 2, QuantizationMode::MidRise, rounding), code, "{} rounded by {:?}", x, rounding);
+            }
+        }
+    }
+}
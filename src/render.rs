@@ -0,0 +1,864 @@
+use std::{sync::Arc, path::Path, fs::OpenOptions, io::{Cursor, Write, Seek}};
+use rustysynth::{SoundFont, SynthesizerSettings, MidiFile};
+
+use crate::config::RenderConfig;
+use crate::dsp::{Biquad, BiquadChain, SampleHold, StereoFilter, nds_dac_filter, soft_clip, stereo_width};
+use crate::error::RenderError;
+use crate::fade::Fades;
+use crate::image::{write_spectrogram_png, write_waveform_png, SPECTROGRAM_WIDTH, SPECTROGRAM_HEIGHT, WAVEFORM_WIDTH, WAVEFORM_HEIGHT};
+use crate::layer::{SoundFontLayer, LayerFile, RepeatingSequencer, split_layers};
+use crate::load::{load_midi_file_with_events, load_sound_font_from_bytes, load_midi_file_from_bytes};
+use crate::midi::{Smf, TrackEvent, EventKind, DRUM_CHANNEL};
+use crate::mix::downmix_mono;
+use crate::normalize::{NormalizeMode, LevelMeter, db_to_gain};
+use crate::output::{write_atomically, write_audio, write_audio_in_place, AudioWriter, WavFileWriter, WavStreamWriter, RawFileWriter, SampleFormat, Codec};
+use crate::quantize::{Quantizer, quantize_to_output_bits};
+use crate::resample::{StereoDecimator, StereoResampler};
+use crate::riff::{append_chunk, smpl_chunk};
+use crate::silence::{SilenceDetector, SilenceTrimmer};
+use crate::stats::{OutputHasher, RenderStats, StatsAccumulator};
+use crate::transform::{transform_midi, window};
+
+/// Renders the MIDI file at `input_file_path` through `sound_font` into an audio file at `output_file_path`
+/// 
+/// When the MIDI file has a loop, see [`RenderConfig::loop_region`], a wave-file also gets a `smpl` chunk so that players and samplers can loop it seamlessly.
+/// Unless `config` asks for images, the file is written block by block as it renders, so even hours of a looped file only take a few blocks of memory
+/// (FLAC and headerless ADPCM excepted, see [`crate::FlacFileWriter`] and [`crate::AdpcmFileWriter`]).
+pub fn render<P: AsRef<Path>>(sound_font: Arc<SoundFont>, input_file_path: P, output_file_path: P, config: &RenderConfig) -> Result<RenderStats, RenderError> {
+    render_with_progress(sound_font, input_file_path, output_file_path, config, |_| {})
+}
+
+/// Same as [`render`], but reports the fraction of the file rendered so far (from 0.0 to 1.0) to `progress`
+pub fn render_with_progress<P, G>(sound_font: Arc<SoundFont>, input_file_path: P, output_file_path: P, config: &RenderConfig, progress: G) -> Result<RenderStats, RenderError>
+where
+    P: AsRef<Path>,
+    G: FnMut(f32),
+{
+    render_layered_with_progress(sound_font, &[], input_file_path, output_file_path, config, progress)
+}
+
+/// Same as [`render_with_progress`], but plays the channels of each of `layers` through the soundfont of that layer instead of `sound_font`
+pub fn render_layered_with_progress<P, G>(sound_font: Arc<SoundFont>, layers: &[SoundFontLayer], input_file_path: P, output_file_path: P, config: &RenderConfig, progress: G) -> Result<RenderStats, RenderError>
+where
+    P: AsRef<Path>,
+    G: FnMut(f32),
+{
+    // The images need the whole render at once; otherwise blocks go to the file as they are rendered, keeping memory bounded however long the render
+    if config.waveform.is_some() || config.spectrogram.is_some() {
+        let FileRender { left, right, stats, loop_region } = render_file_to_buffers(&sound_font, layers, input_file_path, config, progress)?;
+        write_atomically(&output_file_path, |path| {
+            write_audio_in_place(path, &left, &right, config.output_sample_rate(), config)?;
+            append_loop_chunk(path, loop_region, config)
+        })?;
+        write_images(&output_file_path, &left, &right, config)?;
+        return Ok(stats);
+    }
+
+    let (midi_file, smf) = load_midi_file_with_events(input_file_path)?;
+    let mut stats = RenderStats::default();
+    write_atomically(&output_file_path, |path| {
+        let mut writer = AudioWriter::create(path, config.output_sample_rate(), config)?;
+        let (render, loop_region) = render_midi_into(&sound_font, layers, midi_file, smf, config, |left, right| writer.write(left, right), progress)?;
+        writer.finalize()?;
+        stats = render;
+        append_loop_chunk(path, loop_region, config)
+    })?;
+    Ok(stats)
+}
+
+/// Appends a `smpl` chunk for `loop_region` to the finished file at `path` if it is a wave-file of samples
+fn append_loop_chunk(path: &Path, loop_region: Option<(u32, u32)>, config: &RenderConfig) -> Result<(), RenderError> {
+    if config.codec == Codec::Wav && config.format != SampleFormat::Raw {
+        if let Some((start, end)) = loop_region {
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            append_chunk(&mut file, b"smpl", &smpl_chunk(config.output_sample_rate(), start, end))?;
+        }
+    }
+    Ok(())
+}
+
+/// How consecutive files of [`render_concat_with_progress`] are joined together
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConcatJoin {
+    /// Seconds of silence between the end of one file and the start of the next, 0.0 for gapless
+    Gap(f64),
+    /// Seconds over which the end of each file crossfades into the start of the next one, overlapping them
+    Crossfade(f64),
+}
+
+impl Default for ConcatJoin {
+    fn default() -> Self {
+        ConcatJoin::Gap(0.0)
+    }
+}
+
+/// Renders the MIDI files at `input_file_paths` one after the other into a single audio file at `output_file_path`, returning the stats of each
+/// 
+/// Every file is rendered by a synthesizer of its own, so that nothing of one bleeds into the next, and is processed like a file of its own,
+/// e.g. normalized and trimmed by itself. Crossfades mix the renders after bit reduction. `progress` gets the fraction of all files rendered so far.
+pub fn render_concat_with_progress<P, Q, G>(sound_font: Arc<SoundFont>, layers: &[SoundFontLayer], input_file_paths: &[P], output_file_path: Q, config: &RenderConfig, join: ConcatJoin, mut progress: G) -> Result<Vec<RenderStats>, RenderError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    G: FnMut(f32),
+{
+    let to_frames = |seconds: f64| (seconds.max(0.0) * config.output_sample_rate() as f64).round() as usize;
+    let mut left: Vec<f32> = Vec::new();
+    let mut right: Vec<f32> = Vec::new();
+    let mut stats = Vec::with_capacity(input_file_paths.len());
+    let count = input_file_paths.len() as f32;
+    for (index, input_file_path) in input_file_paths.iter().enumerate() {
+        let render = render_file_to_buffers(&sound_font, layers, input_file_path, config, |fraction| progress((index as f32 + fraction) / count))?;
+        match join {
+            _ if index == 0 => {
+                left.extend_from_slice(&render.left);
+                right.extend_from_slice(&render.right);
+            }
+            ConcatJoin::Gap(gap) => {
+                left.resize(left.len() + to_frames(gap), 0.0);
+                right.resize(right.len() + to_frames(gap), 0.0);
+                left.extend_from_slice(&render.left);
+                right.extend_from_slice(&render.right);
+            }
+            ConcatJoin::Crossfade(crossfade) => {
+                let overlap = to_frames(crossfade).min(left.len()).min(render.left.len());
+                let start = left.len() - overlap;
+                // Equal power, since consecutive files have nothing to do with each other
+                for i in 0..overlap {
+                    let t = i as f32 / overlap as f32 * std::f32::consts::FRAC_PI_2;
+                    left[start + i] = left[start + i] * t.cos() + render.left[i] * t.sin();
+                    right[start + i] = right[start + i] * t.cos() + render.right[i] * t.sin();
+                }
+                left.extend_from_slice(&render.left[overlap..]);
+                right.extend_from_slice(&render.right[overlap..]);
+            }
+        }
+        stats.push(render.stats);
+    }
+    write_audio(&output_file_path, &left, &right, config.output_sample_rate(), config)?;
+    write_images(&output_file_path, &left, &right, config)?;
+    Ok(stats)
+}
+
+/// Draws the images of the render `left` and `right` that `config` asks for, named after `output_file_path`
+fn write_images<P: AsRef<Path>>(output_file_path: P, left: &[f32], right: &[f32], config: &RenderConfig) -> Result<(), RenderError> {
+    // Not `with_extension`, which would cut off a soundfont name in the stem
+    let file_name = |extension: &str| {
+        let mut file_name = output_file_path.as_ref().file_stem().unwrap_or_default().to_os_string();
+        file_name.push(".");
+        file_name.push(extension);
+        file_name
+    };
+    if let Some(folder) = &config.waveform {
+        std::fs::create_dir_all(folder)?;
+        let right = (config.channels() == 2).then_some(right);
+        write_waveform_png(folder.join(file_name("png")), left, right, WAVEFORM_WIDTH, WAVEFORM_HEIGHT)?;
+    }
+    if let Some(folder) = &config.spectrogram {
+        std::fs::create_dir_all(folder)?;
+        let path = folder.join(file_name("spectrogram.png"));
+        write_spectrogram_png(path, left, right, config.spectrogram_fft_size, config.spectrogram_window, SPECTROGRAM_WIDTH, SPECTROGRAM_HEIGHT)?;
+    }
+    Ok(())
+}
+
+/// Renders the MIDI file at `input_file_path` through `sound_font` as a wave-file into `sink`, reporting progress like [`render_with_progress`]
+/// 
+/// The wave-file is written with `config.format` regardless of `config.codec`. A wave header can only be completed once the length is known,
+/// so `sink` has to be seekable; a pipe such as stdout takes [`render_to_stream`] instead.
+pub fn render_wav_to_writer<P, W, G>(sound_font: Arc<SoundFont>, input_file_path: P, sink: W, config: &RenderConfig, progress: G) -> Result<RenderStats, RenderError>
+where
+    P: AsRef<Path>,
+    W: Write + Seek,
+    G: FnMut(f32),
+{
+    render_layered_wav_to_writer(sound_font, &[], input_file_path, sink, config, progress)
+}
+
+/// Same as [`render_wav_to_writer`], but plays the channels of each of `layers` through the soundfont of that layer instead of `sound_font`
+pub fn render_layered_wav_to_writer<P, W, G>(sound_font: Arc<SoundFont>, layers: &[SoundFontLayer], input_file_path: P, sink: W, config: &RenderConfig, progress: G) -> Result<RenderStats, RenderError>
+where
+    P: AsRef<Path>,
+    W: Write + Seek,
+    G: FnMut(f32),
+{
+    let (midi_file, smf) = load_midi_file_with_events(input_file_path)?;
+    render_midi_wav_into(&sound_font, layers, midi_file, smf, sink, config, progress)
+}
+
+/// Renders the MIDI file at `input_file_path` through `sound_font` into `sink`, which need not be seekable
+/// 
+/// Every block is written to `sink` as soon as it is rendered, so another program can play or encode it from a pipe while the render goes on.
+/// [`SampleFormat::Raw`] writes the bare samples like a [`crate::RawFileWriter`]; any other format makes a wave-file whose header leaves
+/// the sizes open, see [`crate::WavStreamWriter`]. No loop is stored, since that could only be written at the end.
+/// `config.codec` does not apply, see [`RenderConfig::stream`].
+pub fn render_to_stream<P, W, G>(sound_font: Arc<SoundFont>, layers: &[SoundFontLayer], input_file_path: P, sink: W, config: &RenderConfig, progress: G) -> Result<RenderStats, RenderError>
+where
+    P: AsRef<Path>,
+    W: Write,
+    G: FnMut(f32),
+{
+    let (midi_file, smf) = load_midi_file_with_events(input_file_path)?;
+    if config.format == SampleFormat::Raw {
+        let mut writer = RawFileWriter::new(sink, config.channels(), config.bitdepth, config.endian);
+        let (stats, _) = render_midi_into(&sound_font, layers, midi_file, smf, config, |left, right| writer.write(left, right), progress)?;
+        writer.finalize()?;
+        return Ok(stats);
+    }
+    let mut writer = WavStreamWriter::new(sink, config.output_sample_rate(), config.channels(), config.format)?;
+    let (stats, _) = render_midi_into(&sound_font, layers, midi_file, smf, config, |left, right| writer.write(left, right), progress)?;
+    writer.finalize()?;
+    Ok(stats)
+}
+
+/// Renders the MIDI file in `midi_bytes` through the soundfont in `sf2_bytes`, returning the bytes of the resulting wave-file
+/// 
+/// Nothing touches the filesystem, which makes this suitable for servers and sandboxes. Like [`render_wav_to_writer`],
+/// the wave-file is written with `config.format` regardless of `config.codec`.
+pub fn render_from_bytes(sf2_bytes: &[u8], midi_bytes: &[u8], config: &RenderConfig) -> Result<(Vec<u8>, RenderStats), RenderError> {
+    let sound_font = load_sound_font_from_bytes(sf2_bytes)?;
+    let (midi_file, smf) = load_midi_file_from_bytes(midi_bytes)?;
+    let mut sink = Cursor::new(Vec::new());
+    let stats = render_midi_wav_into(&sound_font, &[], midi_file, smf, &mut sink, config, |_| {})?;
+    Ok((sink.into_inner(), stats))
+}
+
+/// Renders `midi_file`, which has the events of `smf`, as a wave-file into `sink`, writing each block as it is rendered
+fn render_midi_wav_into<W, G>(sound_font: &Arc<SoundFont>, layers: &[SoundFontLayer], midi_file: Arc<MidiFile>, smf: Smf, mut sink: W, config: &RenderConfig, progress: G) -> Result<RenderStats, RenderError>
+where
+    W: Write + Seek,
+    G: FnMut(f32),
+{
+    let mut writer = WavFileWriter::new(&mut sink, config.output_sample_rate(), config.channels(), config.format)?;
+    let (stats, loop_region) = render_midi_into(sound_font, layers, midi_file, smf, config, |left, right| writer.write(left, right), progress)?;
+    writer.finalize()?;
+    // Like a file of raw samples, the fallback to 16 bits of `SampleFormat::Raw` gets no loop, see `append_loop_chunk`
+    if config.format != SampleFormat::Raw {
+        if let Some((start, end)) = loop_region {
+            append_chunk(&mut sink, b"smpl", &smpl_chunk(config.output_sample_rate(), start, end))?;
+        }
+    }
+    Ok(stats)
+}
+
+/// Renders a single note of `key` at `velocity`, held for `length` seconds, on the preset `program` of `bank` into in-memory channels
+/// 
+/// The note goes through the whole pipeline of `config` like a MIDI file would, followed by the tail of `config`,
+/// so that it makes a one-shot sample with the character of a render. Bank 128 plays the drum kits on the drum channel.
+pub fn render_note(sound_font: &Arc<SoundFont>, bank: u8, program: u8, key: u8, velocity: u8, length: f64, config: &RenderConfig) -> Result<(Vec<f32>, Vec<f32>, RenderStats), RenderError> {
+    // 960 ticks per second at the default tempo of 120 BPM
+    const DIVISION: u16 = 480;
+    let (channel, bank) = if bank == 128 { (DRUM_CHANNEL - 1, 0) } else { (0, bank.min(127)) };
+    let midi = |status: u8, data1: u8, data2: u8| EventKind::Midi { status: status | channel, data1, data2 };
+    let track = vec![
+        TrackEvent { delta: 0, kind: midi(0xB0, 0x00, bank) },
+        TrackEvent { delta: 0, kind: midi(0xC0, program.min(127), 0) },
+        TrackEvent { delta: 0, kind: midi(0x90, key.min(127), velocity.clamp(1, 127)) },
+        TrackEvent { delta: (length.max(0.0) * 2.0 * DIVISION as f64).round() as u32, kind: midi(0x80, key.min(127), 0) },
+    ];
+    let smf = Smf { format: 0, division: DIVISION, tracks: vec![track] };
+    let midi_file = smf.to_midi_file()?;
+    let FileRender { left, right, stats, .. } = render_midi_to_buffers(sound_font, &[], midi_file, smf, config, |_| {})?;
+    Ok((left, right, stats))
+}
+
+/// Renders the MIDI file at `input_file_path` into in-memory channels, along with the frames to loop between, if any
+fn render_file_to_buffers<P, G>(sound_font: &Arc<SoundFont>, layers: &[SoundFontLayer], input_file_path: P, config: &RenderConfig, progress: G) -> Result<FileRender, RenderError>
+where
+    P: AsRef<Path>,
+    G: FnMut(f32),
+{
+    let (midi_file, smf) = load_midi_file_with_events(input_file_path)?;
+    render_midi_to_buffers(sound_font, layers, midi_file, smf, config, progress)
+}
+
+/// Renders `midi_file`, which has the events of `smf`, into in-memory channels after applying the event changes of `config`
+fn render_midi_to_buffers<G: FnMut(f32)>(sound_font: &Arc<SoundFont>, layers: &[SoundFontLayer], midi_file: Arc<MidiFile>, smf: Smf, config: &RenderConfig, progress: G) -> Result<FileRender, RenderError> {
+    let mut left: Vec<f32> = Vec::new();
+    let mut right: Vec<f32> = Vec::new();
+    let (stats, loop_region) = render_midi_into(sound_font, layers, midi_file, smf, config, |l, r| {
+        left.extend_from_slice(l);
+        right.extend_from_slice(r);
+        Ok(())
+    }, progress)?;
+    Ok(FileRender { left, right, stats, loop_region })
+}
+
+/// Renders `midi_file`, which has the events of `smf`, after applying the event changes of `config`, handing each block to `write` as it is rendered
+/// 
+/// Returns the frames to loop between along with the stats, see [`loop_frames`]. Once `write` fails the blocks after are dropped
+/// and its error is returned when the render is done.
+#[allow(clippy::too_many_arguments)]
+fn render_midi_into<W, G>(sound_font: &Arc<SoundFont>, layers: &[SoundFontLayer], midi_file: Arc<MidiFile>, smf: Smf, config: &RenderConfig, mut write: W, progress: G) -> Result<(RenderStats, Option<(u32, u32)>), RenderError>
+where
+    W: FnMut(&[f32], &[f32]) -> Result<(), RenderError>,
+    G: FnMut(f32),
+{
+    let mut frames = 0;
+    let mut written = Ok(());
+    let (stats, smf) = render_midi_streaming(sound_font, layers, midi_file, smf, config, DEFAULT_CHUNK_SIZE, |left, right| {
+        if written.is_ok() {
+            frames += left.len();
+            written = write(left, right);
+        }
+    }, progress)?;
+    written?;
+    let loop_region = loop_frames(&smf, config, &stats, frames);
+    Ok((stats, loop_region))
+}
+
+/// Renders the MIDI file at `input_file_path` in blocks like [`render_streaming_with_progress`], but with the event changes of `config`
+/// and the channels of each of `layers` played through the soundfont of that layer, exactly like a render into a file
+pub fn render_file_streaming_with_progress<P, F, G>(sound_font: &Arc<SoundFont>, layers: &[SoundFontLayer], input_file_path: P, config: &RenderConfig, chunk_size: usize, callback: F, progress: G) -> Result<RenderStats, RenderError>
+where
+    P: AsRef<Path>,
+    F: FnMut(&[f32], &[f32]),
+    G: FnMut(f32),
+{
+    let (midi_file, smf) = load_midi_file_with_events(input_file_path)?;
+    Ok(render_midi_streaming(sound_font, layers, midi_file, smf, config, chunk_size, callback, progress)?.0)
+}
+
+/// Renders `midi_file`, which has the events of `smf`, in blocks after applying the event changes of `config`, returning the changed events
+#[allow(clippy::too_many_arguments)]
+fn render_midi_streaming<F, G>(sound_font: &Arc<SoundFont>, layers: &[SoundFontLayer], midi_file: Arc<MidiFile>, mut smf: Smf, config: &RenderConfig, chunk_size: usize, callback: F, progress: G) -> Result<(RenderStats, Smf), RenderError>
+where
+    F: FnMut(&[f32], &[f32]),
+    G: FnMut(f32),
+{
+    let midi_file = if transform_midi(&mut smf, config) { smf.to_midi_file()? } else { midi_file };
+    let mut files = split_layers(sound_font, layers, &midi_file, &smf)?;
+    // Crossfaded repetitions are rendered one by one, so with a loop the first plays up to its end and the others just the loop
+    let mut loop_files = None;
+    if config.loop_crossfade > 0.0 && config.repeat > 1.0 {
+        if let Some((start, end)) = smf.loop_points() {
+            let tempo_map = smf.tempo_map();
+            let end = end.map(|end| tempo_map.seconds_at(end));
+            let part = |start: f64| -> Result<Vec<LayerFile>, RenderError> {
+                let mut part = smf.clone();
+                window(&mut part, start, end);
+                split_layers(sound_font, layers, &part.to_midi_file()?, &part)
+            };
+            files = part(0.0)?;
+            loop_files = Some(part(tempo_map.seconds_at(start))?);
+        }
+    }
+    // The repetitions are part of the file now, unless they are crossfaded
+    let mut config = RenderConfig { repeat: if config.loop_crossfade > 0.0 { config.repeat } else { 1.0 }, ..config.clone() };
+    // `rustysynth` ignores SysEx messages, so the master volume of the file becomes part of the gain
+    if config.master_volume && config.normalize == NormalizeMode::None {
+        if let Some(volume) = smf.master_volume() {
+            let volume_db = 20.0 * (volume as f32).log10();
+            log::debug!("Applying the master volume of the file as {:+.1} dB of gain", volume_db);
+            config.gain_db += volume_db;
+        }
+    }
+    let stats = render_looped_streaming(&files, loop_files.as_deref(), &config, chunk_size, callback, progress)?;
+    Ok((stats, smf))
+}
+
+struct FileRender {
+    left: Vec<f32>,
+    right: Vec<f32>,
+    stats: RenderStats,
+    /// Inclusive start and end frames of the loop, see [`loop_frames`]
+    loop_region: Option<(u32, u32)>,
+}
+
+/// The loop between the loop markers of `smf` as inclusive start and end frames of the `frames` frames written for a render with `stats`
+/// 
+/// Without an end marker the loop runs to the last frame.
+fn loop_frames(smf: &Smf, config: &RenderConfig, stats: &RenderStats, frames: usize) -> Option<(u32, u32)> {
+    let (start, end) = smf.loop_points()?;
+    let tempo_map = smf.tempo_map();
+    // Trimming shifts everything towards the start
+    let offset = stats.trimmed_start as f64 / config.nds_rate as f64;
+    let to_frame = |tick| ((tempo_map.seconds_at(tick) - offset).max(0.0) * config.output_sample_rate() as f64).round() as usize;
+    let start = to_frame(start);
+    let end = end.map_or(frames, to_frame).min(frames).checked_sub(1)?;
+    (start < end).then_some((start as u32, end as u32))
+}
+
+/// Number of frames rendered per block by [`render_to_buffers`]
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// Renders `midi_file` through `sound_font` like [`render_to_buffers`], but returns interleaved frames of [`RenderConfig::channels`] samples
+pub fn render_to_interleaved(sound_font: &Arc<SoundFont>, midi_file: &Arc<MidiFile>, config: &RenderConfig) -> Result<Vec<f32>, RenderError> {
+    let mut samples: Vec<f32> = Vec::new();
+    render_streaming_interleaved(sound_font, midi_file, config, DEFAULT_CHUNK_SIZE, |block| samples.extend_from_slice(block))?;
+    Ok(samples)
+}
+
+/// Renders `midi_file` through `sound_font` and returns the processed left and right channels
+/// 
+/// Samples are in the range [-1.0, 1.0] and have already gone through bit reduction if it is enabled in `config`.
+pub fn render_to_buffers(sound_font: &Arc<SoundFont>, midi_file: &Arc<MidiFile>, config: &RenderConfig) -> Result<(Vec<f32>, Vec<f32>), RenderError> {
+    let mut left: Vec<f32> = Vec::new();
+    let mut right: Vec<f32> = Vec::new();
+    render_streaming(sound_font, midi_file, config, DEFAULT_CHUNK_SIZE, |l, r| {
+        left.extend_from_slice(l);
+        right.extend_from_slice(r);
+    })?;
+
+    Ok((left, right))
+}
+
+/// Renders `midi_file` through `sound_font` in blocks of at most `chunk_size` frames, handing each processed block to `callback`
+/// 
+/// Only a single block is kept in memory at a time, so arbitrarily long renders can be written out or analysed incrementally.
+/// The blocks passed to `callback` are the left and right channels and have already gone through bit reduction if it is enabled in `config`.
+/// With a mono downmix both channels hold the same mixed signal.
+pub fn render_streaming<F>(sound_font: &Arc<SoundFont>, midi_file: &Arc<MidiFile>, config: &RenderConfig, chunk_size: usize, callback: F) -> Result<RenderStats, RenderError>
+where
+    F: FnMut(&[f32], &[f32]),
+{
+    render_streaming_with_progress(sound_font, midi_file, config, chunk_size, callback, |_| {})
+}
+
+/// Same as [`render_streaming`], but passes each block to `callback` as interleaved frames of [`RenderConfig::channels`] samples,
+/// for libraries that take audio in that layout
+pub fn render_streaming_interleaved<F>(sound_font: &Arc<SoundFont>, midi_file: &Arc<MidiFile>, config: &RenderConfig, chunk_size: usize, mut callback: F) -> Result<RenderStats, RenderError>
+where
+    F: FnMut(&[f32]),
+{
+    let mono = config.channels() == 1;
+    let mut block: Vec<f32> = Vec::new();
+    render_streaming(sound_font, midi_file, config, chunk_size, |left, right| {
+        if mono {
+            return callback(left);
+        }
+        block.clear();
+        block.extend(left.iter().zip(right).flat_map(|(&l, &r)| [l, r]));
+        callback(&block);
+    })
+}
+
+/// Same as [`render_streaming`], but also reports the fraction of frames rendered so far (from 0.0 to 1.0) to `progress` after every block
+pub fn render_streaming_with_progress<F, G>(sound_font: &Arc<SoundFont>, midi_file: &Arc<MidiFile>, config: &RenderConfig, chunk_size: usize, callback: F, progress: G) -> Result<RenderStats, RenderError>
+where
+    F: FnMut(&[f32], &[f32]),
+    G: FnMut(f32),
+{
+    render_layered_streaming_with_progress(&[(sound_font.clone(), midi_file.clone())], config, chunk_size, callback, progress)
+}
+
+/// Same as [`render_streaming_with_progress`], but plays every MIDI file of `files` through its own soundfont at the same time and mixes them
+/// 
+/// This is how [`SoundFontLayer`]s are rendered, each with a file of just its own channels. The render is as long as the longest file.
+pub fn render_layered_streaming_with_progress<F, G>(files: &[LayerFile], config: &RenderConfig, chunk_size: usize, callback: F, progress: G) -> Result<RenderStats, RenderError>
+where
+    F: FnMut(&[f32], &[f32]),
+    G: FnMut(f32),
+{
+    render_looped_streaming(files, None, config, chunk_size, callback, progress)
+}
+
+/// Same as [`render_layered_streaming_with_progress`], but with crossfaded repetitions playing `loop_files` instead of `files`, see [`start_sequencer`]
+fn render_looped_streaming<F, G>(files: &[LayerFile], loop_files: Option<&[LayerFile]>, config: &RenderConfig, chunk_size: usize, mut callback: F, mut progress: G) -> Result<RenderStats, RenderError>
+where
+    F: FnMut(&[f32], &[f32]),
+    G: FnMut(f32),
+{
+    if files.is_empty() {
+        return Err(RenderError::InvalidConfig("there has to be at least one MIDI file to render".to_string()));
+    }
+    config.validate()?;
+
+    // Normalization needs the level of the whole render up front, DC removal its mean and a fade-out its length when rendering until silence,
+    // so the (deterministic) synthesizer runs through the file once just to measure it
+    let measure = config.normalize != NormalizeMode::None || config.remove_dc || (config.stop_on_silence.is_some() && config.fade_out > 0.0);
+    let passes = if measure { 2.0 } else { 1.0 };
+    let mut gain = 1.0;
+    let mut measured_length = None;
+    let mut dc_offset = [0_f64; 2];
+    if measure {
+        let (mut sequencer, sample_count) = start_sequencer(files, loop_files, config)?;
+        let chunk_size = chunk_size.clamp(1, sample_count.max(1));
+        let mut left: Vec<f32> = vec![0_f32; chunk_size];
+        let mut right: Vec<f32> = vec![0_f32; chunk_size];
+        let mut meter = LevelMeter::new(config.normalize, config.nds_rate)?;
+        let mut detector = config.stop_on_silence.map(|stop| SilenceDetector::new(&stop, config.nds_rate));
+        let mut filter = tone_filter(config);
+        let mut rendered = 0;
+        while rendered < sample_count {
+            let mut frames = chunk_size.min(sample_count - rendered);
+            let (left, right) = (&mut left[..frames], &mut right[..frames]);
+            sequencer.render(left, right)?;
+            // Measured after the width and the filter, since they change the level
+            if config.width != 1.0 {
+                stereo_width(left, right, config.width);
+            }
+            if !filter.is_empty() {
+                filter.process(left, right);
+            }
+            let stop = detector.as_mut().and_then(|detector| detector.stop_within(left, right));
+            if let Some(kept) = stop {
+                frames = kept;
+            }
+            meter.add(&left[..frames], &right[..frames])?;
+            rendered += frames;
+            progress(rendered as f32 / sample_count as f32 / passes);
+            if stop.is_some() {
+                break;
+            }
+        }
+        gain = meter.gain(config.normalize, config.target_peak_dbfs, config.target_lufs, config.remove_dc);
+        if config.remove_dc {
+            dc_offset = meter.dc_offset();
+        }
+        measured_length = Some(rendered);
+        log::debug!("Measured {} frames, normalizing by {:+.1} dB", rendered, 20.0 * gain.log10());
+    }
+    // The master gain comes on top of normalization; anything pushed past full scale is left to the overflow handling of bit reduction
+    let total_gain = gain * db_to_gain(config.gain_db) as f64;
+    if total_gain != 1.0 {
+        log::debug!("Applying {:+.1} dB of gain in total", 20.0 * total_gain.log10());
+    }
+
+    let (mut sequencer, mut sample_count) = start_sequencer(files, loop_files, config)?;
+    let mut detector = None;
+    if let Some(length) = measured_length {
+        sample_count = length;
+    } else if let Some(stop) = config.stop_on_silence {
+        detector = Some(SilenceDetector::new(&stop, config.nds_rate));
+    }
+    let chunk_size = chunk_size.clamp(1, sample_count.max(1));
+    let mut left: Vec<f32> = vec![0_f32; chunk_size];
+    let mut right: Vec<f32> = vec![0_f32; chunk_size];
+
+    let to_frames = |seconds: f64| (seconds * config.nds_rate as f64).round() as usize;
+    let fades = Fades::new(to_frames(config.fade_in), to_frames(config.fade_out), sample_count, config.fade_curve);
+    let mut filter = tone_filter(config);
+    let mut quantizer = Quantizer::new(config);
+    let mut resampler = (config.output_sample_rate() != config.nds_rate).then(|| {
+        let resampler = StereoResampler::new(config.nds_rate, config.output_sample_rate(), config.resample);
+        match config.antialias {
+            Some(cutoff) => resampler.with_antialias(cutoff),
+            None => resampler,
+        }
+    });
+    let mut resampled_left: Vec<f32> = Vec::new();
+    let mut resampled_right: Vec<f32> = Vec::new();
+    let mut trimmer = config.trim.map(SilenceTrimmer::new);
+    let mut trimmed_left: Vec<f32> = Vec::new();
+    let mut trimmed_right: Vec<f32> = Vec::new();
+    let mut stats = StatsAccumulator::default();
+    let mut hasher = OutputHasher::new(config);
+    let mut callback = |left: &[f32], right: &[f32]| {
+        let (left, right) = if config.swap_channels { (right, left) } else { (left, right) };
+        hasher.add(left, right);
+        callback(left, right);
+    };
+    // The sample-and-hold and the second stage of bit reduction come last, after resampling,
+    // the latter like storing the output of the NDS at a wider depth
+    let mut hold = config.crush_rate.filter(|&divisor| divisor > 1).map(|divisor| SampleHold::new(divisor as usize));
+    let mut final_left: Vec<f32> = Vec::new();
+    let mut final_right: Vec<f32> = Vec::new();
+    let mut callback = |left: &[f32], right: &[f32]| {
+        if hold.is_none() && config.output_bitdepth.is_none() {
+            return callback(left, right);
+        }
+        final_left.clear();
+        final_left.extend_from_slice(left);
+        final_right.clear();
+        final_right.extend_from_slice(right);
+        if let Some(hold) = hold.as_mut() {
+            hold.process(&mut final_left, &mut final_right);
+        }
+        if let Some(bits) = config.output_bitdepth {
+            quantize_to_output_bits(&mut final_left, bits);
+            quantize_to_output_bits(&mut final_right, bits);
+        }
+        callback(&final_left, &final_right);
+    };
+    let mut rendered = 0;
+    while rendered < sample_count {
+        let mut frames = chunk_size.min(sample_count - rendered);
+        sequencer.render(&mut left[..frames], &mut right[..frames])?;
+        let stop = detector.as_mut().and_then(|detector| detector.stop_within(&left[..frames], &right[..frames]));
+        if let Some(kept) = stop {
+            frames = kept;
+        }
+        let (left, right) = (&mut left[..frames], &mut right[..frames]);
+
+        if config.width != 1.0 {
+            stereo_width(left, right, config.width);
+        }
+        // Filtering, DC removal and gain run on each sample in double precision, rounding back once at the end
+        if !filter.is_empty() || dc_offset != [0.0; 2] || total_gain != 1.0 {
+            for (channel, samples) in [&mut *left, &mut *right].into_iter().enumerate() {
+                for x in samples.iter_mut() {
+                    let filtered = filter.process_sample(channel, *x as f64);
+                    *x = ((filtered - dc_offset[channel]) * total_gain) as f32;
+                }
+            }
+        }
+        if !fades.is_empty() {
+            fades.apply(left, right, rendered);
+        }
+        if let Some(threshold) = config.limit {
+            soft_clip(left, db_to_gain(threshold));
+            soft_clip(right, db_to_gain(threshold));
+        }
+        if config.headroom_db != 0.0 {
+            let headroom = db_to_gain(-config.headroom_db);
+            for x in left.iter_mut().chain(right.iter_mut()) {
+                *x *= headroom;
+            }
+        }
+        stats.add(left, right);
+
+        match config.mono {
+            Some(mode) if config.mono_before_quantization => {
+                downmix_mono(left, right, mode);
+                quantizer.process(left, right);
+            }
+            Some(mode) => {
+                quantizer.process(left, right);
+                downmix_mono(left, right, mode);
+            }
+            None => quantizer.process(left, right),
+        }
+
+        let (left, right): (&[f32], &[f32]) = match trimmer.as_mut() {
+            Some(trimmer) => {
+                trimmed_left.clear();
+                trimmed_right.clear();
+                trimmer.process(left, right, &mut trimmed_left, &mut trimmed_right);
+                (&trimmed_left, &trimmed_right)
+            }
+            None => (left, right),
+        };
+
+        if let Some(resampler) = resampler.as_mut() {
+            resampled_left.clear();
+            resampled_right.clear();
+            resampler.process(left, right, &mut resampled_left, &mut resampled_right);
+            callback(&resampled_left, &resampled_right);
+        } else if !left.is_empty() {
+            callback(left, right);
+        }
+        rendered += frames;
+        if stop.is_some() {
+            break;
+        }
+        progress((passes - 1.0 + rendered as f32 / sample_count as f32) / passes);
+    }
+    if let Some(resampler) = resampler.as_mut() {
+        resampled_left.clear();
+        resampled_right.clear();
+        resampler.flush(&mut resampled_left, &mut resampled_right);
+        if !resampled_left.is_empty() {
+            callback(&resampled_left, &resampled_right);
+        }
+    }
+    if sample_count == 0 || detector.is_some() {
+        progress(1.0);
+    }
+
+    let mut stats = stats.finish();
+    stats.gain_db = 20.0 * gain.log10() as f32;
+    (stats.trimmed_start, stats.trimmed_end) = trimmer.map_or((0, 0), SilenceTrimmer::finish);
+    stats.hash = hasher.finish();
+    Ok(stats)
+}
+
+/// The filters of `config` that shape the render before bit reduction, at the NDS rate
+fn tone_filter(config: &RenderConfig) -> StereoFilter {
+    let rate = config.nds_rate as f64;
+    let mut sections = if config.dac_filter { nds_dac_filter(rate, config.dac_highpass) } else { Vec::new() };
+    sections.extend(config.lowpass.map(|cutoff| Biquad::lowpass(rate, cutoff, std::f64::consts::FRAC_1_SQRT_2)));
+    sections.extend(config.highpass.map(|cutoff| Biquad::highpass(rate, cutoff, std::f64::consts::FRAC_1_SQRT_2)));
+    StereoFilter::from_chain(BiquadChain::new(sections).then(BiquadChain::eq(rate, &config.eq)))
+}
+
+/// Sets up sequencers playing each of `files` as configured, along with the number of frames to render at the NDS rate
+/// 
+/// When stopping on silence this is the most that may be rendered. Crossfaded repetitions after the first play `loop_files` if there are any,
+/// which is how a file with an intro repeats just its loop; `files` then end where the loop does.
+fn start_sequencer(files: &[LayerFile], loop_files: Option<&[LayerFile]>, config: &RenderConfig) -> Result<(Source, usize), RenderError> {
+    let mut settings = SynthesizerSettings::new((config.nds_rate * config.oversample) as i32);
+    settings.maximum_polyphony = config.max_voices;
+    settings.enable_reverb_and_chorus = config.reverb_and_chorus;
+    let crossfade = config.loop_crossfade > 0.0 && config.repeat > 1.0;
+    // Without the events to repeat, the file loops until the render has reached the length of every repetition
+    let mut sequencer = RepeatingSequencer::new(files, &settings, config.repeat > 1.0 && !crossfade)?;
+
+    let length_of = |files: &[LayerFile]| files.iter().map(|(_, midi_file)| midi_file.get_length()).fold(0.0, f64::max);
+    let length = length_of(files);
+    let loop_length = loop_files.filter(|_| crossfade).map_or(length, length_of);
+    let to_frames = |seconds: f64, sample_rate: f64| (sample_rate * seconds).round() as usize;
+    if crossfade {
+        let sample_rate = settings.sample_rate as f64;
+        sequencer = sequencer.with_crossfade(config.repeat.ceil() as usize, to_frames(loop_length, sample_rate), to_frames(config.loop_crossfade, sample_rate));
+        if let Some(loop_files) = loop_files {
+            sequencer = sequencer.with_loop(loop_files, to_frames(length, sample_rate));
+        }
+    }
+    // The sequencer keeps running the synthesizer past the end of the file, which lets the last notes release naturally during the tail
+    let played = length + loop_length * (config.repeat - 1.0);
+    let duration = match config.stop_on_silence {
+        Some(stop) => stop.max_duration,
+        None if config.end.is_some() => played,
+        None => played + config.tail,
+    };
+    let source = Source {
+        sequencer,
+        decimator: (config.oversample > 1).then(|| StereoDecimator::new(settings.sample_rate as u32, config.oversample as usize)),
+        oversampled: (Vec::new(), Vec::new()),
+        decimated: (Vec::new(), Vec::new()),
+    };
+    // Rounded rather than truncated so the last partial frame of the file is not cut off; `get_length` is exact to the tick
+    Ok((source, to_frames(duration, config.nds_rate as f64)))
+}
+
+/// The sequencer of a render, brought down to the NDS rate when it runs at a multiple of it, see [`RenderConfig::oversample`]
+struct Source {
+    sequencer: RepeatingSequencer,
+    decimator: Option<StereoDecimator>,
+    /// Blocks at the rate of the synthesizer, and the same blocks decimated
+    oversampled: (Vec<f32>, Vec<f32>),
+    decimated: (Vec<f32>, Vec<f32>),
+}
+
+impl Source {
+    fn render(&mut self, left: &mut [f32], right: &mut [f32]) -> Result<(), RenderError> {
+        let Some(decimator) = self.decimator.as_mut() else {
+            return self.sequencer.render(left, right);
+        };
+        let (oversampled_left, oversampled_right) = &mut self.oversampled;
+        let (decimated_left, decimated_right) = &mut self.decimated;
+        oversampled_left.resize(left.len() * decimator.factor(), 0.0);
+        oversampled_right.resize(right.len() * decimator.factor(), 0.0);
+        self.sequencer.render(oversampled_left, oversampled_right)?;
+        decimated_left.clear();
+        decimated_right.clear();
+        decimator.process(oversampled_left, oversampled_right, decimated_left, decimated_right);
+        left.copy_from_slice(decimated_left);
+        right.copy_from_slice(decimated_right);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, io::{self, SeekFrom}, rc::Rc};
+    use super::*;
+    use crate::testing::{sine_sound_font, smf, midi, tempo, marker, end_of_track, DIVISION};
+
+    /// A sink that keeps nothing, only how long the file written into it is
+    struct CountingSink {
+        position: u64,
+        length: Rc<Cell<u64>>,
+    }
+
+    impl Write for CountingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.position += buf.len() as u64;
+            self.length.set(self.length.get().max(self.position));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for CountingSink {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.position = match pos {
+                SeekFrom::Start(offset) => offset,
+                SeekFrom::End(offset) => self.length.get().saturating_add_signed(offset),
+                SeekFrom::Current(offset) => self.position.saturating_add_signed(offset),
+            };
+            Ok(self.position)
+        }
+    }
+
+    #[test]
+    fn render_length_is_the_file_and_its_tail() {
+        // Two seconds at 120 BPM
+        let file = smf(vec![tempo(0, 500_000), midi(0, 0x90, 60, 100), midi(4 * DIVISION as u32, 0x80, 60, 0), end_of_track(0)]);
+        let midi_file = file.to_midi_file().unwrap();
+        let sound_font = sine_sound_font();
+        for (nds_rate, tail) in [(32729, 0.0), (32729, 0.75), (22050, 0.3)] {
+            let config = RenderConfig { nds_rate, tail, ..Default::default() };
+            let (left, right) = render_to_buffers(&sound_font, &midi_file, &config).unwrap();
+            let expected = (2.0 + tail) * nds_rate as f64;
+            assert!((left.len() as f64 - expected).abs() <= 1.0, "{} frames at {} Hz with a tail of {} s", left.len(), nds_rate, tail);
+            assert_eq!(left.len(), right.len());
+        }
+    }
+
+    #[test]
+    fn streamed_wave_file_only_leaves_the_sizes_open() {
+        let file = smf(vec![tempo(0, 500_000), midi(0, 0x90, 60, 100), midi(DIVISION as u32, 0x80, 60, 0), end_of_track(0)]);
+        let path = std::env::temp_dir().join(format!("nds_sound_render_stream_{}.mid", std::process::id()));
+        std::fs::write(&path, file.to_bytes()).unwrap();
+        let config = RenderConfig { format: SampleFormat::Int16, ..Default::default() };
+
+        let mut seekable = Cursor::new(Vec::new());
+        let mut streamed = Vec::new();
+        let result = render_wav_to_writer(sine_sound_font(), &path, &mut seekable, &config, |_| {})
+            .and_then(|_| render_to_stream(sine_sound_font(), &[], &path, &mut streamed, &config, |_| {}));
+        let _ = std::fs::remove_file(&path);
+        result.unwrap();
+
+        let mut seekable = seekable.into_inner();
+        assert_eq!(&streamed[4..8], &u32::MAX.to_le_bytes());
+        assert_eq!(&streamed[40..44], &u32::MAX.to_le_bytes());
+        seekable[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        seekable[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(seekable, streamed);
+    }
+
+    #[test]
+    fn streamed_raw_output_has_no_header() {
+        let file = smf(vec![tempo(0, 500_000), midi(0, 0x90, 60, 100), midi(DIVISION as u32, 0x80, 60, 0), end_of_track(0)]);
+        let path = std::env::temp_dir().join(format!("nds_sound_render_stream_raw_{}.mid", std::process::id()));
+        std::fs::write(&path, file.to_bytes()).unwrap();
+        // 10 bits are stored as 16-bit samples, the same as in a 16-bit wave-file
+        let config = RenderConfig { format: SampleFormat::Int16, ..Default::default() };
+        let raw_config = RenderConfig { format: SampleFormat::Raw, ..config.clone() };
+
+        let mut wav = Vec::new();
+        let mut raw = Vec::new();
+        let result = render_to_stream(sine_sound_font(), &[], &path, &mut wav, &config, |_| {})
+            .and_then(|_| render_to_stream(sine_sound_font(), &[], &path, &mut raw, &raw_config, |_| {}));
+        let _ = std::fs::remove_file(&path);
+        result.unwrap();
+        assert_eq!(raw, wav[44..]);
+    }
+
+    #[test]
+    fn looped_render_is_written_as_it_renders() {
+        // A short note in a second-long loop (at 120 BPM), repeated for eight seconds, which is far more than the blocks held back
+        let second = 2 * DIVISION as u32;
+        let file = smf(vec![
+            tempo(0, 500_000),
+            marker(0, "loopStart"),
+            midi(0, 0x90, 60, 100),
+            midi(DIVISION as u32 / 2, 0x80, 60, 0),
+            marker(second - DIVISION as u32 / 2, "loopEnd"),
+            end_of_track(0),
+        ]);
+        let path = std::env::temp_dir().join(format!("nds_sound_render_loop_{}.mid", std::process::id()));
+        std::fs::write(&path, file.to_bytes()).unwrap();
+        let config = RenderConfig { nds_rate: 16000, repeat: 8.0, tail: 0.0, format: SampleFormat::Int16, ..Default::default() };
+
+        let length = Rc::new(Cell::new(0));
+        let sink = CountingSink { position: 0, length: length.clone() };
+        let mut progress = Vec::new();
+        let result = render_wav_to_writer(sine_sound_font(), &path, sink, &config, |fraction| progress.push((fraction, length.get())));
+        let _ = std::fs::remove_file(&path);
+        let stats = result.unwrap();
+        assert!(stats.frames >= 8 * 16000);
+
+        // Whatever was rendered but not yet written is held in memory; 44 bytes of header and 4 bytes per frame
+        let most_buffered = progress.iter().map(|&(fraction, bytes)| {
+            let rendered = (fraction as f64 * stats.frames as f64) as u64;
+            rendered.saturating_sub(bytes.saturating_sub(44) / 4)
+        }).max().unwrap();
+        assert!(most_buffered <= 2 * DEFAULT_CHUNK_SIZE as u64, "{} frames were held back", most_buffered);
+    }
+}
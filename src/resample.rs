@@ -0,0 +1,338 @@
+use std::{fmt, str::FromStr};
+
+use crate::dsp::{Biquad, butterworth_lowpass};
+
+/// Resamples `input` from `in_rate` to `out_rate` by zero-order hold, i.e. every output sample repeats the input sample right at or before it
+/// 
+/// This is the "no interpolation" resampling of the NDS mixer, which leaves mirror images of the input spectrum above its Nyquist frequency
+/// instead of filtering them out, and is the source of the ringing high-frequency content of its sound.
+pub fn resample_zoh(input: &[f32], in_rate: u32, out_rate: u32) -> Vec<f32> {
+    resample(input, in_rate, out_rate, ResampleMode::Zoh)
+}
+
+/// Resamples `input` from `in_rate` to `out_rate` using the interpolation given by `mode`
+pub fn resample(input: &[f32], in_rate: u32, out_rate: u32, mode: ResampleMode) -> Vec<f32> {
+    let mut resampler = ChannelResampler::new(in_rate, out_rate, mode);
+    let mut output = Vec::with_capacity(output_len(input.len() as u64, in_rate, out_rate) as usize);
+    resampler.process(input, &mut output);
+    resampler.flush(&mut output);
+    output
+}
+
+/// Number of output samples covering the same duration as `input_len` samples at `in_rate`
+fn output_len(input_len: u64, in_rate: u32, out_rate: u32) -> u64 {
+    (input_len * out_rate as u64).div_ceil(in_rate as u64)
+}
+
+/// Streaming decimation of a stereo signal by a whole `factor`, low-passed just below the Nyquist frequency of the output
+/// 
+/// Like [`ChannelResampler`], the filter and the phase of the kept samples carry on across blocks.
+#[derive(Clone, Debug)]
+pub struct StereoDecimator {
+    factor: usize,
+    filters: [[Biquad; 2]; 2],
+    phase: usize,
+}
+
+impl StereoDecimator {
+    pub fn new(in_rate: u32, factor: usize) -> Self {
+        let factor = factor.max(1);
+        let filter = butterworth_lowpass(in_rate as f64, 0.45 * in_rate as f64 / factor as f64);
+        StereoDecimator { factor, filters: [filter; 2], phase: 0 }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Filters `left_in` and `right_in` in place and appends every `factor`-th frame of them to `left_out` and `right_out`
+    pub fn process(&mut self, left_in: &mut [f32], right_in: &mut [f32], left_out: &mut Vec<f32>, right_out: &mut Vec<f32>) {
+        let frames = left_in.len();
+        for ((input, output), filter) in [(left_in, left_out), (right_in, right_out)].into_iter().zip(self.filters.iter_mut()) {
+            for section in filter.iter_mut() {
+                section.process(input);
+            }
+            output.extend(input.iter().skip((self.factor - self.phase) % self.factor).step_by(self.factor));
+        }
+        self.phase = (self.phase + frames) % self.factor;
+    }
+}
+
+/// Streaming resampler for a single channel
+/// 
+/// Input can be fed in blocks of any size; the position of every output sample is computed exactly from its index,
+/// so no timing error builds up however long the stream gets.
+#[derive(Clone, Debug)]
+pub struct ChannelResampler {
+    mode: ResampleMode,
+    /// Anti-aliasing filter run over the input before it is resampled
+    antialias: Option<[Biquad; 2]>,
+    in_rate: u64,
+    out_rate: u64,
+    /// Input samples that are still needed, starting at input index `dropped`
+    pending: Vec<f32>,
+    dropped: u64,
+    /// Total number of input samples seen so far
+    received: u64,
+    /// Number of output samples produced so far
+    produced: u64,
+}
+
+impl ChannelResampler {
+    pub fn new(in_rate: u32, out_rate: u32, mode: ResampleMode) -> Self {
+        ChannelResampler {
+            mode,
+            antialias: None,
+            in_rate: in_rate as u64,
+            out_rate: out_rate as u64,
+            pending: Vec::new(),
+            dropped: 0,
+            received: 0,
+            produced: 0,
+        }
+    }
+
+    /// Low-passes the input at `cutoff` times the output Nyquist frequency before resampling, if this resampler decimates
+    /// 
+    /// Without it, content above the output Nyquist frequency folds back into the audible band when downsampling.
+    pub fn with_antialias(mut self, cutoff: f32) -> Self {
+        if self.out_rate < self.in_rate {
+            self.antialias = Some(butterworth_lowpass(self.in_rate as f64, cutoff as f64 * self.out_rate as f64 / 2.0));
+        }
+        self
+    }
+
+    /// Feeds `input` into the resampler, appending every output sample that can be computed so far to `output`
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let start = self.pending.len();
+        self.pending.extend_from_slice(input);
+        if let Some(filters) = self.antialias.as_mut() {
+            for filter in filters.iter_mut() {
+                filter.process(&mut self.pending[start..]);
+            }
+        }
+        self.received += input.len() as u64;
+        let (history, lookahead) = self.mode.support();
+        loop {
+            let index = self.produced * self.in_rate / self.out_rate;
+            if index + lookahead >= self.received {
+                break;
+            }
+            output.push(self.interpolate());
+            self.produced += 1;
+        }
+        // Everything too far before the sample the next output starts from is no longer needed
+        let next = (self.produced * self.in_rate / self.out_rate).saturating_sub(history).clamp(self.dropped, self.received);
+        self.pending.drain(..(next - self.dropped) as usize);
+        self.dropped = next;
+    }
+
+    /// Appends the output samples that are still owed for the input seen so far to `output`, holding the last input sample past the end
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let total = output_len(self.received, self.in_rate as u32, self.out_rate as u32);
+        while self.produced < total {
+            output.push(self.interpolate());
+            self.produced += 1;
+        }
+    }
+
+    /// Computes the output sample at index `produced`
+    fn interpolate(&self) -> f32 {
+        let position = self.produced * self.in_rate;
+        let index = (position / self.out_rate) as i64;
+        let t = (position % self.out_rate) as f32 / self.out_rate as f32;
+        match self.mode {
+            ResampleMode::Zoh => self.input(index),
+            ResampleMode::Linear => {
+                let (x0, x1) = (self.input(index), self.input(index + 1));
+                x0 + t * (x1 - x0)
+            }
+            ResampleMode::Cubic => {
+                let (xm1, x0, x1, x2) = (self.input(index - 1), self.input(index), self.input(index + 1), self.input(index + 2));
+                catmull_rom(xm1, x0, x1, x2, t)
+            }
+        }
+    }
+
+    /// Input sample at absolute index `index`, with the first and last samples held beyond either end
+    fn input(&self, index: i64) -> f32 {
+        let index = index.clamp(self.dropped as i64, self.dropped as i64 + self.pending.len() as i64 - 1);
+        self.pending[(index - self.dropped as i64) as usize]
+    }
+}
+
+/// Catmull-Rom spline through `x0` and `x1` at `t` in [0.0, 1.0), using `xm1` and `x2` for the tangents
+fn catmull_rom(xm1: f32, x0: f32, x1: f32, x2: f32, t: f32) -> f32 {
+    let a = -0.5 * xm1 + 1.5 * x0 - 1.5 * x1 + 0.5 * x2;
+    let b = xm1 - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+    let c = -0.5 * xm1 + 0.5 * x1;
+    ((a * t + b) * t + c) * t + x0
+}
+
+/// A pair of [`ChannelResampler`]s for stereo audio
+#[derive(Clone, Debug)]
+pub struct StereoResampler {
+    left: ChannelResampler,
+    right: ChannelResampler,
+}
+
+impl StereoResampler {
+    pub fn new(in_rate: u32, out_rate: u32, mode: ResampleMode) -> Self {
+        StereoResampler {
+            left: ChannelResampler::new(in_rate, out_rate, mode),
+            right: ChannelResampler::new(in_rate, out_rate, mode),
+        }
+    }
+
+    /// See [`ChannelResampler::with_antialias`]
+    pub fn with_antialias(self, cutoff: f32) -> Self {
+        StereoResampler {
+            left: self.left.with_antialias(cutoff),
+            right: self.right.with_antialias(cutoff),
+        }
+    }
+
+    /// Resamples a block of both channels, appending the results to `left_out` and `right_out`
+    pub fn process(&mut self, left: &[f32], right: &[f32], left_out: &mut Vec<f32>, right_out: &mut Vec<f32>) {
+        self.left.process(left, left_out);
+        self.right.process(right, right_out);
+    }
+
+    pub fn flush(&mut self, left_out: &mut Vec<f32>, right_out: &mut Vec<f32>) {
+        self.left.flush(left_out);
+        self.right.flush(right_out);
+    }
+}
+
+/// Interpolation used when resampling
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleMode {
+    /// Zero-order hold, the NDS's own resampling that keeps all of the aliasing images
+    #[default]
+    Zoh,
+    /// Linear interpolation between neighbouring samples, which noticeably attenuates the images
+    Linear,
+    /// Catmull-Rom cubic interpolation over four samples, the cleanest of the three
+    Cubic,
+}
+
+impl ResampleMode {
+    /// Number of input samples needed before and after the current one
+    fn support(self) -> (u64, u64) {
+        match self {
+            ResampleMode::Zoh => (0, 0),
+            ResampleMode::Linear => (0, 1),
+            ResampleMode::Cubic => (1, 2),
+        }
+    }
+}
+
+impl FromStr for ResampleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zoh" => Ok(ResampleMode::Zoh),
+            "linear" => Ok(ResampleMode::Linear),
+            "cubic" => Ok(ResampleMode::Cubic),
+            _ => Err(format!("unknown resampling mode `{}` (expected zoh, linear or cubic)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ResampleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResampleMode::Zoh => "zoh",
+            ResampleMode::Linear => "linear",
+            ResampleMode::Cubic => "cubic",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use super::*;
+    use crate::config::RenderConfig;
+
+    /// Amplitude of the component at `frequency` of `signal`, a whole number of seconds at `sample_rate`
+    fn amplitude(signal: &[f32], sample_rate: u32, frequency: f64) -> f64 {
+        let (re, im) = signal.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, &x)| {
+            let phase = 2.0 * PI * frequency * n as f64 / sample_rate as f64;
+            (re + x as f64 * phase.cos(), im - x as f64 * phase.sin())
+        });
+        2.0 * (re * re + im * im).sqrt() / signal.len() as f64
+    }
+
+    #[test]
+    fn zoh_images_follow_the_hold_response() {
+        const FACTOR: u32 = 4;
+        let nds_rate = RenderConfig::default().nds_rate;
+        let out_rate = nds_rate * FACTOR;
+        // A second of a 1 kHz sine
+        let input: Vec<f32> = (0..nds_rate).map(|n| (2.0 * PI * 1000.0 * n as f64 / nds_rate as f64).sin() as f32).collect();
+        let output = resample_zoh(&input, nds_rate, out_rate);
+        assert_eq!(output.len(), (nds_rate * FACTOR) as usize);
+        // Every input sample is held for `FACTOR` output samples
+        assert!(output.chunks(FACTOR as usize).zip(&input).all(|(held, &x)| held.iter().all(|&y| y == x)));
+
+        // Holding is a box filter, a sampled sinc that lets the images through attenuated instead of removing them
+        let hold = |frequency: f64| {
+            let x = PI * frequency / out_rate as f64;
+            ((FACTOR as f64 * x).sin() / (FACTOR as f64 * x.sin())).abs()
+        };
+        let fundamental = amplitude(&output, out_rate, 1000.0);
+        assert!((fundamental - hold(1000.0)).abs() < 1e-3, "fundamental at {}", fundamental);
+        for image in [nds_rate as f64 - 1000.0, nds_rate as f64 + 1000.0, 2.0 * nds_rate as f64 - 1000.0] {
+            let level = amplitude(&output, out_rate, image);
+            let expected = hold(image);
+            assert!(expected > 0.01);
+            assert!((level - expected).abs() < 1e-3, "image at {} Hz is {}, expected {}", image, level, expected);
+        }
+        // Nothing between the images
+        assert!(amplitude(&output, out_rate, 16000.0) < 1e-3);
+    }
+
+    #[test]
+    fn stereo_resampler_runs_each_channel_on_its_own() {
+        let left: Vec<f32> = (0..1000).map(|n| (n as f32 * 0.37).sin()).collect();
+        let right: Vec<f32> = (0..1000).map(|n| (n as f32 * 0.011).cos() * 0.5).collect();
+        for mode in [ResampleMode::Zoh, ResampleMode::Linear, ResampleMode::Cubic] {
+            for out_rate in [48000, 44100, 22050] {
+                let mut resampler = StereoResampler::new(32728, out_rate, mode).with_antialias(0.9);
+                let (mut left_out, mut right_out) = (Vec::new(), Vec::new());
+                for (l, r) in left.chunks(129).zip(right.chunks(129)) {
+                    resampler.process(l, r, &mut left_out, &mut right_out);
+                }
+                resampler.flush(&mut left_out, &mut right_out);
+                let channel = |input: &[f32]| {
+                    let mut resampler = ChannelResampler::new(32728, out_rate, mode).with_antialias(0.9);
+                    let mut output = Vec::new();
+                    resampler.process(input, &mut output);
+                    resampler.flush(&mut output);
+                    output
+                };
+                assert_eq!(left_out.len(), output_len(1000, 32728, out_rate) as usize);
+                assert_eq!(left_out, channel(&left), "{} at {} Hz", mode, out_rate);
+                assert_eq!(right_out, channel(&right), "{} at {} Hz", mode, out_rate);
+            }
+        }
+    }
+
+    #[test]
+    fn zoh_in_blocks_matches_a_single_pass() {
+        let input: Vec<f32> = (0..1000).map(|n| (n as f32 * 0.37).sin()).collect();
+        let whole = resample_zoh(&input, 32728, 48000);
+        let mut resampler = ChannelResampler::new(32728, 48000, ResampleMode::Zoh);
+        let mut blocks = Vec::new();
+        for block in input.chunks(97) {
+            resampler.process(block, &mut blocks);
+        }
+        resampler.flush(&mut blocks);
+        assert_eq!(whole, blocks);
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::{self, Write, Seek, SeekFrom};
+
+use crate::error::RenderError;
+
+/// The id and body of a chunk
+pub(crate) type Chunk<'a> = (&'a [u8; 4], &'a [u8]);
+
+/// Appends a chunk with the given `id` to the RIFF file in `file` and updates the size in its header
+/// 
+/// `hound` has no way of writing chunks of its own, so extra metadata is patched into the finished file with this.
+pub fn append_chunk<F: Write + Seek>(file: &mut F, id: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(id)?;
+    file.write_all(&(data.len() as u32).to_le_bytes())?;
+    file.write_all(data)?;
+    // Chunks are word-aligned
+    if data.len() % 2 == 1 {
+        file.write_all(&[0])?;
+    }
+    let length = file.stream_position()?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&((length - 8) as u32).to_le_bytes())?;
+    file.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+/// Body of a `smpl` chunk describing a single forward loop over the frames `start..=end`, repeating forever
+pub fn smpl_chunk(sample_rate: u32, start: u32, end: u32) -> Vec<u8> {
+    smpl_chunk_with_unity_note(sample_rate, 60, start, end)
+}
+
+/// Same as [`smpl_chunk`], but for audio that plays at its recorded pitch on MIDI note `unity_note` instead of middle C
+pub fn smpl_chunk_with_unity_note(sample_rate: u32, unity_note: u32, start: u32, end: u32) -> Vec<u8> {
+    let sample_period = (1_000_000_000 / sample_rate.max(1) as u64) as u32;
+    let fields: [u32; 15] = [
+        0, // manufacturer
+        0, // product
+        sample_period, // nanoseconds per sample
+        unity_note, // MIDI unity note
+        0, // MIDI pitch fraction
+        0, // SMPTE format
+        0, // SMPTE offset
+        1, // number of loops
+        0, // sampler data size
+        // The loop
+        0, // cue point ID
+        0, // type, forward
+        start,
+        end,
+        0, // fraction
+        0, // play count, infinite
+    ];
+    fields.iter().flat_map(|field| field.to_le_bytes()).collect()
+}
+
+/// The Standard MIDI File inside an RMID file, along with the DLS bank it embeds as a complete RIFF file, if any
+/// 
+/// Returns `None` if `bytes` is not an RMID file.
+pub fn unwrap_rmid(bytes: &[u8]) -> Option<(&[u8], Option<Vec<u8>>)> {
+    let (_, body) = *chunks(bytes).ok()?.first().filter(|(id, body)| *id == b"RIFF" && body.starts_with(b"RMID"))?;
+    let children = chunks(&body[4..]).ok()?;
+    let smf = children.iter().find(|(id, _)| *id == b"data").map(|(_, data)| *data)?;
+    let dls = children.iter().find(|(id, body)| *id == b"RIFF" && body.starts_with(b"DLS ")).map(|(_, body)| {
+        let mut dls = Vec::with_capacity(body.len() + 8);
+        write_chunk(&mut dls, b"RIFF", body);
+        dls
+    });
+    Some((smf, dls))
+}
+
+/// The body of the `LIST` chunk of type `kind` among the chunks in `data`, without the type
+pub(crate) fn find_list<'a>(data: &'a [u8], kind: &[u8; 4]) -> Result<&'a [u8], RenderError> {
+    chunks(data)?.into_iter()
+        .find(|(id, body)| *id == b"LIST" && body.starts_with(kind))
+        .map(|(_, body)| &body[4..])
+        .ok_or_else(|| invalid(&format!("missing {} list", String::from_utf8_lossy(kind))))
+}
+
+/// The body of the chunk `id` among the chunks in `data`
+pub(crate) fn find_chunk<'a>(data: &'a [u8], id: &[u8; 4]) -> Result<&'a [u8], RenderError> {
+    chunks(data)?.into_iter()
+        .find(|(chunk_id, _)| *chunk_id == id)
+        .map(|(_, body)| body)
+        .ok_or_else(|| invalid(&format!("missing {} chunk", String::from_utf8_lossy(id))))
+}
+
+/// The ids and bodies of the consecutive RIFF chunks in `data`
+pub(crate) fn chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>, RenderError> {
+    let mut chunks = Vec::new();
+    let mut position = 0;
+    while position + 8 <= data.len() {
+        let id: &[u8; 4] = data[position..position + 4].try_into().expect("slice of 4 bytes");
+        let length = u32::from_le_bytes([data[position + 4], data[position + 5], data[position + 6], data[position + 7]]) as usize;
+        let body = data.get(position + 8..position + 8 + length).ok_or_else(|| invalid("chunk runs past the end of the file"))?;
+        chunks.push((id, body));
+        // Chunks are word-aligned
+        position += 8 + length + length % 2;
+    }
+    Ok(chunks)
+}
+
+/// Appends a chunk to `out`, padded to word alignment
+pub(crate) fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Appends a `LIST` chunk of type `kind` to `out`
+pub(crate) fn write_list(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+    write_chunk(out, b"LIST", &body);
+}
+
+fn invalid(message: &str) -> RenderError {
+    RenderError::InvalidSoundFont(message.to_string())
+}
@@ -0,0 +1,68 @@
+use std::{collections::HashSet, fs::OpenOptions, path::{Path, PathBuf}};
+use rustysynth::{SoundFont, SampleHeader};
+
+use crate::error::RenderError;
+use crate::output::write_atomically;
+use crate::riff::{append_chunk, smpl_chunk_with_unity_note};
+
+/// Writes every sample of `sound_font` into `output_folder` as a 16-bit mono wave-file at its own sample rate, returning the paths written
+/// 
+/// Files are named after the samples, with characters that do not belong in file names replaced and a number added to repeated names.
+/// Looping samples get a `smpl` chunk with their loop and original pitch.
+pub fn dump_samples<P: AsRef<Path>>(sound_font: &SoundFont, output_folder: P) -> Result<Vec<PathBuf>, RenderError> {
+    std::fs::create_dir_all(&output_folder)?;
+    let wave_data = sound_font.get_wave_data();
+    let mut names = HashSet::new();
+    let mut paths = Vec::new();
+    for header in sound_font.get_sample_headers() {
+        let start = (header.get_start().max(0) as usize).min(wave_data.len());
+        let end = (header.get_end().max(0) as usize).clamp(start, wave_data.len());
+        let path = output_folder.as_ref().join(format!("{}.wav", unique_name(&mut names, header.get_name())));
+
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: header.get_sample_rate().max(1) as u32,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        write_atomically(&path, |path| {
+            let mut writer = hound::WavWriter::create(path, spec)?;
+            for &sample in &wave_data[start..end] {
+                writer.write_sample(sample)?;
+            }
+            writer.finalize()?;
+
+            if let Some((loop_start, loop_end)) = sample_loop(header, start, end) {
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                let unity_note = header.get_original_pitch().clamp(0, 127) as u32;
+                append_chunk(&mut file, b"smpl", &smpl_chunk_with_unity_note(spec.sample_rate, unity_note, loop_start, loop_end))?;
+            }
+            Ok(())
+        })?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// The loop of `header` as inclusive frames of the sample running from `start` to `end` in the wave data, if it has a usable one
+fn sample_loop(header: &SampleHeader, start: usize, end: usize) -> Option<(u32, u32)> {
+    let loop_start = (header.get_start_loop().max(0) as usize).checked_sub(start)?;
+    // The loop end of a soundfont is the first sample after the loop
+    let loop_end = (header.get_end_loop().max(0) as usize).min(end).checked_sub(start + 1)?;
+    (loop_start < loop_end).then_some((loop_start as u32, loop_end as u32))
+}
+
+/// `name` made safe to use as a file name, numbered if an earlier sample already took it
+fn unique_name(names: &mut HashSet<String>, name: &str) -> String {
+    let mut base: String = name.trim().chars().map(|c| if c.is_alphanumeric() || " -_.()".contains(c) { c } else { '_' }).collect();
+    if base.is_empty() || base.chars().all(|c| c == '.') {
+        base = "sample".to_string();
+    }
+    let mut unique = base.clone();
+    let mut number = 2;
+    while !names.insert(unique.to_lowercase()) {
+        unique = format!("{} ({})", base, number);
+        number += 1;
+    }
+    unique
+}
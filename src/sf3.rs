@@ -0,0 +1,132 @@
+use std::io::Cursor;
+use lewton::inside_ogg::OggStreamReader;
+
+use crate::error::RenderError;
+use crate::riff::{chunks, find_chunk, find_list, write_chunk, write_list};
+
+/// Size of a sample header record of the `shdr` chunk
+const SAMPLE_HEADER_SIZE: usize = 46;
+/// Flag of the sample type of a sample header marking Ogg Vorbis-compressed sample data
+const COMPRESSED_SAMPLE: u16 = 0x10;
+/// Zero samples that the SF2 specification requires after every sample
+const SAMPLE_PADDING: usize = 46;
+
+/// Whether `bytes` is an SF3 soundfont, whose samples are compressed with Ogg Vorbis
+pub fn is_sf3(bytes: &[u8]) -> bool {
+    let Ok(top) = riff_body(bytes) else { return false };
+    let Ok(info) = find_list(top, b"INFO") else { return false };
+    // The version in `ifil` is 3.x for SF3
+    matches!(find_chunk(info, b"ifil"), Ok(ifil) if ifil.len() >= 2 && u16::from_le_bytes([ifil[0], ifil[1]]) == 3)
+}
+
+/// Decodes the Ogg Vorbis samples of the SF3 soundfont in `bytes`, returning an equivalent SF2 soundfont with 16-bit PCM samples
+/// 
+/// `rustysynth` only reads uncompressed samples, so this lets SF3 soundfonts be loaded like any other. Everything but the samples,
+/// their headers and the version is copied over unchanged.
+pub fn decode_sf3(bytes: &[u8]) -> Result<Vec<u8>, RenderError> {
+    let top = riff_body(bytes)?;
+    let info = find_list(top, b"INFO")?;
+    let sdta = find_list(top, b"sdta")?;
+    let pdta = find_list(top, b"pdta")?;
+    let smpl = find_chunk(sdta, b"smpl")?;
+    let shdr = find_chunk(pdta, b"shdr")?;
+
+    let mut samples: Vec<i16> = Vec::new();
+    let mut headers = shdr.to_vec();
+    for header in headers.chunks_exact_mut(SAMPLE_HEADER_SIZE) {
+        let field = |offset: usize| u32::from_le_bytes([header[offset], header[offset + 1], header[offset + 2], header[offset + 3]]);
+        let (start, end, start_loop, end_loop) = (field(20), field(24), field(28), field(32));
+        let sample_type = u16::from_le_bytes([header[44], header[45]]);
+        let position = samples.len() as u32;
+        let (new_start, new_end, new_start_loop, new_end_loop) = if sample_type & COMPRESSED_SAMPLE != 0 {
+            // `start` and `end` are byte offsets of the Ogg stream, and the loop is relative to the start of the decoded sample
+            let stream = smpl.get(start as usize..).ok_or_else(|| invalid("compressed sample starts past the sample data"))?;
+            let decoded = decode_vorbis(&stream[..ogg_stream_length(stream, end.saturating_sub(start) as usize + 1)])?;
+            samples.extend_from_slice(&decoded);
+            (position, position + decoded.len() as u32, position + start_loop, position + end_loop)
+        } else {
+            // ROM samples and the terminating header have no data of their own to move
+            let range = smpl.get(start as usize * 2..end as usize * 2);
+            match range {
+                Some(data) if end > start => {
+                    samples.extend(data.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])));
+                    (position, position + end - start, position + start_loop.saturating_sub(start), position + end_loop.saturating_sub(start))
+                }
+                _ => (position, position, position, position),
+            }
+        };
+        if new_end > position {
+            samples.resize(samples.len() + SAMPLE_PADDING, 0);
+        }
+        header[20..24].copy_from_slice(&new_start.to_le_bytes());
+        header[24..28].copy_from_slice(&new_end.to_le_bytes());
+        header[28..32].copy_from_slice(&new_start_loop.to_le_bytes());
+        header[32..36].copy_from_slice(&new_end_loop.to_le_bytes());
+        header[44..46].copy_from_slice(&(sample_type & !COMPRESSED_SAMPLE).to_le_bytes());
+    }
+
+    let mut new_info = Vec::new();
+    for (id, data) in chunks(info)? {
+        if id == b"ifil" {
+            // Version 2.01
+            write_chunk(&mut new_info, b"ifil", &[2, 0, 1, 0]);
+        } else {
+            write_chunk(&mut new_info, id, data);
+        }
+    }
+    let sample_bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+    let mut new_sdta = Vec::new();
+    write_chunk(&mut new_sdta, b"smpl", &sample_bytes);
+    let mut new_pdta = Vec::new();
+    for (id, data) in chunks(pdta)? {
+        write_chunk(&mut new_pdta, id, if id == b"shdr" { &headers } else { data });
+    }
+
+    let mut body = b"sfbk".to_vec();
+    write_list(&mut body, b"INFO", &new_info);
+    write_list(&mut body, b"sdta", &new_sdta);
+    write_list(&mut body, b"pdta", &new_pdta);
+    let mut sf2 = Vec::with_capacity(body.len() + 8);
+    write_chunk(&mut sf2, b"RIFF", &body);
+    Ok(sf2)
+}
+
+/// Decodes a single Ogg Vorbis stream into 16-bit samples, keeping only the first channel
+fn decode_vorbis(stream: &[u8]) -> Result<Vec<i16>, RenderError> {
+    let mut reader = OggStreamReader::new(Cursor::new(stream)).map_err(|e| invalid(&format!("failed to decode sample: {}", e)))?;
+    let channels = reader.ident_hdr.audio_channels.max(1) as usize;
+    let mut samples = Vec::new();
+    while let Some(packet) = reader.read_dec_packet_itl().map_err(|e| invalid(&format!("failed to decode sample: {}", e)))? {
+        samples.extend(packet.iter().step_by(channels));
+    }
+    Ok(samples)
+}
+
+/// Length in bytes of the Ogg stream at the start of `data`, found by walking its pages up to the one marked as the end
+/// 
+/// Encoders disagree on whether the end offset of a compressed sample is inclusive, so the pages are the reliable source; `fallback` is used if they cannot be walked.
+fn ogg_stream_length(data: &[u8], fallback: usize) -> usize {
+    let mut position = 0;
+    while data.len() >= position + 27 && &data[position..position + 4] == b"OggS" {
+        let header_type = data[position + 5];
+        let segment_count = data[position + 26] as usize;
+        let Some(segments) = data.get(position + 27..position + 27 + segment_count) else { break };
+        position += 27 + segment_count + segments.iter().map(|&length| length as usize).sum::<usize>();
+        // End of stream
+        if header_type & 0x04 != 0 {
+            return position.min(data.len());
+        }
+    }
+    fallback.min(data.len())
+}
+
+fn riff_body(bytes: &[u8]) -> Result<&[u8], RenderError> {
+    match chunks(bytes)?.first() {
+        Some((b"RIFF", body)) if body.starts_with(b"sfbk") => Ok(&body[4..]),
+        _ => Err(invalid("not a RIFF soundfont")),
+    }
+}
+
+fn invalid(message: &str) -> RenderError {
+    RenderError::InvalidSoundFont(message.to_string())
+}
@@ -0,0 +1,105 @@
+use crate::normalize::db_to_gain;
+
+/// Ends a render once it has gone quiet instead of after a fixed length, see [`crate::RenderConfig::stop_on_silence`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SilenceStop {
+    /// Level in dBFS that every sample has to stay below to count as silence
+    pub threshold_dbfs: f32,
+    /// How long the output has to stay silent before the render stops, in seconds
+    pub hold: f64,
+    /// Longest render in seconds, for patches that sustain forever
+    pub max_duration: f64,
+}
+
+impl Default for SilenceStop {
+    fn default() -> Self {
+        SilenceStop {
+            threshold_dbfs: -70.0,
+            hold: 1.0,
+            max_duration: 600.0,
+        }
+    }
+}
+
+/// Watches a render block by block for the point where it has stayed silent long enough
+/// 
+/// Silence before the first audible sample does not count, so a file that starts with rests is not cut short.
+pub(crate) struct SilenceDetector {
+    threshold: f32,
+    hold_frames: usize,
+    heard: bool,
+    silent_frames: usize,
+}
+
+impl SilenceDetector {
+    pub fn new(stop: &SilenceStop, sample_rate: u32) -> Self {
+        SilenceDetector {
+            threshold: db_to_gain(stop.threshold_dbfs),
+            hold_frames: ((stop.hold * sample_rate as f64).round() as usize).max(1),
+            heard: false,
+            silent_frames: 0,
+        }
+    }
+
+    /// Number of frames of the block to keep if the render should stop within it, at the end of the silent hold
+    pub fn stop_within(&mut self, left: &[f32], right: &[f32]) -> Option<usize> {
+        for (i, (l, r)) in left.iter().zip(right.iter()).enumerate() {
+            if l.abs() >= self.threshold || r.abs() >= self.threshold {
+                self.heard = true;
+                self.silent_frames = 0;
+            } else if self.heard {
+                self.silent_frames += 1;
+                if self.silent_frames >= self.hold_frames {
+                    return Some(i + 1);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Drops silent frames from the start and end of a render as it streams through
+/// 
+/// Silent frames after the first audible one are held back until something audible follows them, and are dropped if nothing does.
+pub(crate) struct SilenceTrimmer {
+    threshold: f32,
+    started: bool,
+    held_left: Vec<f32>,
+    held_right: Vec<f32>,
+    trimmed: usize,
+}
+
+impl SilenceTrimmer {
+    pub fn new(threshold_dbfs: f32) -> Self {
+        SilenceTrimmer {
+            threshold: db_to_gain(threshold_dbfs),
+            started: false,
+            held_left: Vec::new(),
+            held_right: Vec::new(),
+            trimmed: 0,
+        }
+    }
+
+    /// Appends the frames of a block that are certain to be kept to `left_out` and `right_out`
+    pub fn process(&mut self, left: &[f32], right: &[f32], left_out: &mut Vec<f32>, right_out: &mut Vec<f32>) {
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            if l.abs() >= self.threshold || r.abs() >= self.threshold {
+                self.started = true;
+                left_out.append(&mut self.held_left);
+                right_out.append(&mut self.held_right);
+                left_out.push(l);
+                right_out.push(r);
+            } else if self.started {
+                self.held_left.push(l);
+                self.held_right.push(r);
+            } else {
+                self.trimmed += 1;
+            }
+        }
+    }
+
+    /// Drops the trailing silence and returns the number of frames trimmed from the start and from the end
+    pub fn finish(self) -> (usize, usize) {
+        (self.trimmed, self.held_left.len())
+    }
+}
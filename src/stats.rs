@@ -0,0 +1,179 @@
+use crate::config::RenderConfig;
+use crate::output::{SampleFormat, Endianness, to_signed_int, to_unsigned_u8, raw_bits_per_sample};
+
+/// Level statistics of a render, measured on the signal right before bit reduction (after any normalization)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// Number of stereo frames rendered
+    pub frames: usize,
+    /// Largest absolute sample value across both channels
+    pub peak: f32,
+    /// Root-mean-square level across both channels
+    pub rms: f32,
+    /// Number of samples that hit or exceeded full scale (±1.0)
+    pub clipped_samples: usize,
+    /// Gain applied by normalization in dB, 0.0 without it
+    pub gain_db: f32,
+    /// Number of silent frames removed from the start by [`crate::RenderConfig::trim`]
+    pub trimmed_start: usize,
+    /// Number of silent frames removed from the end by [`crate::RenderConfig::trim`]
+    pub trimmed_end: usize,
+    /// 64-bit FNV-1a hash of the samples as they are encoded into the output, see [`OutputHasher`]
+    /// 
+    /// Rendering is deterministic, dither included, so this only changes when the output does, which makes it suitable for regression tests.
+    pub hash: u64,
+}
+
+impl RenderStats {
+    /// Peak level in dBFS
+    pub fn peak_dbfs(&self) -> f32 {
+        to_dbfs(self.peak)
+    }
+
+    /// RMS level in dBFS
+    pub fn rms_dbfs(&self) -> f32 {
+        to_dbfs(self.rms)
+    }
+}
+
+fn to_dbfs(x: f32) -> f32 {
+    20.0 * x.log10()
+}
+
+/// Accumulates [`RenderStats`] block by block
+#[derive(Default)]
+pub(crate) struct StatsAccumulator {
+    frames: usize,
+    peak: f32,
+    sum_of_squares: f64,
+    clipped_samples: usize,
+}
+
+impl StatsAccumulator {
+    pub fn add(&mut self, left: &[f32], right: &[f32]) {
+        self.frames += left.len();
+        for &x in left.iter().chain(right.iter()) {
+            let magnitude = x.abs();
+            self.peak = self.peak.max(magnitude);
+            self.sum_of_squares += (x as f64) * (x as f64);
+            if magnitude >= 1.0 {
+                self.clipped_samples += 1;
+            }
+        }
+    }
+
+    pub fn finish(self) -> RenderStats {
+        let rms = if self.frames == 0 {
+            0.0
+        } else {
+            (self.sum_of_squares / (self.frames * 2) as f64).sqrt() as f32
+        };
+        RenderStats {
+            frames: self.frames,
+            peak: self.peak,
+            rms,
+            clipped_samples: self.clipped_samples,
+            gain_db: 0.0,
+            trimmed_start: 0,
+            trimmed_end: 0,
+            hash: 0,
+        }
+    }
+}
+
+/// Hashes the samples of a render block by block as [`RenderConfig::format`] encodes them, which are the bytes of the sample data
+/// of a wave or raw file, in frame order with just the left channel of a mono output
+/// 
+/// FLAC, Ogg Vorbis and ADPCM encode these samples further, so for them it is the samples handed to the encoder.
+/// FNV-1a is used since, unlike the hashers of `std`, it is specified to stay the same across versions and platforms.
+pub(crate) struct OutputHasher {
+    hash: u64,
+    stereo: bool,
+    format: SampleFormat,
+    /// Bits per sample and byte order of [`SampleFormat::Raw`]
+    raw_bits: u32,
+    endian: Endianness,
+}
+
+impl OutputHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new(config: &RenderConfig) -> Self {
+        OutputHasher {
+            hash: Self::OFFSET_BASIS,
+            stereo: config.channels() > 1,
+            format: config.format,
+            raw_bits: raw_bits_per_sample(config.bitdepth),
+            endian: config.endian,
+        }
+    }
+
+    pub fn add(&mut self, left: &[f32], right: &[f32]) {
+        for (i, &l) in left.iter().enumerate() {
+            self.write(l);
+            if self.stereo {
+                self.write(right[i]);
+            }
+        }
+    }
+
+    fn write(&mut self, x: f32) {
+        let (bytes, length) = match self.format {
+            SampleFormat::Float32 => (x.to_le_bytes(), 4),
+            SampleFormat::UInt8 => ([to_unsigned_u8(x), 0, 0, 0], 1),
+            SampleFormat::Int16 => (to_signed_int(x, 16).to_le_bytes(), 2),
+            SampleFormat::Int24 => (to_signed_int(x, 24).to_le_bytes(), 3),
+            SampleFormat::Raw => {
+                let length = (self.raw_bits / 8) as usize;
+                match self.endian {
+                    Endianness::Little => (to_signed_int(x, self.raw_bits).to_le_bytes(), length),
+                    Endianness::Big => {
+                        // The bytes that are written are the last ones
+                        let mut bytes = to_signed_int(x, self.raw_bits).to_be_bytes();
+                        bytes.rotate_left(4 - length);
+                        (bytes, length)
+                    }
+                }
+            }
+        };
+        for &byte in &bytes[..length] {
+            self.hash = (self.hash ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+    use crate::output::WavFileWriter;
+
+    /// FNV-1a of `bytes`, the way [`OutputHasher`] hashes them
+    fn fnv(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(OutputHasher::OFFSET_BASIS, |hash, &byte| (hash ^ byte as u64).wrapping_mul(OutputHasher::PRIME))
+    }
+
+    #[test]
+    fn hash_covers_the_sample_data_of_a_wave_file() {
+        let left = [0.0, 0.5, -0.25, 1.0, -1.0, 0.123_456];
+        let right = [0.1, -0.5, 0.75, -1.0, 1.0, -0.987_654];
+        for format in [SampleFormat::Float32, SampleFormat::UInt8, SampleFormat::Int16, SampleFormat::Int24] {
+            let config = RenderConfig { format, ..Default::default() };
+            let mut hasher = OutputHasher::new(&config);
+            hasher.add(&left, &right);
+            let mut wav = Cursor::new(Vec::new());
+            let mut writer = WavFileWriter::new(&mut wav, 32768, 2, format).unwrap();
+            writer.write(&left, &right).unwrap();
+            writer.finalize().unwrap();
+            let wav = wav.into_inner();
+            // The data chunk comes last
+            let data = &wav[wav.len() - left.len() * 2 * format.bits_per_sample() as usize / 8..];
+            assert_eq!(hasher.finish(), fnv(data), "{:?}", format);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Tiny soundfonts and MIDI files built in memory for the tests, so that they need nothing on disk
+
+use std::sync::Arc;
+use rustysynth::SoundFont;
+
+use crate::load::load_sound_font_from_bytes;
+use crate::midi::{Smf, TrackEvent, EventKind, META_TEMPO, META_MARKER, META_END_OF_TRACK};
+
+/// Ticks per quarter note of the files of [`smf`]
+pub(crate) const DIVISION: u16 = 480;
+
+/// A RIFF chunk with the id `id` around `data`, padded to a whole word
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut bytes = id.to_vec();
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    bytes
+}
+
+/// A `LIST` (or `RIFF`) chunk of the form `kind` around `chunks`
+fn list(id: &[u8; 4], kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = kind.to_vec();
+    for chunk in chunks {
+        data.extend_from_slice(chunk);
+    }
+    chunk(id, &data)
+}
+
+/// A name field of `length` bytes, padded with zeros
+fn name(text: &str, length: usize) -> Vec<u8> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.resize(length, 0);
+    bytes
+}
+
+fn words(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+/// The bytes of an SF2 file with a single preset, bank 0 program 0, playing a looped sine at middle C on every key
+pub(crate) fn sine_sf2() -> Vec<u8> {
+    const SAMPLE_RATE: u32 = 32000;
+    const LENGTH: u32 = 800;
+    // Whole periods, so that the loop is seamless
+    let mut wave: Vec<i16> = (0..LENGTH).map(|i| ((i as f64 * 2.0 * std::f64::consts::PI * 40.0 / LENGTH as f64).sin() * 16000.0) as i16).collect();
+    // Every sample is followed by at least 46 zeros
+    wave.resize(LENGTH as usize + 46, 0);
+    let smpl: Vec<u8> = wave.iter().flat_map(|x| x.to_le_bytes()).collect();
+
+    let info = list(b"LIST", b"INFO", &[
+        chunk(b"ifil", &words(&[2, 1])),
+        chunk(b"isng", b"EMU8000\0"),
+        chunk(b"INAM", b"Sine\0\0"),
+    ]);
+    let sdta = list(b"LIST", b"sdta", &[chunk(b"smpl", &smpl)]);
+
+    // Each list of records ends with a terminal one, which the indices of the list before point past
+    let phdr_record = |preset_name: &str, preset: u16, bank: u16, bag: u16| [name(preset_name, 20), words(&[preset, bank, bag]), vec![0; 12]].concat();
+    let phdr = [phdr_record("Sine", 0, 0, 0), phdr_record("EOP", 0, 0, 1)].concat();
+    let pbag = words(&[0, 0, 1, 0]);
+    let pmod = vec![0; 10];
+    // Instrument 0
+    let pgen = words(&[41, 0, 0, 0]);
+    let inst = [name("Sine", 20), words(&[0]), name("EOI", 20), words(&[1])].concat();
+    let ibag = words(&[0, 0, 2, 0]);
+    let imod = vec![0; 10];
+    // Continuous loop, then sample 0, which has to come last in its zone
+    let igen = words(&[54, 1, 53, 0, 0, 0]);
+    let shdr_record = |sample_name: &str, start: u32, end: u32, start_loop: u32, end_loop: u32, sample_rate: u32, sample_type: u16| {
+        let fields: Vec<u8> = [start, end, start_loop, end_loop, sample_rate].iter().flat_map(|field| field.to_le_bytes()).collect();
+        [name(sample_name, 20), fields, vec![60, 0], words(&[0, sample_type])].concat()
+    };
+    let shdr = [shdr_record("Sine", 0, LENGTH, 0, LENGTH, SAMPLE_RATE, 1), shdr_record("EOS", 0, 0, 0, 0, 0, 0)].concat();
+    let pdta = list(b"LIST", b"pdta", &[
+        chunk(b"phdr", &phdr),
+        chunk(b"pbag", &pbag),
+        chunk(b"pmod", &pmod),
+        chunk(b"pgen", &pgen),
+        chunk(b"inst", &inst),
+        chunk(b"ibag", &ibag),
+        chunk(b"imod", &imod),
+        chunk(b"igen", &igen),
+        chunk(b"shdr", &shdr),
+    ]);
+    list(b"RIFF", b"sfbk", &[info, sdta, pdta])
+}
+
+/// [`sine_sf2`] loaded
+pub(crate) fn sine_sound_font() -> Arc<SoundFont> {
+    load_sound_font_from_bytes(&sine_sf2()).expect("the test soundfont is valid")
+}
+
+/// A channel message on the first channel
+pub(crate) fn midi(delta: u32, status: u8, data1: u8, data2: u8) -> TrackEvent {
+    TrackEvent { delta, kind: EventKind::Midi { status, data1, data2 } }
+}
+
+/// A tempo change to `tempo` microseconds per quarter note
+pub(crate) fn tempo(delta: u32, tempo: u32) -> TrackEvent {
+    TrackEvent { delta, kind: EventKind::Meta { kind: META_TEMPO, data: tempo.to_be_bytes()[1..].to_vec() } }
+}
+
+/// A marker named `text`
+pub(crate) fn marker(delta: u32, text: &str) -> TrackEvent {
+    TrackEvent { delta, kind: EventKind::Meta { kind: META_MARKER, data: text.as_bytes().to_vec() } }
+}
+
+pub(crate) fn end_of_track(delta: u32) -> TrackEvent {
+    TrackEvent { delta, kind: EventKind::Meta { kind: META_END_OF_TRACK, data: Vec::new() } }
+}
+
+/// A format 0 file of `events`, at [`DIVISION`] ticks per quarter note
+pub(crate) fn smf(events: Vec<TrackEvent>) -> Smf {
+    Smf { format: 0, division: DIVISION, tracks: vec![events] }
+}
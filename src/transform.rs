@@ -0,0 +1,863 @@
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+use crate::config::RenderConfig;
+use crate::midi::{Smf, TrackEvent, EventKind, DRUM_CHANNEL, META_TEMPO, META_MARKER, META_END_OF_TRACK, DEFAULT_MICROSECONDS_PER_QUARTER};
+use crate::midi::{LOOP_START_MARKER, LOOP_END_MARKER, LOOP_START_CONTROLLER, is_marker_named};
+
+/// Applies the MIDI event changes of `config`, such as muting channels, to `smf`
+/// 
+/// Returns whether anything had to be changed, in which case the file has to go through [`Smf::to_midi_file`] again before rendering it.
+/// The file-based render functions do this by themselves; the streaming ones render the [`rustysynth::MidiFile`] they are given as is.
+pub fn transform_midi(smf: &mut Smf, config: &RenderConfig) -> bool {
+    // The changes below line the tracks up side by side, which the sequences of a format 2 file are not
+    let mut changed = smf.chain_sequences();
+    // Its times are those of the file as it is, before any of the other changes
+    if let Some((start, end)) = config.loop_region {
+        set_loop_region(smf, start, end);
+        changed = true;
+    }
+    if !config.mute.is_empty() || !config.solo.is_empty() {
+        // Dropping every channel message rather than just the notes leaves nothing of a muted channel behind
+        let mut muted = [0_usize; 16];
+        smf.retain_events(|kind| match kind {
+            EventKind::Midi { status, .. } => {
+                let audible = config.is_channel_audible((status & 0x0F) + 1);
+                muted[(status & 0x0F) as usize] += !audible as usize;
+                audible
+            }
+            _ => true,
+        });
+        for (channel, &count) in muted.iter().enumerate().filter(|(_, &count)| count > 0) {
+            log::debug!("Muted {} events of channel {}", count, channel + 1);
+        }
+        changed = true;
+    }
+    // Channel numbers from here on are the ones the synthesizer plays
+    if config.drum_channel != DRUM_CHANNEL {
+        swap_channels(smf, config.drum_channel - 1, DRUM_CHANNEL - 1);
+        changed = true;
+    }
+    if !config.drum_map.is_empty() {
+        remap_drums(smf, &config.drum_map);
+        changed = true;
+    }
+    match config.bank_mode {
+        BankMode::Gm => {
+            smf.retain_events(|kind| !matches!(kind, EventKind::Midi { status, data1: 0x00 | 0x20, .. } if status & 0xF0 == 0xB0));
+            changed = true;
+        }
+        BankMode::Gs => {}
+        BankMode::Xg => {
+            select_xg_banks(smf);
+            changed = true;
+        }
+    }
+    if !config.programs.is_empty() {
+        override_programs(smf, &config.programs);
+        changed = true;
+    }
+    if let Some(semitones) = config.bend_range {
+        set_bend_range(smf, semitones);
+        changed = true;
+    }
+    if config.nds_pan {
+        for event in smf.tracks.iter_mut().flatten() {
+            if let EventKind::Midi { status, data1: 0x0A, data2 } = &mut event.kind {
+                if *status & 0xF0 == 0xB0 {
+                    *data2 = nds_pan(*data2);
+                }
+            }
+        }
+        changed = true;
+    }
+    if config.transpose != 0 {
+        transpose(smf, config.transpose);
+        changed = true;
+    }
+    if let Some(bpm) = config.bpm {
+        smf.retain_events(|kind| !matches!(kind, EventKind::Meta { kind: META_TEMPO, .. }));
+        if let Some(track) = smf.tracks.first_mut() {
+            let tempo = (60_000_000.0 / bpm).round() as u32;
+            track.insert(0, TrackEvent { delta: 0, kind: EventKind::Meta { kind: META_TEMPO, data: tempo_bytes(tempo) } });
+        }
+        changed = true;
+    }
+    if config.tempo_scale != 1.0 {
+        scale_tempo(smf, config.tempo_scale);
+        changed = true;
+    }
+    if config.velocity_scale != 1.0 || config.velocity_curve != VelocityCurve::Linear {
+        for event in smf.tracks.iter_mut().flatten() {
+            if let EventKind::Midi { status, data2, .. } = &mut event.kind {
+                // A note-on with velocity 0 is a note-off and has to stay one
+                if *status & 0xF0 == 0x90 && *data2 > 0 {
+                    *data2 = config.velocity_curve.map(*data2, config.velocity_scale);
+                }
+            }
+        }
+        changed = true;
+    }
+    if config.voice_stealing == VoiceStealing::Oldest {
+        steal_oldest_notes(smf, config.max_voices);
+        changed = true;
+    }
+    // Windowing comes last, since its times are in the tempo the file ends up with
+    if config.start > 0.0 || config.end.is_some() {
+        window(smf, config.start, config.end);
+        changed = true;
+    }
+    // Then repeating, so that every repetition is the windowed part; crossfaded repetitions are rendered one by one instead
+    if config.repeat != 1.0 && config.loop_crossfade == 0.0 {
+        repeat(smf, config.repeat);
+        changed = true;
+    }
+    changed
+}
+
+/// Plays `smf` `count` times in a row, the last time only partly for a fractional `count`
+/// 
+/// With loop points, see [`Smf::loop_points`], only the loop is repeated: what comes before it plays once, and of what comes after it only
+/// the note-offs are kept, at the end of the last repetition. Notes still sounding at the end of a repetition release into the next one,
+/// and only the last one is followed by the tail.
+fn repeat(smf: &mut Smf, count: f64) {
+    let track_length = |track: &Vec<TrackEvent>| track.iter().map(|event| event.delta as u64).sum::<u64>();
+    let length = smf.tracks.iter().map(track_length).max().unwrap_or(0);
+    let region = smf.loop_points().map(|(start, end)| (start, end.unwrap_or(length).min(length))).filter(|&(start, end)| start < end);
+    let (start, end) = region.unwrap_or((0, length));
+    // Otherwise every repetition after the first would start in the tempo the one before it ended in
+    let tempo_map = smf.tempo_map();
+    let tempo = tempo_map.changes().take_while(|&(tick, _)| tick <= start).last().map_or(DEFAULT_MICROSECONDS_PER_QUARTER, |(_, tempo)| tempo);
+    let has_tempo_at_start = smf.timeline().iter().any(|(tick, event)| *tick == start && matches!(event.kind, EventKind::Meta { kind: META_TEMPO, .. }));
+    let (start_seconds, end_seconds) = (tempo_map.seconds_at(start), tempo_map.seconds_at(end));
+    let loop_length = end - start;
+    let passes = count.ceil().max(1.0) as u64;
+    let last_end = end + (passes - 1) * loop_length;
+    for (index, track) in smf.tracks.iter_mut().enumerate() {
+        let mut tick = 0;
+        let mut events = Vec::new();
+        if index == 0 && !has_tempo_at_start {
+            events.push((start, EventKind::Meta { kind: META_TEMPO, data: tempo_bytes(tempo) }));
+        }
+        for event in track.iter() {
+            tick += event.delta as u64;
+            if !matches!(event.kind, EventKind::Meta { kind: META_END_OF_TRACK, .. }) {
+                events.push((tick, event.kind.clone()));
+            }
+        }
+        events.sort_by_key(|&(tick, _)| tick);
+        let is_note_on = |kind: &EventKind| matches!(*kind, EventKind::Midi { status, data2, .. } if status & 0xF0 == 0x90 && data2 > 0);
+        let is_note_off = |kind: &EventKind| matches!(*kind, EventKind::Midi { status, .. } if matches!(status & 0xF0, 0x80 | 0x90)) && !is_note_on(kind);
+        // Whatever ends the loop, such as its note-offs and `loopEnd` marker, belongs to every repetition, but notes starting there do not
+        let in_loop = |tick: u64, kind: &EventKind| tick >= start && (tick < end || (tick == end && (region.is_none() || !is_note_on(kind))));
+        let mut placed: Vec<(u64, EventKind)> = events.iter().filter(|(tick, _)| *tick < start).cloned().collect();
+        for pass in 0..passes {
+            placed.extend(events.iter().filter(|(tick, kind)| in_loop(*tick, kind)).map(|(tick, kind)| (tick + pass * loop_length, kind.clone())));
+        }
+        placed.extend(events.iter().filter(|(tick, kind)| *tick > end && is_note_off(kind)).map(|(_, kind)| (last_end, kind.clone())));
+        // Stable, so that events at the same tick keep their order, with the end of a repetition before the start of the next
+        placed.sort_by_key(|&(tick, _)| tick);
+        let mut previous = 0;
+        *track = placed.into_iter().map(|(tick, kind)| {
+            let delta = (tick - previous) as u32;
+            previous = tick;
+            TrackEvent { delta, kind }
+        }).collect();
+        track.push(TrackEvent { delta: (last_end - previous) as u32, kind: EventKind::Meta { kind: META_END_OF_TRACK, data: Vec::new() } });
+    }
+    if count.fract() != 0.0 {
+        window(smf, 0.0, Some(start_seconds + (end_seconds - start_seconds) * count));
+    }
+}
+
+/// Replaces the loop points of `smf` with `loopStart` and `loopEnd` markers at `start` and `end` seconds into it
+fn set_loop_region(smf: &mut Smf, start: f64, end: f64) {
+    smf.retain_events(|kind| match kind {
+        EventKind::Meta { kind: META_MARKER, data } => {
+            let text = String::from_utf8_lossy(data);
+            !is_marker_named(&text, LOOP_START_MARKER) && !is_marker_named(&text, LOOP_END_MARKER)
+        }
+        EventKind::Midi { status, data1: LOOP_START_CONTROLLER, .. } => status & 0xF0 != 0xB0,
+        _ => true,
+    });
+    let tempo_map = smf.tempo_map();
+    let marker = |name: &str| EventKind::Meta { kind: META_MARKER, data: name.as_bytes().to_vec() };
+    let markers = [
+        (tempo_map.tick_at(start).round() as u64, marker(LOOP_START_MARKER)),
+        (tempo_map.tick_at(end).round() as u64, marker(LOOP_END_MARKER)),
+    ];
+    if smf.tracks.is_empty() {
+        smf.tracks.push(Vec::new());
+    }
+    let track = &mut smf.tracks[0];
+    let mut tick = 0;
+    let mut events: Vec<(u64, EventKind)> = track.iter().map(|event| {
+        tick += event.delta as u64;
+        (tick, event.kind.clone())
+    }).collect();
+    // Before the end of the track, which moves back if a marker comes after it
+    let end_of_track = match events.last() {
+        Some((_, EventKind::Meta { kind: META_END_OF_TRACK, .. })) => events.pop(),
+        _ => None,
+    };
+    events.extend(markers);
+    events.sort_by_key(|&(tick, _)| tick);
+    if let Some((end_tick, end_of_track)) = end_of_track {
+        let last = events.last().map_or(0, |&(tick, _)| tick);
+        events.push((end_tick.max(last), end_of_track));
+    }
+    let mut previous = 0;
+    *track = events.into_iter().map(|(tick, kind)| {
+        let delta = (tick - previous) as u32;
+        previous = tick;
+        TrackEvent { delta, kind }
+    }).collect();
+}
+
+/// The pan controller value that places a channel where the NDS would put pan register value `pan`
+/// 
+/// The NDS pans linearly, with gains of `(127 - pan) / 127` and `pan / 127`, where `rustysynth` pans with equal power along a quarter circle.
+/// The value returned has the same ratio between the channels, rounded to one of the 128 steps that both the pan register and CC10 have.
+fn nds_pan(pan: u8) -> u8 {
+    let position = pan.min(127) as f64 / 127.0;
+    let angle = position.atan2(1.0 - position);
+    (angle / std::f64::consts::FRAC_PI_2 * 127.0).round() as u8
+}
+
+/// Ends the oldest sounding note whenever a note would start while `max_notes` are already sounding
+/// 
+/// The note-off a stolen note eventually gets from the file is dropped, so that it cannot end a later note of the same key.
+fn steal_oldest_notes(smf: &mut Smf, max_notes: usize) {
+    // Events at their absolute tick, with `None` for the dropped ones
+    let mut tracks: Vec<Vec<(u64, Option<EventKind>)>> = smf.tracks.iter().map(|track| {
+        let mut tick = 0;
+        track.iter().map(|event| {
+            tick += event.delta as u64;
+            (tick, Some(event.kind.clone()))
+        }).collect()
+    }).collect();
+    let mut timeline: Vec<(u64, usize, usize)> = tracks.iter().enumerate()
+        .flat_map(|(index, events)| events.iter().enumerate().map(move |(position, &(tick, _))| (tick, index, position)))
+        .collect();
+    timeline.sort_by_key(|&(tick, index, _)| (tick, index));
+
+    let mut sounding: VecDeque<(usize, u8, u8)> = VecDeque::new();
+    let mut stolen: Vec<(u8, u8)> = Vec::new();
+    let mut note_offs: Vec<Vec<(u64, Option<EventKind>)>> = vec![Vec::new(); tracks.len()];
+    for (tick, index, position) in timeline {
+        let Some(EventKind::Midi { status, data1: key, data2: velocity }) = tracks[index][position].1 else { continue };
+        let channel = status & 0x0F;
+        match status & 0xF0 {
+            0x90 if velocity > 0 => {
+                if sounding.len() >= max_notes.max(1) {
+                    if let Some((track, channel, key)) = sounding.pop_front() {
+                        note_offs[track].push((tick, Some(EventKind::Midi { status: 0x80 | channel, data1: key, data2: 0 })));
+                        stolen.push((channel, key));
+                    }
+                }
+                sounding.push_back((index, channel, key));
+            }
+            0x80 | 0x90 => {
+                if let Some(found) = sounding.iter().position(|&(_, c, k)| (c, k) == (channel, key)) {
+                    sounding.remove(found);
+                } else if let Some(found) = stolen.iter().position(|&note| note == (channel, key)) {
+                    stolen.swap_remove(found);
+                    tracks[index][position].1 = None;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for ((track, events), note_offs) in smf.tracks.iter_mut().zip(tracks).zip(note_offs) {
+        // Stable, so that the note-offs come before anything else at their tick, such as the note taking over
+        let mut events: Vec<(u64, Option<EventKind>)> = note_offs.into_iter().chain(events).collect();
+        events.sort_by_key(|&(tick, _)| tick);
+        let mut previous = 0;
+        *track = events.into_iter().filter_map(|(tick, kind)| {
+            let kind = kind?;
+            let delta = (tick - previous) as u32;
+            previous = tick;
+            Some(TrackEvent { delta, kind })
+        }).collect();
+    }
+}
+
+/// Exchanges every channel message of the channels `a` and `b`, numbered from 0
+fn swap_channels(smf: &mut Smf, a: u8, b: u8) {
+    for event in smf.tracks.iter_mut().flatten() {
+        if let EventKind::Midi { status, .. } = &mut event.kind {
+            let channel = *status & 0x0F;
+            if channel == a {
+                *status = (*status & 0xF0) | b;
+            } else if channel == b {
+                *status = (*status & 0xF0) | a;
+            }
+        }
+    }
+}
+
+/// Replaces the notes of the drum channel by `map`, as `(from, to)`; every note is mapped once, so `(35, 36)` and `(36, 35)` swap two drums
+fn remap_drums(smf: &mut Smf, map: &[(u8, u8)]) {
+    for event in smf.tracks.iter_mut().flatten() {
+        if let EventKind::Midi { status, data1, .. } = &mut event.kind {
+            // Note-off, note-on and polyphonic pressure all name the key they apply to
+            if matches!(*status & 0xF0, 0x80 | 0x90 | 0xA0) && (*status & 0x0F) + 1 == DRUM_CHANNEL {
+                if let Some(&(_, to)) = map.iter().rev().find(|&&(from, _)| from == *data1) {
+                    *data1 = to.min(127);
+                }
+            }
+        }
+    }
+}
+
+/// Holds every channel of `programs`, as `(channel, program)` with channels from 1 to 16, at its program
+/// 
+/// Program changes of the file on those channels are rewritten rather than dropped, so that the bank selects before them still take effect,
+/// and the program is also set at the very start for notes that come before any program change.
+fn override_programs(smf: &mut Smf, programs: &[(u8, u8)]) {
+    // The last override of a channel wins
+    let program_of = |channel: u8| programs.iter().rev().find(|&&(overridden, _)| overridden == channel + 1).map(|&(_, program)| program.min(127));
+    for event in smf.tracks.iter_mut().flatten() {
+        if let EventKind::Midi { status, data1, .. } = &mut event.kind {
+            if *status & 0xF0 == 0xC0 {
+                if let Some(program) = program_of(*status & 0x0F) {
+                    *data1 = program;
+                }
+            }
+        }
+    }
+    if let Some(track) = smf.tracks.first_mut() {
+        let events: Vec<TrackEvent> = (0..16).filter_map(|channel| {
+            let program = program_of(channel)?;
+            Some(TrackEvent { delta: 0, kind: EventKind::Midi { status: 0xC0 | channel, data1: program, data2: 0 } })
+        }).collect();
+        track.splice(0..0, events);
+    }
+}
+
+/// Sets the pitch bend range of every channel to `semitones` at the start of `smf`, replacing the ranges the file sets itself
+fn set_bend_range(smf: &mut Smf, semitones: f64) {
+    // Data entries only change the bend range while RPN 0 is selected on their channel
+    let mut selected = [(0x7F_u8, 0x7F_u8); 16];
+    smf.retain_events(|kind| match *kind {
+        EventKind::Midi { status, data1, data2 } if status & 0xF0 == 0xB0 => {
+            let (msb, lsb) = &mut selected[(status & 0x0F) as usize];
+            match data1 {
+                0x65 => *msb = data2,
+                0x64 => *lsb = data2,
+                // Selecting an NRPN deselects the RPN
+                0x62 | 0x63 => (*msb, *lsb) = (0x7F, 0x7F),
+                0x06 | 0x26 | 0x60 | 0x61 => return (*msb, *lsb) != (0, 0),
+                _ => {}
+            }
+            true
+        }
+        _ => true,
+    });
+    let coarse = semitones.trunc() as u8;
+    let cents = ((semitones.fract() * 100.0).round() as u8).min(99);
+    if let Some(track) = smf.tracks.first_mut() {
+        let mut events = Vec::new();
+        for channel in 0..16 {
+            // Selecting the null RPN afterwards keeps later data entries of the file from changing the range again
+            for (controller, value) in [(0x65, 0x00), (0x64, 0x00), (0x06, coarse), (0x26, cents), (0x65, 0x7F), (0x64, 0x7F)] {
+                events.push(TrackEvent { delta: 0, kind: EventKind::Midi { status: 0xB0 | channel, data1: controller, data2: value } });
+            }
+        }
+        track.splice(0..0, events);
+    }
+}
+
+/// Replaces the bank selects of every track with a single MSB that `rustysynth` understands right before each program change
+/// 
+/// Bank selects and the program changes they apply to are assumed to be in the same track, as they practically always are.
+fn select_xg_banks(smf: &mut Smf) {
+    for track in &mut smf.tracks {
+        let mut banks = [(0_u8, 0_u8); 16];
+        let mut carried = 0;
+        let events = std::mem::take(track);
+        for mut event in events {
+            event.delta += carried;
+            carried = 0;
+            if let EventKind::Midi { status, data1, data2 } = event.kind {
+                let channel = status & 0x0F;
+                let (msb, lsb) = &mut banks[channel as usize];
+                match (status & 0xF0, data1) {
+                    (0xB0, 0x00) => {
+                        *msb = data2;
+                        carried = event.delta;
+                        continue;
+                    }
+                    (0xB0, 0x20) => {
+                        *lsb = data2;
+                        carried = event.delta;
+                        continue;
+                    }
+                    (0xC0, _) => {
+                        // MSB 0 picks the variation in the LSB, and 127 the drum kits, which are only available on the drum channel
+                        let bank = match *msb {
+                            0 => *lsb,
+                            127 if channel + 1 == DRUM_CHANNEL => 0,
+                            msb => msb,
+                        };
+                        track.push(TrackEvent { delta: event.delta, kind: EventKind::Midi { status: 0xB0 | channel, data1: 0x00, data2: bank } });
+                        event.delta = 0;
+                    }
+                    _ => {}
+                }
+            }
+            track.push(event);
+        }
+    }
+}
+
+/// Which sounding voice makes room for a new one once [`RenderConfig::max_voices`] are playing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VoiceStealing {
+    /// The synthesizer ends the quietest voice, preferring ones that are already releasing, which is what `rustysynth` does by itself
+    #[default]
+    Quietest,
+    /// The oldest note is ended before the new one starts
+    /// 
+    /// This counts notes rather than voices, so a note that plays several soundfont regions at once still counts once;
+    /// the synthesizer steals the quietest voice on top of it when the regions exceed the limit.
+    Oldest,
+}
+
+impl FromStr for VoiceStealing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "quietest" => Ok(VoiceStealing::Quietest),
+            "oldest" => Ok(VoiceStealing::Oldest),
+            _ => Err(format!("unknown voice stealing policy `{}` (expected quietest or oldest)", s)),
+        }
+    }
+}
+
+impl fmt::Display for VoiceStealing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VoiceStealing::Quietest => "quietest",
+            VoiceStealing::Oldest => "oldest",
+        })
+    }
+}
+
+/// How bank select messages (CC0 for the MSB and CC32 for the LSB) pick the soundfont bank of a program change
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BankMode {
+    /// General MIDI has no banks, so bank selects are ignored and every program comes from bank 0 (128 on the drum channel)
+    Gm,
+    /// Roland GS: the MSB is the bank and the LSB is ignored, which is what `rustysynth` does by itself
+    #[default]
+    Gs,
+    /// Yamaha XG: an MSB of 0 selects the bank in the LSB, 127 a drum kit and anything else the bank in the MSB
+    /// 
+    /// Drum kits only play on the drum channel, where they come from bank 128 like in the other modes.
+    Xg,
+}
+
+impl FromStr for BankMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gm" => Ok(BankMode::Gm),
+            "gs" => Ok(BankMode::Gs),
+            "xg" => Ok(BankMode::Xg),
+            _ => Err(format!("unknown bank mode `{}` (expected gm, gs or xg)", s)),
+        }
+    }
+}
+
+impl fmt::Display for BankMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BankMode::Gm => "gm",
+            BankMode::Gs => "gs",
+            BankMode::Xg => "xg",
+        })
+    }
+}
+
+/// How note velocities are remapped before being scaled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VelocityCurve {
+    /// Velocities stay as they are
+    #[default]
+    Linear,
+    /// Soft notes get softer while loud ones stay loud, widening the dynamics
+    Exponential,
+    /// Soft notes get louder while loud ones stay loud, compressing the dynamics
+    Logarithmic,
+}
+
+impl VelocityCurve {
+    /// Remaps the note-on velocity `velocity` along the curve, scales it by `scale` and clamps the result to 1 to 127
+    pub fn map(self, velocity: u8, scale: f64) -> u8 {
+        let x = velocity as f64 / 127.0;
+        // Both curves run from 0 to 1 and are the inverse of each other
+        let y = match self {
+            VelocityCurve::Linear => x,
+            VelocityCurve::Exponential => (10_f64.powf(x) - 1.0) / 9.0,
+            VelocityCurve::Logarithmic => (1.0 + 9.0 * x).log10(),
+        };
+        (y * scale * 127.0).round().clamp(1.0, 127.0) as u8
+    }
+}
+
+impl FromStr for VelocityCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(VelocityCurve::Linear),
+            "exponential" | "exp" => Ok(VelocityCurve::Exponential),
+            "logarithmic" | "log" => Ok(VelocityCurve::Logarithmic),
+            _ => Err(format!("unknown velocity curve `{}` (expected linear, exponential or logarithmic)", s)),
+        }
+    }
+}
+
+impl fmt::Display for VelocityCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VelocityCurve::Linear => "linear",
+            VelocityCurve::Exponential => "exponential",
+            VelocityCurve::Logarithmic => "logarithmic",
+        })
+    }
+}
+
+fn transpose(smf: &mut Smf, semitones: i32) {
+    let shifted = |status: u8, key: u8| match status & 0xF0 {
+        // Note-off, note-on and polyphonic pressure all name the key they apply to
+        0x80 | 0x90 | 0xA0 if (status & 0x0F) + 1 != DRUM_CHANNEL => Some(key as i32 + semitones),
+        _ => None,
+    };
+    // Both ends of a note shift the same way, so a dropped note-on never leaves its note-off hanging
+    smf.retain_events(|kind| match *kind {
+        EventKind::Midi { status, data1, .. } => shifted(status, data1).filter(|key| !(0..=127).contains(key)).is_none(),
+        _ => true,
+    });
+    for event in smf.tracks.iter_mut().flatten() {
+        if let EventKind::Midi { status, data1, .. } = &mut event.kind {
+            if let Some(key) = shifted(*status, *data1) {
+                *data1 = key as u8;
+            }
+        }
+    }
+}
+
+/// Sets the default tempo explicitly at the start of `smf` if it does not set one there itself
+fn insert_initial_tempo(smf: &mut Smf) {
+    let has_initial_tempo = smf.timeline().iter().any(|(tick, event)| *tick == 0 && matches!(event.kind, EventKind::Meta { kind: META_TEMPO, .. }));
+    if !has_initial_tempo {
+        if let Some(track) = smf.tracks.first_mut() {
+            track.insert(0, TrackEvent { delta: 0, kind: EventKind::Meta { kind: META_TEMPO, data: tempo_bytes(DEFAULT_MICROSECONDS_PER_QUARTER) } });
+        }
+    }
+}
+
+fn scale_tempo(smf: &mut Smf, factor: f64) {
+    // A file without tempo events plays at the default tempo, which has to be scaled as well
+    insert_initial_tempo(smf);
+    for event in smf.tracks.iter_mut().flatten() {
+        if let EventKind::Meta { kind: META_TEMPO, data } = &mut event.kind {
+            if data.len() >= 3 {
+                let tempo = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                *data = tempo_bytes((tempo as f64 / factor).round() as u32);
+            }
+        }
+    }
+}
+
+/// The 3 bytes of a tempo meta event, clamped to the range they can hold
+fn tempo_bytes(microseconds_per_quarter: u32) -> Vec<u8> {
+    microseconds_per_quarter.clamp(1, 0xFF_FFFF).to_be_bytes()[1..].to_vec()
+}
+
+/// Controller number of the sustain pedal
+const SUSTAIN_CONTROLLER: u8 = 64;
+
+/// Cuts `smf` down to the time between `start` and `end` seconds
+/// 
+/// Everything but the notes before `start` is moved to the very beginning, so that the channels are set up as they would be by then,
+/// and the tracks end at `end`, which is where the length of the file is taken from during playback. Notes still sounding at `end`
+/// are released there, along with any sustain pedal held down, since their own note-offs are cut off.
+pub(crate) fn window(smf: &mut Smf, start: f64, end: Option<f64>) {
+    let tempo_map = smf.tempo_map();
+    let start_tick = tempo_map.tick_at(start).round() as u64;
+    let end_tick = end.map(|end| tempo_map.tick_at(end).round() as u64);
+    for track in &mut smf.tracks {
+        let mut tick = 0;
+        let mut previous = 0;
+        // The channel and key of every note sounding so far, which a single note-off releases however often it was struck,
+        // and the channels with the sustain pedal down
+        let mut sounding: Vec<(u8, u8)> = Vec::new();
+        let mut sustained = [false; 16];
+        let mut released = false;
+        let events = std::mem::take(track);
+        for mut event in events {
+            tick += event.delta as u64;
+            let is_end_of_track = matches!(event.kind, EventKind::Meta { kind: META_END_OF_TRACK, .. });
+            let is_note = matches!(event.kind, EventKind::Midi { status, .. } if matches!(status & 0xF0, 0x80 | 0x90 | 0xA0));
+            if tick < start_tick && is_note {
+                continue;
+            }
+            let mut event_tick = tick;
+            if let Some(end_tick) = end_tick {
+                if tick > end_tick && !is_end_of_track {
+                    continue;
+                }
+                event_tick = event_tick.min(end_tick);
+            }
+            let event_tick = event_tick.saturating_sub(start_tick);
+            if is_end_of_track && end_tick.is_some() && !released {
+                track.extend(releases(&sounding, &sustained, event_tick - previous));
+                previous = event_tick;
+                released = true;
+            }
+            if let EventKind::Midi { status, data1, data2 } = event.kind {
+                let channel = status & 0x0F;
+                match status & 0xF0 {
+                    0x90 if data2 > 0 => {
+                        if !sounding.contains(&(channel, data1)) {
+                            sounding.push((channel, data1));
+                        }
+                    }
+                    0x80 | 0x90 => sounding.retain(|&note| note != (channel, data1)),
+                    0xB0 if data1 == SUSTAIN_CONTROLLER => sustained[channel as usize] = data2 >= 64,
+                    _ => {}
+                }
+            }
+            event.delta = (event_tick - previous) as u32;
+            previous = event_tick;
+            track.push(event);
+        }
+        // A track without an end can still be cut
+        if let Some(end_tick) = end_tick.filter(|_| !released) {
+            let end_tick = end_tick.saturating_sub(start_tick).max(previous);
+            track.extend(releases(&sounding, &sustained, end_tick - previous));
+        }
+    }
+}
+
+/// Note-offs for the notes of `sounding` and sustain pedal releases for the channels of `sustained`, the first `delta` ticks after the event before
+fn releases(sounding: &[(u8, u8)], sustained: &[bool; 16], delta: u64) -> Vec<TrackEvent> {
+    let note_offs = sounding.iter().map(|&(channel, key)| EventKind::Midi { status: 0x80 | channel, data1: key, data2: 0 });
+    let pedals = (0..16).filter(|&channel| sustained[channel as usize]).map(|channel| EventKind::Midi { status: 0xB0 | channel, data1: SUSTAIN_CONTROLLER, data2: 0 });
+    note_offs.chain(pedals).enumerate().map(|(index, kind)| TrackEvent { delta: if index == 0 { delta as u32 } else { 0 }, kind }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{smf, midi, tempo, marker, end_of_track};
+
+    /// The events of the first track of `smf` at their absolute ticks, without the end of the track, and the tick it ends at
+    fn events(smf: &Smf) -> (Vec<(u64, EventKind)>, u64) {
+        let mut tick = 0;
+        let mut events = Vec::new();
+        for event in &smf.tracks[0] {
+            tick += event.delta as u64;
+            if !matches!(event.kind, EventKind::Meta { kind: META_END_OF_TRACK, .. }) {
+                events.push((tick, event.kind.clone()));
+            }
+        }
+        (events, tick)
+    }
+
+    fn at(tick: u64, event: TrackEvent) -> (u64, EventKind) {
+        (tick, event.kind)
+    }
+
+    /// A note in a second at 120 BPM
+    fn plain() -> Smf {
+        smf(vec![tempo(0, 500_000), midi(0, 0x90, 60, 100), midi(480, 0x80, 60, 0), end_of_track(480)])
+    }
+
+    /// A note before a loop from tick 480 to 1200 with a tempo change inside it, and a note held past its end
+    fn looped() -> Smf {
+        smf(vec![
+            tempo(0, 600_000),
+            midi(0, 0x90, 60, 100),
+            midi(240, 0x80, 60, 0),
+            marker(240, "loopStart"),
+            midi(0, 0x90, 64, 100),
+            tempo(480, 300_000),
+            marker(240, "loopEnd"),
+            midi(240, 0x80, 64, 0),
+            end_of_track(480),
+        ])
+    }
+
+    /// A note on the first channel, one on the drum channel and one on channel 2, each a beat long
+    fn band() -> Smf {
+        smf(vec![
+            tempo(0, 500_000),
+            midi(0, 0x90, 60, 100),
+            midi(0, 0x99, 36, 100),
+            midi(0, 0x91, 48, 80),
+            midi(480, 0x80, 60, 0),
+            midi(0, 0x89, 36, 0),
+            midi(0, 0x81, 48, 0),
+            end_of_track(0),
+        ])
+    }
+
+    #[test]
+    fn transform_midi_leaves_a_file_alone_by_default() {
+        let mut file = band();
+        assert!(!transform_midi(&mut file, &RenderConfig::default()));
+        assert_eq!(file.tracks, band().tracks);
+    }
+
+    #[test]
+    fn transform_midi_mutes_and_transposes() {
+        let mut file = band();
+        let config = RenderConfig { mute: vec![2], transpose: 12, ..Default::default() };
+        assert!(transform_midi(&mut file, &config));
+        assert_eq!(events(&file), (vec![
+            at(0, tempo(0, 500_000)),
+            at(0, midi(0, 0x90, 72, 100)),
+            // Drums keep their keys
+            at(0, midi(0, 0x99, 36, 100)),
+            at(480, midi(0, 0x80, 72, 0)),
+            at(480, midi(0, 0x89, 36, 0)),
+        ], 480));
+    }
+
+    #[test]
+    fn transform_midi_repeats_in_the_new_tempo() {
+        let mut file = band();
+        let config = RenderConfig { bpm: Some(60.0), repeat: 2.0, ..Default::default() };
+        assert!(transform_midi(&mut file, &config));
+        let (events, length) = events(&file);
+        assert_eq!(length, 960);
+        assert_eq!(file.tempo_map().seconds_at(length), 2.0);
+        assert_eq!(events.iter().filter(|(_, kind)| matches!(kind, EventKind::Midi { status: 0x90, .. })).count(), 2);
+    }
+
+    #[test]
+    fn repeat_once_keeps_a_plain_file() {
+        let mut file = plain();
+        repeat(&mut file, 1.0);
+        assert_eq!(events(&file), events(&plain()));
+    }
+
+    #[test]
+    fn repeat_twice_plays_a_plain_file_again() {
+        let mut file = plain();
+        repeat(&mut file, 2.0);
+        assert_eq!(events(&file), (vec![
+            at(0, tempo(0, 500_000)),
+            at(0, midi(0, 0x90, 60, 100)),
+            at(480, midi(0, 0x80, 60, 0)),
+            at(960, tempo(0, 500_000)),
+            at(960, midi(0, 0x90, 60, 100)),
+            at(1440, midi(0, 0x80, 60, 0)),
+        ], 1920));
+    }
+
+    #[test]
+    fn repeat_one_and_a_half_times_cuts_a_plain_file() {
+        let mut file = plain();
+        repeat(&mut file, 1.5);
+        assert_eq!(events(&file), (vec![
+            at(0, tempo(0, 500_000)),
+            at(0, midi(0, 0x90, 60, 100)),
+            at(480, midi(0, 0x80, 60, 0)),
+            at(960, tempo(0, 500_000)),
+            at(960, midi(0, 0x90, 60, 100)),
+            at(1440, midi(0, 0x80, 60, 0)),
+        ], 1440));
+    }
+
+    #[test]
+    fn repeat_once_ends_at_the_loop() {
+        let mut file = looped();
+        repeat(&mut file, 1.0);
+        assert_eq!(events(&file), (vec![
+            at(0, tempo(0, 600_000)),
+            at(0, midi(0, 0x90, 60, 100)),
+            at(240, midi(0, 0x80, 60, 0)),
+            // The tempo the loop starts in
+            at(480, tempo(0, 600_000)),
+            at(480, marker(0, "loopStart")),
+            at(480, midi(0, 0x90, 64, 100)),
+            at(960, tempo(0, 300_000)),
+            at(1200, marker(0, "loopEnd")),
+            // Moved from after the loop
+            at(1200, midi(0, 0x80, 64, 0)),
+        ], 1200));
+    }
+
+    #[test]
+    fn repeat_twice_repeats_only_the_loop() {
+        let mut file = looped();
+        repeat(&mut file, 2.0);
+        assert_eq!(events(&file), (vec![
+            at(0, tempo(0, 600_000)),
+            at(0, midi(0, 0x90, 60, 100)),
+            at(240, midi(0, 0x80, 60, 0)),
+            at(480, tempo(0, 600_000)),
+            at(480, marker(0, "loopStart")),
+            at(480, midi(0, 0x90, 64, 100)),
+            at(960, tempo(0, 300_000)),
+            at(1200, marker(0, "loopEnd")),
+            at(1200, tempo(0, 600_000)),
+            at(1200, marker(0, "loopStart")),
+            at(1200, midi(0, 0x90, 64, 100)),
+            at(1680, tempo(0, 300_000)),
+            at(1920, marker(0, "loopEnd")),
+            at(1920, midi(0, 0x80, 64, 0)),
+        ], 1920));
+    }
+
+    #[test]
+    fn repeat_one_and_a_half_times_cuts_the_loop_in_time() {
+        let mut file = looped();
+        repeat(&mut file, 1.5);
+        // The loop lasts 0.75 s, half of which is 300 ticks in the tempo it starts in
+        assert_eq!(events(&file), (vec![
+            at(0, tempo(0, 600_000)),
+            at(0, midi(0, 0x90, 60, 100)),
+            at(240, midi(0, 0x80, 60, 0)),
+            at(480, tempo(0, 600_000)),
+            at(480, marker(0, "loopStart")),
+            at(480, midi(0, 0x90, 64, 100)),
+            at(960, tempo(0, 300_000)),
+            at(1200, marker(0, "loopEnd")),
+            at(1200, tempo(0, 600_000)),
+            at(1200, marker(0, "loopStart")),
+            at(1200, midi(0, 0x90, 64, 100)),
+            at(1500, midi(0, 0x80, 64, 0)),
+        ], 1500));
+    }
+
+    #[test]
+    fn window_releases_notes_and_pedals_at_the_end() {
+        let mut file = smf(vec![
+            tempo(0, 500_000),
+            midi(0, 0xB0, 64, 127),
+            midi(0, 0x90, 60, 100),
+            midi(0, 0x91, 48, 80),
+            midi(240, 0x81, 48, 0),
+            midi(720, 0x80, 60, 0),
+            end_of_track(0),
+        ]);
+        window(&mut file, 0.0, Some(0.5));
+        assert_eq!(events(&file), (vec![
+            at(0, tempo(0, 500_000)),
+            at(0, midi(0, 0xB0, 64, 127)),
+            at(0, midi(0, 0x90, 60, 100)),
+            at(0, midi(0, 0x91, 48, 80)),
+            at(240, midi(0, 0x81, 48, 0)),
+            at(480, midi(0, 0x80, 60, 0)),
+            at(480, midi(0, 0xB0, 64, 0)),
+        ], 480));
+    }
+}